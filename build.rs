@@ -1,4 +1,28 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+const PROTOS: &[&str] = &[
+    "protos/rfid.proto",
+    "protos/health.proto",
+    "protos/reflection.proto",
+];
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("protos/rfid.proto")?;
+    tonic_build::configure().compile(PROTOS, &["protos"])?;
+
+    // served by the reflection service
+    let out = PathBuf::from(env::var("OUT_DIR")?).join("descriptors.bin");
+    let status = Command::new(prost_build::protoc())
+        .arg("--include_imports")
+        .arg("-I")
+        .arg("protos")
+        .arg("--descriptor_set_out")
+        .arg(&out)
+        .args(PROTOS)
+        .status()?;
+    if !status.success() {
+        return Err(format!("protoc failed writing {}", out.display()).into());
+    }
     Ok(())
 }
@@ -0,0 +1,25 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// the parser is built from the sources of the server, which is not a library
+#[allow(dead_code)]
+#[path = "../../src/reader/constants.rs"]
+mod constants;
+#[allow(dead_code)]
+#[path = "../../src/reader/parse.rs"]
+mod parse;
+
+use constants::UUID_REGEX;
+
+fuzz_target!(|data: &[u8]| {
+    let reply = String::from_utf8_lossy(data);
+    let _ = parse::uuid(&reply).map(|uid| parse::reverse_uuid(&uid));
+    let _ = parse::rssi(&reply);
+
+    // whatever the regex lets through has to come apart
+    if let Some(slot) = parse::first_match(&reply, &[UUID_REGEX]).unwrap() {
+        let uid = parse::uuid(slot).unwrap();
+        parse::reverse_uuid(&uid).unwrap();
+        parse::rssi(slot).unwrap();
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// the parser is built from the sources of the server, which is not a library
+#[allow(dead_code)]
+#[path = "../../src/reader/constants.rs"]
+mod constants;
+#[allow(dead_code)]
+#[path = "../../src/reader/parse.rs"]
+mod parse;
+
+use constants::SINGLE_BLK_REGEX;
+
+fuzz_target!(|data: &[u8]| {
+    let reply = String::from_utf8_lossy(data);
+    let _ = parse::single_block(&reply);
+
+    // whatever the regex lets through has to come apart
+    if let Some(found) = parse::first_match(&reply, &[SINGLE_BLK_REGEX]).unwrap() {
+        parse::single_block(found).unwrap();
+    }
+});
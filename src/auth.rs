@@ -0,0 +1,419 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tonic::codegen::{http, Context, Poll, Service};
+use tonic::metadata::MetadataValue;
+use tonic::transport::NamedService;
+use tonic::{Interceptor, Request, Status};
+
+pub mod err;
+
+use crate::logging;
+use err::AuthError;
+
+// set from the request path for the interceptor, which only sees the metadata
+pub const METHOD_METADATA: &str = "rfid-method";
+pub const AUTHORIZATION_METADATA: &str = "authorization";
+// who the interceptor found the caller to be, for the event log
+pub const IDENTITY_METADATA: &str = "rfid-identity";
+
+const BEARER: &str = "Bearer ";
+const ANY_METHOD: &str = "*";
+
+/* hex sha-256 of a der encoded certificate, as listed in the policy file */
+pub fn fingerprint(der: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(der))
+}
+
+fn to_fingerprint(listed: &str) -> String {
+    listed.replace(':', "").to_lowercase()
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PolicyFile {
+    // role to the methods it may call
+    roles: HashMap<String, Vec<String>>,
+    // api token to role
+    tokens: HashMap<String, String>,
+    // client certificate fingerprint to role
+    certificates: HashMap<String, String>,
+}
+
+/* who may call which methods. callers are known by an api token or, over mutual tls,
+by their certificate */
+#[derive(Debug, PartialEq)]
+pub struct Policy {
+    roles: HashMap<String, HashSet<String>>,
+    tokens: HashMap<String, String>,
+    certificates: HashMap<String, String>,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Policy, AuthError> {
+        let content = fs::read_to_string(path).map_err(|e| AuthError::Io(path.to_path_buf(), e))?;
+        Policy::from_toml(&content)
+    }
+
+    pub fn from_toml(content: &str) -> Result<Policy, AuthError> {
+        let file: PolicyFile = toml::from_str(content)?;
+        let certificates: HashMap<String, String> = file
+            .certificates
+            .into_iter()
+            .map(|(fp, role)| (to_fingerprint(&fp), role))
+            .collect();
+
+        // the token itself is not worth printing
+        let identities = file
+            .tokens
+            .values()
+            .map(|role| (String::from("a token"), role))
+            .chain(
+                certificates
+                    .iter()
+                    .map(|(fp, role)| (format!("certificate {}", fp), role)),
+            );
+        for (identity, role) in identities {
+            if !file.roles.contains_key(role) {
+                return Err(AuthError::UnknownRole(identity, role.clone()));
+            }
+        }
+
+        Ok(Policy {
+            roles: file
+                .roles
+                .into_iter()
+                .map(|(role, methods)| (role, methods.into_iter().collect()))
+                .collect(),
+            tokens: file.tokens,
+            certificates,
+        })
+    }
+
+    /* a token, when sent, has to be known. without one the client certificate is used.
+    the caller is named by its role for a token, by its fingerprint for a certificate */
+    fn caller<T>(&self, request: &Request<T>) -> Result<(String, &str), Status> {
+        let unknown = || Status::unauthenticated("Missing or unknown credentials");
+
+        if let Some(value) = request.metadata().get(AUTHORIZATION_METADATA) {
+            let token = value
+                .to_str()
+                .ok()
+                .filter(|v| v.starts_with(BEARER))
+                .map(|v| &v[BEARER.len()..])
+                .ok_or_else(unknown)?;
+            let role = self.tokens.get(token).ok_or_else(unknown)?;
+            return Ok((format!("token:{}", role), role));
+        }
+
+        let fp = certificate(request).ok_or_else(unknown)?;
+        let role = self.certificates.get(&fp).ok_or_else(unknown)?;
+        Ok((format!("certificate:{}", fp), role))
+    }
+
+    /* the caller, when it may make the call */
+    pub fn check<T>(&self, request: &Request<T>) -> Result<String, Status> {
+        let (caller, role) = self.caller(request)?;
+        let method = request
+            .metadata()
+            .get(METHOD_METADATA)
+            .and_then(|m| m.to_str().ok())
+            .unwrap_or_default();
+
+        let allowed = &self.roles[role];
+        if allowed.contains(ANY_METHOD) || allowed.contains(method) {
+            Ok(caller)
+        } else {
+            Err(Status::permission_denied(format!(
+                "Role {} may not call {}",
+                role, method
+            )))
+        }
+    }
+}
+
+/* the fingerprint of the client certificate, over mutual tls */
+fn certificate<T>(request: &Request<T>) -> Option<String> {
+    let certs = request.peer_certs()?;
+    // the first certificate is the client's own, the rest its chain
+    let der = certs.first()?;
+    Some(fingerprint(der.get_ref()))
+}
+
+/* checks every call against the policy, lets everything through without one. the first
+to see the connection, so the peer is logged from here. the caller it found is handed on
+to the service, a client sending the metadata itself gets it dropped */
+pub fn interceptor(policy: Option<Policy>) -> Interceptor {
+    let policy = policy.map(Arc::new);
+    Interceptor::new(move |mut request: Request<()>| {
+        logging::record_peer(&request);
+        request.metadata_mut().remove(IDENTITY_METADATA);
+        let caller = match policy {
+            Some(ref policy) => Some(policy.check(&request)?),
+            None => certificate(&request).map(|fp| format!("certificate:{}", fp)),
+        };
+        if let Some(value) = caller.and_then(|c| MetadataValue::from_str(&c).ok()) {
+            request.metadata_mut().insert(IDENTITY_METADATA, value);
+        }
+        Ok(request)
+    })
+}
+
+/* hands the name of the method called to the interceptor of the service it wraps. a
+client sending the metadata itself gets it overwritten */
+#[derive(Debug, Clone)]
+pub struct Authorize<S> {
+    inner: S,
+}
+
+impl<S> Authorize<S> {
+    pub fn new(inner: S) -> Authorize<S> {
+        Authorize { inner }
+    }
+}
+
+impl<S: NamedService> NamedService for Authorize<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B> Service<http::Request<B>> for Authorize<S>
+where
+    S: Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        let method = req.uri().path().rsplit('/').next().unwrap_or_default();
+        match http::HeaderValue::from_str(method) {
+            Ok(value) => {
+                req.headers_mut().insert(METHOD_METADATA, value);
+            }
+            // not a method of the service, nobody may call it
+            Err(_) => {
+                req.headers_mut().remove(METHOD_METADATA);
+            }
+        }
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::{EventLog, EventOptions};
+    use crate::include::read_info_client::ReadInfoClient;
+    use crate::include::read_info_server::ReadInfoServer;
+    use crate::include::{Empty, QueryEventsRequest, SingleBlockRequest};
+    use crate::reader::MockReaderTraits;
+    use crate::rfid::Rfid;
+    use crate::scaffold::scaffold::*;
+    use crate::tls::Tls;
+    use futures_util::FutureExt;
+    use std::io::Cursor;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+    use tokio_rustls::rustls::internal::pemfile;
+    use tonic::transport::Server;
+    use tonic::Code;
+
+    const POLICY: &str = r#"
+        [roles]
+        reader = ["ReadUuid", "ReadSingleBlock"]
+        admin = ["*"]
+
+        [tokens]
+        "reader-token" = "reader"
+        "admin-token" = "admin"
+    "#;
+
+    fn call(token: Option<&str>, method: &str) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(token) = token {
+            let value = MetadataValue::from_str(&format!("Bearer {}", token)).unwrap();
+            request.metadata_mut().insert(AUTHORIZATION_METADATA, value);
+        }
+        let method = MetadataValue::from_str(method).unwrap();
+        request.metadata_mut().insert(METHOD_METADATA, method);
+        request
+    }
+
+    fn code<T>(res: Result<T, Status>) -> Option<Code> {
+        res.err().map(|e| e.code())
+    }
+
+    #[test]
+    fn tokens_and_roles() {
+        let policy = Policy::from_toml(POLICY).unwrap();
+
+        assert_eq!(
+            policy
+                .check(&call(Some("reader-token"), "ReadUuid"))
+                .unwrap(),
+            "token:reader"
+        );
+        assert_eq!(
+            code(policy.check(&call(Some("reader-token"), "AcquireLease"))),
+            Some(Code::PermissionDenied)
+        );
+        assert_eq!(
+            code(policy.check(&call(Some("admin-token"), "AcquireLease"))),
+            None
+        );
+        assert_eq!(
+            code(policy.check(&call(Some("guessed"), "ReadUuid"))),
+            Some(Code::Unauthenticated)
+        );
+        assert_eq!(
+            code(policy.check(&call(None, "ReadUuid"))),
+            Some(Code::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn malformed_authorization() {
+        let policy = Policy::from_toml(POLICY).unwrap();
+        let mut request = call(None, "ReadUuid");
+        request.metadata_mut().insert(
+            AUTHORIZATION_METADATA,
+            MetadataValue::from_static("reader-token"),
+        );
+        assert_eq!(code(policy.check(&request)), Some(Code::Unauthenticated));
+    }
+
+    #[test]
+    fn unknown_role() {
+        let res = Policy::from_toml("[certificates]\n\"AB:CD\" = \"provisioning\"");
+        match res {
+            Err(AuthError::UnknownRole(identity, role)) => {
+                assert_eq!(identity, "certificate abcd");
+                assert_eq!(role, "provisioning");
+            }
+            _ => panic!("{}", "Should have been an unknown role"),
+        }
+        assert!(Policy::from_toml("[users]\nbob = \"admin\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn certificates_over_mutual_tls() {
+        let dir = temp_dir();
+        let pki = pki();
+        let der = pemfile::certs(&mut Cursor::new(&pki.client.0)).unwrap();
+        let policy = Policy::from_toml(&format!(
+            "{}\n[certificates]\n\"{}\" = \"reader\"",
+            POLICY,
+            fingerprint(&der[0].0)
+        ))
+        .unwrap();
+
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+        let service = ReadInfoServer::with_interceptor(
+            Rfid::new(Box::new(reader)),
+            interceptor(Some(policy)),
+        );
+        let tls = Tls::load(&write_tls(&dir, &pki, true)).unwrap();
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (stop, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            Server::builder()
+                .add_service(Authorize::new(service))
+                .serve_with_incoming_shutdown(tls.incoming(listener), rx.map(drop))
+                .await
+                .unwrap();
+        });
+
+        let mut client = tls_client(port, &pki.ca, Some(&pki.client)).await.unwrap();
+        let read = client.read_uuid(Request::new(Empty {})).await;
+        let leased = client.acquire_lease(Request::new(Default::default())).await;
+        // a token takes the place of the certificate
+        let mut request = Request::new(SingleBlockRequest { block_index: 0 });
+        request.metadata_mut().insert(
+            AUTHORIZATION_METADATA,
+            MetadataValue::from_static("Bearer guessed"),
+        );
+        let guessed = client.read_single_block(request).await;
+        // the method name cannot be forged
+        let mut request = Request::new(Default::default());
+        request
+            .metadata_mut()
+            .insert(METHOD_METADATA, MetadataValue::from_static("ReadUuid"));
+        let forged = client.acquire_lease(request).await;
+        stop.send(()).unwrap();
+        server.await.unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(read.unwrap().into_inner().info, "CAFEDEADBEEFB0B0");
+        assert_eq!(leased.unwrap_err().code(), Code::PermissionDenied);
+        assert_eq!(guessed.unwrap_err().code(), Code::Unauthenticated);
+        assert_eq!(forged.unwrap_err().code(), Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn events_name_the_caller() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+        let events = EventLog::open(&EventOptions {
+            path: PathBuf::from(":memory:"),
+            retention: None,
+            max_events: None,
+        })
+        .unwrap();
+        let service = ReadInfoServer::with_interceptor(
+            Rfid::with_events(Box::new(reader), events),
+            interceptor(Some(Policy::from_toml(POLICY).unwrap())),
+        );
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (stop, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            Server::builder()
+                .add_service(Authorize::new(service))
+                .serve_with_incoming_shutdown(listener, rx.map(drop))
+                .await
+                .unwrap();
+        });
+
+        let mut client = ReadInfoClient::connect(format!("http://127.0.0.1:{}", port))
+            .await
+            .unwrap();
+        let token = MetadataValue::from_static("Bearer admin-token");
+        let mut request = Request::new(Empty {});
+        request
+            .metadata_mut()
+            .insert(AUTHORIZATION_METADATA, token.clone());
+        // the caller cannot be forged either
+        request.metadata_mut().insert(
+            IDENTITY_METADATA,
+            MetadataValue::from_static("token:reader"),
+        );
+        client.read_uuid(request).await.unwrap();
+        let mut request = Request::new(QueryEventsRequest::default());
+        request.metadata_mut().insert(AUTHORIZATION_METADATA, token);
+        let logged = client.query_events(request).await.unwrap().into_inner();
+        stop.send(()).unwrap();
+        server.await.unwrap();
+
+        assert_eq!(logged.events.len(), 1);
+        assert_eq!(logged.events[0].client, "token:admin");
+    }
+}
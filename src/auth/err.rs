@@ -0,0 +1,33 @@
+use std::fmt;
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum AuthError {
+    Io(PathBuf, std::io::Error),
+    Parse(toml::de::Error),
+    // identity, role
+    UnknownRole(String, String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuthError::Io(ref path, ref e) => {
+                write!(f, "Unable to read policy file {}: {}", path.display(), e)
+            }
+            AuthError::Parse(ref e) => write!(f, "Invalid policy file: {}", e),
+            AuthError::UnknownRole(ref identity, ref role) => {
+                write!(f, "Unknown role {} for {}", role, identity)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<toml::de::Error> for AuthError {
+    fn from(err: toml::de::Error) -> AuthError {
+        AuthError::Parse(err)
+    }
+}
@@ -0,0 +1,1547 @@
+use serde::Deserialize;
+use serialport::prelude::*;
+use serialport::SerialPortSettings;
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use structopt::StructOpt;
+
+pub mod err;
+
+use crate::dedup::{DedupMode, DedupOptions, DEFAULT_WINDOW_MS};
+use crate::events::{EventOptions, DEFAULT_EVENTS_PATH, DEFAULT_RETENTION_DAYS};
+use crate::flow::{self, FlowOptions};
+use crate::health::{HealthOptions, DEFAULT_PROBE_INTERVAL_MS};
+use crate::inventory::{InventoryOptions, DEFAULT_BUFFER, DEFAULT_POLL_INTERVAL_MS};
+use crate::listen::DEFAULT_LISTEN;
+use crate::logging::{self, LogFormat, LoggingOptions, DEFAULT_LEVEL};
+use crate::metrics::DEFAULT_READER_ID;
+use crate::presence::{PresenceOptions, DEFAULT_DEPARTURE_TIMEOUT_MS};
+use crate::queue::{QueueOptions, QueueOrder, DEFAULT_MAX_DEPTH};
+use crate::reader::rf::{ModulationDepth, OutputPower, ReaderProfile};
+use crate::serial::frame::{FrameOptions, DEFAULT_IDLE_TIMEOUT_MS, DEFAULT_MAX_FRAME_LEN};
+use crate::serial::options::{
+    RetryPolicy, SerialOptions, DEFAULT_BAUD_RATE, DEFAULT_TIMEOUT_MS, DEFAULT_TRIES,
+};
+use crate::serial::select::PortSelector;
+use crate::serial::sim::tag::{VirtualTag, DEFAULT_BLOCKS, DEFAULT_BLOCK_SIZE, DEFAULT_RSSI};
+use crate::tls::{TlsOptions, DEFAULT_RELOAD_INTERVAL_MS};
+use err::ConfigError;
+
+/* command line flags, each of them can also be given through the environment */
+#[derive(Debug, Default, StructOpt)]
+#[structopt(name = "rfid-trf7970-grpc")]
+pub struct Opt {
+    /// TOML config file
+    #[structopt(short, long, env = "RFID_CONFIG", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Serial port to open, skips port discovery
+    #[structopt(long, env = "RFID_PORT")]
+    pub port: Option<String>,
+
+    /// Glob matched against the names of the available ports
+    #[structopt(long, env = "RFID_PORT_GLOB")]
+    pub port_glob: Option<String>,
+
+    /// USB vendor id of the port, in hex
+    #[structopt(long, env = "RFID_USB_VID", parse(try_from_str = parse_hex))]
+    pub usb_vid: Option<u16>,
+
+    /// USB product id of the port, in hex
+    #[structopt(long, env = "RFID_USB_PID", parse(try_from_str = parse_hex))]
+    pub usb_pid: Option<u16>,
+
+    /// USB serial number of the port
+    #[structopt(long, env = "RFID_USB_SERIAL")]
+    pub usb_serial: Option<String>,
+
+    /// Baud rate of the serial port
+    #[structopt(long, env = "RFID_BAUD_RATE")]
+    pub baud_rate: Option<u32>,
+
+    /// Default time to wait for a reply, in milliseconds
+    #[structopt(long, env = "RFID_SERIAL_TIMEOUT_MS")]
+    pub serial_timeout_ms: Option<u64>,
+
+    /// Number of times a command is sent before giving up
+    #[structopt(long, env = "RFID_SERIAL_TRIES")]
+    pub serial_tries: Option<u32>,
+
+    /// Start serving even if the reader cannot be reached, and keep trying to connect
+    #[structopt(long)]
+    pub allow_no_reader: bool,
+
+    /// Address the grpc server listens on
+    #[structopt(long, env = "RFID_LISTEN")]
+    pub listen: Option<SocketAddr>,
+
+    /// Also listen on this unix domain socket, for local clients
+    #[structopt(long, env = "RFID_UNIX_SOCKET", parse(from_os_str))]
+    pub unix_socket: Option<PathBuf>,
+
+    /// RF output power written on connect, full or half
+    #[structopt(long, env = "RFID_OUTPUT_POWER")]
+    pub output_power: Option<String>,
+
+    /// Modulation depth written on connect, e.g. ask10 or ook100
+    #[structopt(long, env = "RFID_MODULATION")]
+    pub modulation: Option<String>,
+
+    /// Serve prometheus metrics over http on this address
+    #[structopt(long, env = "RFID_METRICS_LISTEN")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Reader label of the metrics
+    #[structopt(long, env = "RFID_READER_ID")]
+    pub reader_id: Option<String>,
+
+    /// Log filter, e.g. debug or info,rfid_trf7970_grpc::serial=debug
+    #[structopt(long, env = "RFID_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Log as JSON lines
+    #[structopt(long)]
+    pub log_json: bool,
+
+    /// Keep tag uids and data out of the logs
+    #[structopt(long)]
+    pub redact_tags: bool,
+
+    /// Append every serial exchange to this capture file
+    #[structopt(long, env = "RFID_SERIAL_CAPTURE", parse(from_os_str))]
+    pub serial_capture: Option<PathBuf>,
+
+    /// Play a capture file back instead of opening a serial port
+    #[structopt(long, env = "RFID_SERIAL_REPLAY", parse(from_os_str))]
+    pub serial_replay: Option<PathBuf>,
+
+    /// Talk to a simulated reader with the tags of the config file instead of a serial port
+    #[structopt(long)]
+    pub simulate: bool,
+}
+
+fn parse_hex(s: &str) -> Result<u16, std::num::ParseIntError> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub serial: SerialConfig,
+    pub reader: ReaderConfig,
+    pub queue: QueueConfig,
+    pub inventory: InventoryConfig,
+    pub presence: PresenceConfig,
+    pub stream: StreamConfig,
+    pub dedup: DedupConfig,
+    pub events: EventsConfig,
+    pub tls: TlsConfig,
+    pub auth: AuthConfig,
+    pub server: ServerConfig,
+    pub health: HealthConfig,
+    pub metrics: MetricsConfig,
+    pub logging: LoggingConfig,
+    pub simulator: SimulatorConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReaderConfig {
+    // serve as unavailable instead of exiting when the reader is missing at startup
+    pub allow_missing: bool,
+    pub connect_interval_ms: u64,
+    pub output_power: Option<String>,
+    pub modulation: Option<String>,
+}
+
+impl Default for ReaderConfig {
+    fn default() -> ReaderConfig {
+        ReaderConfig {
+            allow_missing: false,
+            connect_interval_ms: 5000,
+            output_power: None,
+            modulation: None,
+        }
+    }
+}
+
+impl ReaderConfig {
+    pub fn profile(&self) -> Result<ReaderProfile, ConfigError> {
+        let output_power =
+            match self.output_power {
+                Some(ref name) => Some(OutputPower::from_name(name).ok_or_else(|| {
+                    ConfigError::InvalidValue("reader.output_power", name.clone())
+                })?),
+                None => None,
+            };
+        let modulation = match self.modulation {
+            Some(ref name) => Some(
+                ModulationDepth::from_name(name)
+                    .ok_or_else(|| ConfigError::InvalidValue("reader.modulation", name.clone()))?,
+            ),
+            None => None,
+        };
+        Ok(ReaderProfile {
+            output_power,
+            modulation,
+        })
+    }
+}
+
+/* where the grpc server listens, the unix socket is served in addition and without tls */
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    pub unix_socket: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            listen: DEFAULT_LISTEN.parse().unwrap(),
+            unix_socket: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormatConfig {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub format: LogFormatConfig,
+    pub redact_tags: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        LoggingConfig {
+            level: String::from(DEFAULT_LEVEL),
+            format: LogFormatConfig::Text,
+            redact_tags: false,
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn options(&self) -> Result<LoggingOptions, ConfigError> {
+        if !logging::is_valid_level(&self.level) {
+            return Err(ConfigError::InvalidValue(
+                "logging.level",
+                self.level.clone(),
+            ));
+        }
+        let format = match self.format {
+            LogFormatConfig::Text => LogFormat::Text,
+            LogFormatConfig::Json => LogFormat::Json,
+        };
+        Ok(LoggingOptions {
+            level: self.level.clone(),
+            format,
+            redact_tags: self.redact_tags,
+        })
+    }
+}
+
+/* the metrics endpoint is off unless an address is set */
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub listen: Option<SocketAddr>,
+    // label of every metric, to tell readers apart on one dashboard
+    pub reader_id: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> MetricsConfig {
+        MetricsConfig {
+            listen: None,
+            reader_id: String::from(DEFAULT_READER_ID),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    pub probe_interval_ms: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> HealthConfig {
+        HealthConfig {
+            probe_interval_ms: DEFAULT_PROBE_INTERVAL_MS,
+        }
+    }
+}
+
+impl HealthConfig {
+    pub fn options(&self) -> Result<HealthOptions, ConfigError> {
+        if self.probe_interval_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "health.probe_interval_ms",
+                String::from("0"),
+            ));
+        }
+        Ok(HealthOptions {
+            probe_interval: Duration::from_millis(self.probe_interval_ms),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueOrderConfig {
+    Fifo,
+    Priority,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    pub max_depth: usize,
+    pub order: QueueOrderConfig,
+}
+
+impl Default for QueueConfig {
+    fn default() -> QueueConfig {
+        QueueConfig {
+            max_depth: DEFAULT_MAX_DEPTH,
+            order: QueueOrderConfig::Fifo,
+        }
+    }
+}
+
+impl QueueConfig {
+    pub fn options(&self) -> Result<QueueOptions, ConfigError> {
+        if self.max_depth == 0 {
+            return Err(ConfigError::InvalidValue(
+                "queue.max_depth",
+                String::from("0"),
+            ));
+        }
+        let order = match self.order {
+            QueueOrderConfig::Fifo => QueueOrder::Fifo,
+            QueueOrderConfig::Priority => QueueOrder::Priority,
+        };
+        Ok(QueueOptions {
+            max_depth: self.max_depth,
+            order,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InventoryConfig {
+    pub poll_interval_ms: u64,
+    pub buffer: usize,
+}
+
+impl Default for InventoryConfig {
+    fn default() -> InventoryConfig {
+        InventoryConfig {
+            poll_interval_ms: DEFAULT_POLL_INTERVAL_MS,
+            buffer: DEFAULT_BUFFER,
+        }
+    }
+}
+
+impl InventoryConfig {
+    pub fn options(&self) -> Result<InventoryOptions, ConfigError> {
+        if self.poll_interval_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "inventory.poll_interval_ms",
+                String::from("0"),
+            ));
+        }
+        if self.buffer == 0 {
+            return Err(ConfigError::InvalidValue(
+                "inventory.buffer",
+                String::from("0"),
+            ));
+        }
+        Ok(InventoryOptions {
+            poll_interval: Duration::from_millis(self.poll_interval_ms),
+            buffer: self.buffer,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PresenceConfig {
+    pub departure_timeout_ms: u64,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> PresenceConfig {
+        PresenceConfig {
+            departure_timeout_ms: DEFAULT_DEPARTURE_TIMEOUT_MS,
+        }
+    }
+}
+
+impl PresenceConfig {
+    pub fn options(&self, inventory: &InventoryOptions) -> Result<PresenceOptions, ConfigError> {
+        /* shorter than a poll, every tag would depart between two reads */
+        if Duration::from_millis(self.departure_timeout_ms) <= inventory.poll_interval {
+            return Err(ConfigError::InvalidValue(
+                "presence.departure_timeout_ms",
+                self.departure_timeout_ms.to_string(),
+            ));
+        }
+        Ok(PresenceOptions {
+            departure_timeout: Duration::from_millis(self.departure_timeout_ms),
+        })
+    }
+}
+
+/* flow control of the continuous read streams */
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamConfig {
+    pub idle_timeout_ms: u64,
+    pub max_credits: u32,
+}
+
+impl Default for StreamConfig {
+    fn default() -> StreamConfig {
+        StreamConfig {
+            idle_timeout_ms: flow::DEFAULT_IDLE_TIMEOUT_MS,
+            max_credits: flow::DEFAULT_MAX_CREDITS,
+        }
+    }
+}
+
+impl StreamConfig {
+    pub fn options(&self) -> Result<FlowOptions, ConfigError> {
+        if self.idle_timeout_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "stream.idle_timeout_ms",
+                String::from("0"),
+            ));
+        }
+        if self.max_credits == 0 {
+            return Err(ConfigError::InvalidValue(
+                "stream.max_credits",
+                String::from("0"),
+            ));
+        }
+        Ok(FlowOptions {
+            idle_timeout: Duration::from_millis(self.idle_timeout_ms),
+            max_credits: self.max_credits,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupModeConfig {
+    Off,
+    Window,
+    OnChange,
+}
+
+/* how streams suppress repeated reads unless they ask for something else */
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DedupConfig {
+    pub mode: DedupModeConfig,
+    pub window_ms: u64,
+}
+
+impl Default for DedupConfig {
+    fn default() -> DedupConfig {
+        DedupConfig {
+            mode: DedupModeConfig::Off,
+            window_ms: DEFAULT_WINDOW_MS,
+        }
+    }
+}
+
+impl DedupConfig {
+    pub fn options(&self) -> Result<DedupOptions, ConfigError> {
+        if self.window_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "dedup.window_ms",
+                String::from("0"),
+            ));
+        }
+        let mode = match self.mode {
+            DedupModeConfig::Off => DedupMode::Off,
+            DedupModeConfig::Window => DedupMode::Window,
+            DedupModeConfig::OnChange => DedupMode::OnChange,
+        };
+        Ok(DedupOptions {
+            mode,
+            window: Duration::from_millis(self.window_ms),
+        })
+    }
+}
+
+/* the log of every tag seen, kept in an sqlite database */
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    // zero keeps events forever
+    pub retention_days: u64,
+    // zero keeps any number of events
+    pub max_events: u64,
+}
+
+impl Default for EventsConfig {
+    fn default() -> EventsConfig {
+        EventsConfig {
+            enabled: false,
+            path: PathBuf::from(DEFAULT_EVENTS_PATH),
+            retention_days: DEFAULT_RETENTION_DAYS,
+            max_events: 0,
+        }
+    }
+}
+
+impl EventsConfig {
+    /* none when the log is disabled */
+    pub fn options(&self) -> Option<EventOptions> {
+        if !self.enabled {
+            return None;
+        }
+        Some(EventOptions {
+            path: self.path.clone(),
+            retention: Some(self.retention_days)
+                .filter(|days| *days > 0)
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            max_events: Some(self.max_events).filter(|max| *max > 0),
+        })
+    }
+}
+
+/* a virtual tag of the simulator, data is hex from block 0 on and the rest stays blank */
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimTagConfig {
+    pub uid: String,
+    #[serde(default = "default_sim_blocks")]
+    pub blocks: usize,
+    #[serde(default = "default_sim_block_size")]
+    pub block_size: usize,
+    #[serde(default)]
+    pub data: String,
+    #[serde(default)]
+    pub locked: Vec<usize>,
+    #[serde(default)]
+    pub afi: u8,
+    #[serde(default)]
+    pub dsfid: u8,
+    #[serde(default = "default_sim_rssi")]
+    pub rssi: u8,
+}
+
+fn default_sim_blocks() -> usize {
+    DEFAULT_BLOCKS
+}
+
+fn default_sim_block_size() -> usize {
+    DEFAULT_BLOCK_SIZE
+}
+
+fn default_sim_rssi() -> u8 {
+    DEFAULT_RSSI
+}
+
+impl SimTagConfig {
+    pub fn tag(&self) -> Result<VirtualTag, ConfigError> {
+        let uid = match u64::from_str_radix(&self.uid, 16) {
+            Ok(uid) if self.uid.len() == 16 => uid,
+            _ => {
+                return Err(ConfigError::InvalidValue(
+                    "simulator.tags.uid",
+                    self.uid.clone(),
+                ))
+            }
+        };
+        // the block number and size both go over the air in a single byte
+        if self.blocks == 0 || self.blocks > 256 {
+            return Err(ConfigError::InvalidValue(
+                "simulator.tags.blocks",
+                self.blocks.to_string(),
+            ));
+        }
+        if self.block_size == 0 || self.block_size > 32 {
+            return Err(ConfigError::InvalidValue(
+                "simulator.tags.block_size",
+                self.block_size.to_string(),
+            ));
+        }
+
+        let mut memory = vec![0; self.blocks * self.block_size];
+        let data = (0..self.data.len())
+            .step_by(2)
+            .map(|i| {
+                self.data
+                    .get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>();
+        match data {
+            Some(ref data) if data.len() <= memory.len() => {
+                memory[..data.len()].copy_from_slice(data)
+            }
+            _ => {
+                return Err(ConfigError::InvalidValue(
+                    "simulator.tags.data",
+                    self.data.clone(),
+                ))
+            }
+        }
+        let mut locked = vec![false; self.blocks];
+        for idx in &self.locked {
+            match locked.get_mut(*idx) {
+                Some(l) => *l = true,
+                None => {
+                    return Err(ConfigError::InvalidValue(
+                        "simulator.tags.locked",
+                        idx.to_string(),
+                    ))
+                }
+            }
+        }
+
+        Ok(VirtualTag {
+            uid,
+            block_size: self.block_size,
+            memory,
+            locked,
+            afi: self.afi,
+            dsfid: self.dsfid,
+            rssi: self.rssi,
+            ..VirtualTag::new(uid)
+        })
+    }
+}
+
+/* a simulated reader in place of the serial port, for trying the service without hardware */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulatorConfig {
+    pub enabled: bool,
+    pub tags: Vec<SimTagConfig>,
+}
+
+impl SimulatorConfig {
+    /* none when the simulator is disabled */
+    pub fn tags(&self) -> Result<Option<Vec<VirtualTag>>, ConfigError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let tags = self
+            .tags
+            .iter()
+            .map(SimTagConfig::tag)
+            .collect::<Result<Vec<VirtualTag>, ConfigError>>()?;
+        Ok(Some(tags))
+    }
+}
+
+/* serves over tls when a certificate is set, clients need a certificate signed by
+client_ca when that is set too */
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub client_ca: Option<PathBuf>,
+    pub reload_interval_ms: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> TlsConfig {
+        TlsConfig {
+            cert: None,
+            key: None,
+            client_ca: None,
+            reload_interval_ms: DEFAULT_RELOAD_INTERVAL_MS,
+        }
+    }
+}
+
+impl TlsConfig {
+    /* none when serving plaintext */
+    pub fn options(&self) -> Result<Option<TlsOptions>, ConfigError> {
+        if self.reload_interval_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "tls.reload_interval_ms",
+                String::from("0"),
+            ));
+        }
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => Ok(Some(TlsOptions {
+                cert: cert.clone(),
+                key: key.clone(),
+                client_ca: self.client_ca.clone(),
+                reload_interval: Duration::from_millis(self.reload_interval_ms),
+            })),
+            (None, None) if self.client_ca.is_none() => Ok(None),
+            (None, None) => Err(ConfigError::IncompleteTls("client_ca needs a cert and key")),
+            (Some(_), None) => Err(ConfigError::IncompleteTls("cert needs a key")),
+            (None, Some(_)) => Err(ConfigError::IncompleteTls("key needs a cert")),
+        }
+    }
+}
+
+/* without a policy file every client may call everything */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub policy: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialConfig {
+    pub port: PortConfig,
+    pub baud_rate: u32,
+    pub data_bits: u8,
+    pub parity: ParityConfig,
+    pub stop_bits: u8,
+    pub flow_control: FlowControlConfig,
+    pub timeout_ms: u64,
+    // command prefix to timeout in milliseconds
+    pub command_timeouts: HashMap<String, u64>,
+    pub retry: RetryConfig,
+    pub framing: FramingConfig,
+    // a file every exchange is appended to, or one to play back in place of the port
+    pub capture: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+}
+
+impl Default for SerialConfig {
+    fn default() -> SerialConfig {
+        SerialConfig {
+            port: PortConfig::default(),
+            baud_rate: DEFAULT_BAUD_RATE,
+            data_bits: 8,
+            parity: ParityConfig::None,
+            stop_bits: 1,
+            flow_control: FlowControlConfig::None,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            command_timeouts: HashMap::new(),
+            retry: RetryConfig::default(),
+            framing: FramingConfig::default(),
+            capture: None,
+            replay: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParityConfig {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControlConfig {
+    None,
+    Software,
+    Hardware,
+}
+
+/* io error kinds a command is resent on */
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryOn {
+    TimedOut,
+    Interrupted,
+    WouldBlock,
+    UnexpectedEof,
+    InvalidData,
+    BrokenPipe,
+    Other,
+}
+
+impl From<RetryOn> for std::io::ErrorKind {
+    fn from(r: RetryOn) -> std::io::ErrorKind {
+        match r {
+            RetryOn::TimedOut => std::io::ErrorKind::TimedOut,
+            RetryOn::Interrupted => std::io::ErrorKind::Interrupted,
+            RetryOn::WouldBlock => std::io::ErrorKind::WouldBlock,
+            RetryOn::UnexpectedEof => std::io::ErrorKind::UnexpectedEof,
+            RetryOn::InvalidData => std::io::ErrorKind::InvalidData,
+            RetryOn::BrokenPipe => std::io::ErrorKind::BrokenPipe,
+            RetryOn::Other => std::io::ErrorKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    pub tries: u32,
+    pub backoff_ms: u64,
+    pub backoff_factor: u32,
+    pub max_backoff_ms: u64,
+    // unset retries on any error
+    pub retry_on: Option<Vec<RetryOn>>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> RetryConfig {
+        let policy = RetryPolicy::default();
+        RetryConfig {
+            tries: DEFAULT_TRIES,
+            backoff_ms: policy.backoff.as_millis() as u64,
+            backoff_factor: policy.backoff_factor,
+            max_backoff_ms: policy.max_backoff.as_millis() as u64,
+            retry_on: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FramingConfig {
+    // unset ends every reply on the idle timeout
+    pub end_marker: Option<String>,
+    pub idle_timeout_ms: u64,
+    pub max_len: usize,
+}
+
+impl Default for FramingConfig {
+    fn default() -> FramingConfig {
+        FramingConfig {
+            end_marker: None,
+            idle_timeout_ms: DEFAULT_IDLE_TIMEOUT_MS,
+            max_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+}
+
+impl SerialConfig {
+    pub fn options(&self) -> Result<SerialOptions, ConfigError> {
+        let data_bits = match self.data_bits {
+            5 => DataBits::Five,
+            6 => DataBits::Six,
+            7 => DataBits::Seven,
+            8 => DataBits::Eight,
+            n => return Err(ConfigError::InvalidValue("serial.data_bits", n.to_string())),
+        };
+        let stop_bits = match self.stop_bits {
+            1 => StopBits::One,
+            2 => StopBits::Two,
+            n => return Err(ConfigError::InvalidValue("serial.stop_bits", n.to_string())),
+        };
+        if self.retry.tries == 0 {
+            return Err(ConfigError::InvalidValue(
+                "serial.retry.tries",
+                String::from("0"),
+            ));
+        }
+        if self.framing.idle_timeout_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "serial.framing.idle_timeout_ms",
+                String::from("0"),
+            ));
+        }
+        if self.framing.max_len == 0 {
+            return Err(ConfigError::InvalidValue(
+                "serial.framing.max_len",
+                String::from("0"),
+            ));
+        }
+        if self.capture.is_some() && self.replay.is_some() {
+            return Err(ConfigError::ConflictingCapture);
+        }
+
+        let parity = match self.parity {
+            ParityConfig::None => Parity::None,
+            ParityConfig::Odd => Parity::Odd,
+            ParityConfig::Even => Parity::Even,
+        };
+        let flow_control = match self.flow_control {
+            FlowControlConfig::None => FlowControl::None,
+            FlowControlConfig::Software => FlowControl::Software,
+            FlowControlConfig::Hardware => FlowControl::Hardware,
+        };
+
+        // a replay is opened by the path of the capture, whatever port was configured
+        let port = match self.replay {
+            Some(ref path) => PortSelector::Path(path.display().to_string()),
+            None => self.port.selector()?,
+        };
+        Ok(SerialOptions {
+            port,
+            settings: SerialPortSettings {
+                baud_rate: self.baud_rate,
+                data_bits,
+                flow_control,
+                parity,
+                stop_bits,
+                timeout: Duration::from_millis(self.timeout_ms),
+            },
+            command_timeouts: self
+                .command_timeouts
+                .iter()
+                .map(|(prefix, ms)| (prefix.clone(), Duration::from_millis(*ms)))
+                .collect(),
+            retry: RetryPolicy {
+                tries: self.retry.tries,
+                backoff: Duration::from_millis(self.retry.backoff_ms),
+                backoff_factor: self.retry.backoff_factor,
+                max_backoff: Duration::from_millis(self.retry.max_backoff_ms),
+                retry_on: self
+                    .retry
+                    .retry_on
+                    .as_ref()
+                    .map(|kinds| kinds.iter().map(|k| (*k).into()).collect()),
+            },
+            framing: FrameOptions {
+                end_marker: self.framing.end_marker.clone(),
+                idle_timeout: Duration::from_millis(self.framing.idle_timeout_ms),
+                max_len: self.framing.max_len,
+            },
+        })
+    }
+}
+
+/* at most one way of selecting the port may be set, none falls back to the usb heuristic */
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PortConfig {
+    pub path: Option<String>,
+    pub glob: Option<String>,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+}
+
+impl PortConfig {
+    fn is_empty(&self) -> bool {
+        *self == PortConfig::default()
+    }
+
+    pub fn selector(&self) -> Result<PortSelector, ConfigError> {
+        let usb = self.vid.is_some() || self.pid.is_some() || self.serial_number.is_some();
+        let kinds = [self.path.is_some(), self.glob.is_some(), usb];
+        if kinds.iter().filter(|k| **k).count() > 1 {
+            return Err(ConfigError::ConflictingPortSelectors);
+        }
+
+        if let Some(ref path) = self.path {
+            return Ok(PortSelector::Path(path.clone()));
+        }
+        if let Some(ref glob) = self.glob {
+            return Ok(PortSelector::Glob(glob.clone()));
+        }
+        if usb {
+            return Ok(PortSelector::Usb {
+                vid: self.vid,
+                pid: self.pid,
+                serial_number: self.serial_number.clone(),
+            });
+        }
+        Ok(PortSelector::Auto)
+    }
+}
+
+impl Config {
+    /* reads the command line, then the config file it points to */
+    pub fn load() -> Result<Config, ConfigError> {
+        Config::from_opt(Opt::from_args())
+    }
+
+    pub fn from_opt(opt: Opt) -> Result<Config, ConfigError> {
+        let mut config = match opt.config {
+            Some(ref path) => {
+                let raw = fs::read_to_string(path)
+                    .map_err(|e| ConfigError::IoError(path.display().to_string(), e))?;
+                Config::from_toml(&raw)?
+            }
+            None => Config::default(),
+        };
+
+        /* port flags replace the file's port section as a whole */
+        let port = PortConfig {
+            path: opt.port,
+            glob: opt.port_glob,
+            vid: opt.usb_vid,
+            pid: opt.usb_pid,
+            serial_number: opt.usb_serial,
+        };
+        if !port.is_empty() {
+            config.serial.port = port;
+        }
+        if let Some(baud_rate) = opt.baud_rate {
+            config.serial.baud_rate = baud_rate;
+        }
+        if let Some(timeout_ms) = opt.serial_timeout_ms {
+            config.serial.timeout_ms = timeout_ms;
+        }
+        if let Some(tries) = opt.serial_tries {
+            config.serial.retry.tries = tries;
+        }
+        if opt.allow_no_reader {
+            config.reader.allow_missing = true;
+        }
+        if let Some(listen) = opt.listen {
+            config.server.listen = listen;
+        }
+        if opt.unix_socket.is_some() {
+            config.server.unix_socket = opt.unix_socket;
+        }
+        if opt.output_power.is_some() {
+            config.reader.output_power = opt.output_power;
+        }
+        if opt.modulation.is_some() {
+            config.reader.modulation = opt.modulation;
+        }
+        if opt.metrics_listen.is_some() {
+            config.metrics.listen = opt.metrics_listen;
+        }
+        if let Some(reader_id) = opt.reader_id {
+            config.metrics.reader_id = reader_id;
+        }
+        if let Some(level) = opt.log_level {
+            config.logging.level = level;
+        }
+        if opt.log_json {
+            config.logging.format = LogFormatConfig::Json;
+        }
+        if opt.redact_tags {
+            config.logging.redact_tags = true;
+        }
+        if opt.serial_capture.is_some() {
+            config.serial.capture = opt.serial_capture;
+        }
+        if opt.serial_replay.is_some() {
+            config.serial.replay = opt.serial_replay;
+        }
+        if opt.simulate {
+            config.simulator.enabled = true;
+        }
+
+        config.serial.options()?;
+        config.reader.profile()?;
+        config.logging.options()?;
+        if config.simulator.tags()?.is_some() && config.serial.replay.is_some() {
+            return Err(ConfigError::ConflictingSimulator);
+        }
+        Ok(config)
+    }
+
+    pub fn from_toml(raw: &str) -> Result<Config, ConfigError> {
+        Ok(toml::from_str(raw)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_is_auto() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.serial.port.selector().unwrap(), PortSelector::Auto);
+    }
+
+    #[test]
+    fn usb_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            [serial.port]
+            vid = 0x2047
+            pid = 0x0300
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.serial.port.selector().unwrap(),
+            PortSelector::Usb {
+                vid: Some(0x2047),
+                pid: Some(0x0300),
+                serial_number: None,
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_key() {
+        let res = Config::from_toml("[serial.port]\nname = \"/dev/ttyACM0\"");
+        assert!(res.unwrap_err().to_string().contains("Invalid config file"));
+    }
+
+    #[test]
+    fn conflicting_selectors() {
+        let config = Config::from_toml(
+            r#"
+            [serial.port]
+            path = "/dev/ttyACM0"
+            glob = "/dev/ttyACM*"
+            "#,
+        )
+        .unwrap();
+        assert!(config.serial.port.selector().is_err());
+    }
+
+    #[test]
+    fn flags_override_file() {
+        let opt = Opt::from_iter(&["rfid", "--usb-vid", "0x2047", "--usb-serial", "EVM1"]);
+        let config = Config::from_opt(opt).unwrap();
+        assert_eq!(
+            config.serial.port.selector().unwrap(),
+            PortSelector::Usb {
+                vid: Some(0x2047),
+                pid: None,
+                serial_number: Some(String::from("EVM1")),
+            }
+        );
+    }
+
+    #[test]
+    fn missing_file() {
+        let opt = Opt {
+            config: Some(PathBuf::from("/nonexistent/rfid.toml")),
+            ..Default::default()
+        };
+        let res = Config::from_opt(opt);
+        assert!(res.unwrap_err().to_string().contains("Unable to read"));
+    }
+
+    #[test]
+    fn serial_defaults() {
+        let options = Config::from_toml("").unwrap().serial.options().unwrap();
+        assert_eq!(options.settings.baud_rate, DEFAULT_BAUD_RATE);
+        assert_eq!(
+            options.settings.timeout,
+            Duration::from_millis(DEFAULT_TIMEOUT_MS)
+        );
+        assert_eq!(options.retry, RetryPolicy::default());
+    }
+
+    #[test]
+    fn serial_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            [serial]
+            baud_rate = 9600
+            parity = "even"
+            stop_bits = 2
+            timeout_ms = 500
+
+            [serial.command_timeouts]
+            "010B000304" = 4000
+
+            [serial.retry]
+            tries = 5
+            backoff_ms = 50
+            retry_on = ["timed_out", "interrupted"]
+            "#,
+        )
+        .unwrap();
+        let options = config.serial.options().unwrap();
+
+        assert_eq!(options.settings.baud_rate, 9600);
+        assert_eq!(options.settings.parity, Parity::Even);
+        assert_eq!(options.settings.stop_bits, StopBits::Two);
+        assert_eq!(
+            options.timeout_for("010B000304142601000000"),
+            Duration::from_millis(4000)
+        );
+        assert_eq!(options.retry.tries, 5);
+        assert_eq!(options.retry.backoff, Duration::from_millis(50));
+        assert_eq!(
+            options.retry.retry_on,
+            Some(vec![
+                std::io::ErrorKind::TimedOut,
+                std::io::ErrorKind::Interrupted
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_data_bits() {
+        let config = Config::from_toml("[serial]\ndata_bits = 9").unwrap();
+        let res = config.serial.options();
+        assert!(res.unwrap_err().to_string().contains("serial.data_bits"));
+    }
+
+    #[test]
+    fn framing_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            [serial.framing]
+            end_marker = ">"
+            idle_timeout_ms = 20
+            "#,
+        )
+        .unwrap();
+        let options = config.serial.options().unwrap();
+        assert_eq!(options.framing.end_marker, Some(String::from(">")));
+        assert_eq!(options.framing.idle_timeout, Duration::from_millis(20));
+        assert_eq!(options.framing.max_len, DEFAULT_MAX_FRAME_LEN);
+    }
+
+    #[test]
+    fn zero_idle_timeout() {
+        let config = Config::from_toml("[serial.framing]\nidle_timeout_ms = 0").unwrap();
+        let res = config.serial.options();
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("serial.framing.idle_timeout_ms"));
+    }
+
+    #[test]
+    fn queue_from_toml() {
+        let config = Config::from_toml("[queue]\nmax_depth = 4\norder = \"priority\"").unwrap();
+        let options = config.queue.options().unwrap();
+        assert_eq!(options.max_depth, 4);
+        assert_eq!(options.order, QueueOrder::Priority);
+        assert_eq!(
+            Config::from_toml("").unwrap().queue.options().unwrap(),
+            QueueOptions::default()
+        );
+    }
+
+    #[test]
+    fn inventory_and_presence_from_toml() {
+        let config = Config::from_toml(
+            "[inventory]\npoll_interval_ms = 50\nbuffer = 8\n[presence]\ndeparture_timeout_ms = 300",
+        )
+        .unwrap();
+        let inventory = config.inventory.options().unwrap();
+        assert_eq!(inventory.poll_interval, Duration::from_millis(50));
+        assert_eq!(inventory.buffer, 8);
+        let presence = config.presence.options(&inventory).unwrap();
+        assert_eq!(presence.departure_timeout, Duration::from_millis(300));
+
+        let config = Config::from_toml("").unwrap();
+        let inventory = config.inventory.options().unwrap();
+        assert_eq!(inventory, InventoryOptions::default());
+        assert_eq!(
+            config.presence.options(&inventory).unwrap(),
+            PresenceOptions::default()
+        );
+
+        let config = Config::from_toml(
+            "[inventory]\npoll_interval_ms = 200\n[presence]\ndeparture_timeout_ms = 100",
+        )
+        .unwrap();
+        let res = config
+            .presence
+            .options(&config.inventory.options().unwrap());
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("departure_timeout_ms"));
+    }
+
+    #[test]
+    fn stream_from_toml() {
+        let config =
+            Config::from_toml("[stream]\nidle_timeout_ms = 5000\nmax_credits = 8").unwrap();
+        let options = config.stream.options().unwrap();
+        assert_eq!(options.idle_timeout, Duration::from_secs(5));
+        assert_eq!(options.max_credits, 8);
+        assert_eq!(
+            Config::from_toml("").unwrap().stream.options().unwrap(),
+            FlowOptions::default()
+        );
+
+        let config = Config::from_toml("[stream]\nmax_credits = 0").unwrap();
+        assert!(config.stream.options().is_err());
+    }
+
+    #[test]
+    fn dedup_from_toml() {
+        let config = Config::from_toml("[dedup]\nmode = \"on_change\"\nwindow_ms = 250").unwrap();
+        let options = config.dedup.options().unwrap();
+        assert_eq!(options.mode, DedupMode::OnChange);
+        assert_eq!(options.window, Duration::from_millis(250));
+        assert_eq!(
+            Config::from_toml("").unwrap().dedup.options().unwrap(),
+            DedupOptions::default()
+        );
+        assert!(Config::from_toml("[dedup]\nmode = \"sometimes\"").is_err());
+    }
+
+    #[test]
+    fn events_from_toml() {
+        assert_eq!(Config::from_toml("").unwrap().events.options(), None);
+
+        let config = Config::from_toml(
+            r#"
+            [events]
+            enabled = true
+            path = "/var/lib/rfid/events.db"
+            retention_days = 0
+            max_events = 100000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.events.options(),
+            Some(EventOptions {
+                path: PathBuf::from("/var/lib/rfid/events.db"),
+                retention: None,
+                max_events: Some(100000),
+            })
+        );
+
+        let config = Config::from_toml("[events]\nenabled = true").unwrap();
+        let options = config.events.options().unwrap();
+        assert_eq!(
+            options.retention,
+            Some(Duration::from_secs(30 * 24 * 60 * 60))
+        );
+        assert_eq!(options.max_events, None);
+    }
+
+    #[test]
+    fn tls_from_toml() {
+        assert_eq!(Config::from_toml("").unwrap().tls.options().unwrap(), None);
+
+        let config = Config::from_toml(
+            r#"
+            [tls]
+            cert = "server.pem"
+            key = "server.key"
+            client_ca = "ca.pem"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.tls.options().unwrap(),
+            Some(TlsOptions {
+                cert: PathBuf::from("server.pem"),
+                key: PathBuf::from("server.key"),
+                client_ca: Some(PathBuf::from("ca.pem")),
+                reload_interval: Duration::from_millis(DEFAULT_RELOAD_INTERVAL_MS),
+            })
+        );
+
+        let config = Config::from_toml("[tls]\ncert = \"server.pem\"").unwrap();
+        assert!(config
+            .tls
+            .options()
+            .unwrap_err()
+            .to_string()
+            .contains("cert needs a key"));
+        let config = Config::from_toml("[tls]\nclient_ca = \"ca.pem\"").unwrap();
+        assert!(config.tls.options().is_err());
+    }
+
+    #[test]
+    fn flags_override_serial_settings() {
+        let opt = Opt::from_iter(&["rfid", "--baud-rate", "57600", "--serial-tries", "1"]);
+        let options = Config::from_opt(opt).unwrap().serial.options().unwrap();
+        assert_eq!(options.settings.baud_rate, 57600);
+        assert_eq!(options.retry.tries, 1);
+    }
+
+    #[test]
+    fn reader_allow_missing() {
+        let config = Config::from_toml("").unwrap();
+        assert!(!config.reader.allow_missing);
+
+        let opt = Opt::from_iter(&["rfid", "--allow-no-reader"]);
+        assert!(Config::from_opt(opt).unwrap().reader.allow_missing);
+    }
+
+    #[test]
+    fn reader_profile() {
+        let profile = Config::from_toml("").unwrap().reader.profile().unwrap();
+        assert_eq!(profile, ReaderProfile::default());
+
+        let config = Config::from_toml(
+            "[reader]
+output_power = \"half\"\nmodulation = \"ook100\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.reader.profile().unwrap(),
+            ReaderProfile {
+                output_power: Some(OutputPower::Half),
+                modulation: Some(ModulationDepth::Ook100),
+            }
+        );
+
+        let opt = Opt::from_iter(&["rfid", "--modulation", "ask99"]);
+        assert!(Config::from_opt(opt)
+            .unwrap_err()
+            .to_string()
+            .contains("reader.modulation"));
+    }
+
+    #[test]
+    fn server_listen() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.server.listen, DEFAULT_LISTEN.parse().unwrap());
+        assert_eq!(config.server.unix_socket, None);
+
+        let config = Config::from_toml(
+            "[server]\nlisten = \"127.0.0.1:6000\"\nunix_socket = \"/run/rfid.sock\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.server.listen,
+            SocketAddr::from(([127, 0, 0, 1], 6000))
+        );
+        assert_eq!(
+            config.server.unix_socket,
+            Some(PathBuf::from("/run/rfid.sock"))
+        );
+
+        let opt = Opt::from_iter(&["rfid", "--listen", "0.0.0.0:7000"]);
+        let config = Config::from_opt(opt).unwrap();
+        assert_eq!(config.server.listen, SocketAddr::from(([0, 0, 0, 0], 7000)));
+    }
+
+    #[test]
+    fn health_probe_interval() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.health.options().unwrap(), HealthOptions::default());
+
+        let config = Config::from_toml("[health]\nprobe_interval_ms = 1000").unwrap();
+        assert_eq!(
+            config.health.options().unwrap().probe_interval,
+            Duration::from_millis(1000)
+        );
+
+        let config = Config::from_toml("[health]\nprobe_interval_ms = 0").unwrap();
+        assert!(config.health.options().is_err());
+    }
+
+    #[test]
+    fn metrics_endpoint() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.metrics.listen, None);
+        assert_eq!(config.metrics.reader_id, DEFAULT_READER_ID);
+
+        let config =
+            Config::from_toml("[metrics]\nlisten = \"0.0.0.0:9100\"\nreader_id = \"dock-1\"")
+                .unwrap();
+        assert_eq!(
+            config.metrics.listen,
+            Some(SocketAddr::from(([0, 0, 0, 0], 9100)))
+        );
+        assert_eq!(config.metrics.reader_id, "dock-1");
+
+        let opt = Opt::from_iter(&["rfid", "--reader-id", "dock-2"]);
+        assert_eq!(Config::from_opt(opt).unwrap().metrics.reader_id, "dock-2");
+    }
+
+    #[test]
+    fn logging_options() {
+        let options = Config::from_toml("").unwrap().logging.options().unwrap();
+        assert_eq!(options, LoggingOptions::default());
+
+        let config = Config::from_toml(
+            "[logging]\nlevel = \"info,rfid_trf7970_grpc::serial=debug\"\nformat = \"json\"",
+        )
+        .unwrap();
+        let options = config.logging.options().unwrap();
+        assert_eq!(options.format, LogFormat::Json);
+        assert!(!options.redact_tags);
+
+        let opt = Opt::from_iter(&["rfid", "--log-level", "serial=loud"]);
+        assert!(Config::from_opt(opt)
+            .unwrap_err()
+            .to_string()
+            .contains("logging.level"));
+        let opt = Opt::from_iter(&["rfid", "--redact-tags", "--log-json"]);
+        let options = Config::from_opt(opt).unwrap().logging.options().unwrap();
+        assert!(options.redact_tags);
+        assert_eq!(options.format, LogFormat::Json);
+    }
+
+    #[test]
+    fn serial_capture() {
+        let config = Config::from_toml("[serial]\ncapture = \"/tmp/rfid.jsonl\"").unwrap();
+        assert_eq!(
+            config.serial.capture,
+            Some(PathBuf::from("/tmp/rfid.jsonl"))
+        );
+        assert!(config.serial.options().is_ok());
+
+        let opt = Opt::from_iter(&["rfid", "--serial-replay", "/tmp/rfid.jsonl"]);
+        let options = Config::from_opt(opt).unwrap().serial.options().unwrap();
+        assert_eq!(
+            options.port,
+            PortSelector::Path(String::from("/tmp/rfid.jsonl"))
+        );
+
+        let opt = Opt::from_iter(&[
+            "rfid",
+            "--serial-capture",
+            "/tmp/a.jsonl",
+            "--serial-replay",
+            "/tmp/b.jsonl",
+        ]);
+        match Config::from_opt(opt) {
+            Err(ConfigError::ConflictingCapture) => {}
+            _ => panic!("{}", "Should have been ConflictingCapture"),
+        }
+    }
+
+    #[test]
+    fn simulator_tags() {
+        let config = Config::from_toml(
+            r#"
+            [simulator]
+            enabled = true
+
+            [[simulator.tags]]
+            uid = "E0040150D2F3A4B5"
+            data = "CAFEBABE"
+            locked = [0]
+            afi = 7
+
+            [[simulator.tags]]
+            uid = "E004015000001235"
+            blocks = 8
+            "#,
+        )
+        .unwrap();
+        let tags = config.simulator.tags().unwrap().unwrap();
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].uid, 0xE004_0150_D2F3_A4B5);
+        assert_eq!(tags[0].block(0).unwrap(), &[0xCA, 0xFE, 0xBA, 0xBE]);
+        assert!(tags[0].locked[0] && !tags[0].locked[1]);
+        assert_eq!(tags[0].afi, 7);
+        assert_eq!(tags[1].blocks(), 8);
+        assert!(Config::default().simulator.tags().unwrap().is_none());
+
+        for tag in &[
+            "uid = \"E004\"",
+            "uid = \"E0040150D2F3A4B5\"\ndata = \"CAF\"",
+            "uid = \"E0040150D2F3A4B5\"\nblocks = 1\ndata = \"CAFEBABE00\"",
+            "uid = \"E0040150D2F3A4B5\"\nlocked = [28]",
+        ] {
+            let raw = format!("[simulator]\nenabled = true\n[[simulator.tags]]\n{}", tag);
+            assert!(Config::from_toml(&raw).unwrap().simulator.tags().is_err());
+        }
+
+        let opt = Opt::from_iter(&["rfid", "--simulate", "--serial-replay", "/tmp/a.jsonl"]);
+        match Config::from_opt(opt) {
+            Err(ConfigError::ConflictingSimulator) => {}
+            _ => panic!("{}", "Should have been ConflictingSimulator"),
+        }
+    }
+}
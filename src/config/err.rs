@@ -0,0 +1,47 @@
+use std::fmt;
+use std::fmt::Debug;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IoError(String, std::io::Error),
+    ParseError(toml::de::Error),
+    ConflictingPortSelectors,
+    ConflictingCapture,
+    ConflictingSimulator,
+    InvalidValue(&'static str, String),
+    IncompleteTls(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::IoError(ref path, ref e) => {
+                write!(f, "Unable to read config file {}: {}", path, e)
+            }
+            ConfigError::ParseError(ref e) => write!(f, "Invalid config file: {}", e),
+            ConfigError::ConflictingPortSelectors => write!(
+                f,
+                "Serial port can only be selected by one of path, glob or usb ids"
+            ),
+            ConfigError::ConflictingCapture => write!(
+                f,
+                "Serial traffic can either be captured or replayed, not both"
+            ),
+            ConfigError::ConflictingSimulator => {
+                write!(f, "A replay cannot be used together with the simulator")
+            }
+            ConfigError::InvalidValue(key, ref value) => {
+                write!(f, "Invalid value for {}: {}", key, value)
+            }
+            ConfigError::IncompleteTls(missing) => write!(f, "Incomplete tls config: {}", missing),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> ConfigError {
+        ConfigError::ParseError(err)
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_WINDOW_MS: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DedupMode {
+    Off,
+    // repeats of a tag within the window after it was reported are suppressed
+    Window,
+    // repeats are suppressed until a different tag, or none, is read
+    OnChange,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DedupOptions {
+    pub mode: DedupMode,
+    pub window: Duration,
+}
+
+impl Default for DedupOptions {
+    fn default() -> DedupOptions {
+        DedupOptions {
+            mode: DedupMode::Off,
+            window: Duration::from_millis(DEFAULT_WINDOW_MS),
+        }
+    }
+}
+
+/* suppresses repeated reads for one stream, counting what it held back */
+pub struct Dedup {
+    options: DedupOptions,
+    // last reported read for on change, none when no tag answered
+    last: Option<Option<String>>,
+    // when each tag was reported, for the window. older entries are dropped
+    reported: HashMap<String, Instant>,
+    suppressed: u64,
+}
+
+impl Dedup {
+    pub fn new(options: DedupOptions) -> Dedup {
+        Dedup {
+            options,
+            last: None,
+            reported: HashMap::new(),
+            suppressed: 0,
+        }
+    }
+
+    /* the number of reads suppressed since the last report if this one is to be reported */
+    pub fn check(&mut self, uid: Option<&str>, now: Instant) -> Option<u64> {
+        let repeat = match self.options.mode {
+            DedupMode::Off => false,
+            DedupMode::Window => self.in_window(uid, now),
+            DedupMode::OnChange => match self.last {
+                Some(ref last) => last.as_deref() == uid,
+                None => false,
+            },
+        };
+
+        if repeat {
+            self.suppressed += 1;
+            return None;
+        }
+        self.last = Some(uid.map(String::from));
+        Some(std::mem::replace(&mut self.suppressed, 0))
+    }
+
+    /* whether the tag was reported within the window, noting it as reported if not.
+    no tag answering is always reported and leaves the window as it is */
+    fn in_window(&mut self, uid: Option<&str>, now: Instant) -> bool {
+        let window = self.options.window;
+        self.reported
+            .retain(|_, at| now.saturating_duration_since(*at) < window);
+
+        let uid = match uid {
+            Some(uid) => uid,
+            None => return false,
+        };
+        if self.reported.contains_key(uid) {
+            return true;
+        }
+        self.reported.insert(String::from(uid), now);
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const UID: &str = "CAFEDEADBEEFB0B0";
+
+    fn dedup(mode: DedupMode) -> Dedup {
+        Dedup::new(DedupOptions {
+            mode,
+            window: Duration::from_millis(500),
+        })
+    }
+
+    #[test]
+    fn off_reports_everything() {
+        let mut dedup = dedup(DedupMode::Off);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(dedup.check(Some(UID), now), Some(0));
+        }
+    }
+
+    #[test]
+    fn window_suppresses_repeats() {
+        let mut dedup = dedup(DedupMode::Window);
+        let start = Instant::now();
+
+        assert_eq!(dedup.check(Some(UID), start), Some(0));
+        assert_eq!(
+            dedup.check(Some(UID), start + Duration::from_millis(100)),
+            None
+        );
+        assert_eq!(
+            dedup.check(Some(UID), start + Duration::from_millis(400)),
+            None
+        );
+        assert_eq!(
+            dedup.check(Some(UID), start + Duration::from_millis(500)),
+            Some(2)
+        );
+        assert_eq!(
+            dedup.check(Some(UID), start + Duration::from_millis(600)),
+            None
+        );
+    }
+
+    #[test]
+    fn window_reports_other_tags() {
+        let mut dedup = dedup(DedupMode::Window);
+        let start = Instant::now();
+        let other = "E004015012345678";
+
+        assert_eq!(dedup.check(Some(UID), start), Some(0));
+        assert_eq!(
+            dedup.check(Some(other), start + Duration::from_millis(100)),
+            Some(0)
+        );
+        // each tag keeps its own window, reading another one in between changes nothing
+        assert_eq!(
+            dedup.check(Some(UID), start + Duration::from_millis(200)),
+            None
+        );
+        assert_eq!(
+            dedup.check(None, start + Duration::from_millis(250)),
+            Some(1)
+        );
+        assert_eq!(
+            dedup.check(Some(other), start + Duration::from_millis(300)),
+            None
+        );
+        assert_eq!(
+            dedup.check(Some(UID), start + Duration::from_millis(500)),
+            Some(1)
+        );
+        assert_eq!(
+            dedup.check(Some(other), start + Duration::from_millis(550)),
+            None
+        );
+        assert_eq!(
+            dedup.check(Some(other), start + Duration::from_millis(600)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn on_change() {
+        let mut dedup = dedup(DedupMode::OnChange);
+        let start = Instant::now();
+
+        assert_eq!(dedup.check(Some(UID), start), Some(0));
+        assert_eq!(
+            dedup.check(Some(UID), start + Duration::from_secs(10)),
+            None
+        );
+        assert_eq!(dedup.check(None, start + Duration::from_secs(11)), Some(1));
+        assert_eq!(dedup.check(None, start + Duration::from_secs(12)), None);
+        assert_eq!(
+            dedup.check(Some(UID), start + Duration::from_secs(13)),
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use crate::reader::err::ReaderError;
+use crate::reader::rf::{ModulationDepth, OutputPower, ReaderProfile};
+use crate::reader::ReaderTraits;
+
+pub const DEFAULT_ROUNDS: u32 = 10;
+pub const MAX_ROUNDS: u32 = 1000;
+
+/* what the reader is left with after a sweep, unless the profile sets something else */
+const RESTORE_POWER: OutputPower = OutputPower::Half;
+const RESTORE_DEPTH: ModulationDepth = ModulationDepth::Ook100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Setting {
+    pub power: OutputPower,
+    pub depth: ModulationDepth,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingReport {
+    pub setting: Setting,
+    pub rounds: u32,
+    pub reads: u32,
+    pub rssi_min: u8,
+    pub rssi_max: u8,
+    rssi_sum: u32,
+}
+
+impl SettingReport {
+    fn new(setting: Setting, rounds: u32) -> SettingReport {
+        SettingReport {
+            setting,
+            rounds,
+            reads: 0,
+            rssi_min: 0,
+            rssi_max: 0,
+            rssi_sum: 0,
+        }
+    }
+
+    fn record(&mut self, rssi: u8) {
+        if self.reads == 0 || rssi < self.rssi_min {
+            self.rssi_min = rssi;
+        }
+        if rssi > self.rssi_max {
+            self.rssi_max = rssi;
+        }
+        self.rssi_sum += rssi as u32;
+        self.reads += 1;
+    }
+
+    pub fn success_rate(&self) -> f32 {
+        if self.rounds == 0 {
+            return 0.0;
+        }
+        self.reads as f32 / self.rounds as f32
+    }
+
+    pub fn rssi_avg(&self) -> f32 {
+        if self.reads == 0 {
+            return 0.0;
+        }
+        self.rssi_sum as f32 / self.reads as f32
+    }
+
+    // higher success rate wins, rssi breaks ties
+    fn better_than(&self, other: &SettingReport) -> bool {
+        let (rate, other_rate) = (self.success_rate(), other.success_rate());
+        rate > other_rate || (rate == other_rate && self.rssi_avg() > other.rssi_avg())
+    }
+}
+
+/* walks through every power and modulation combination, one setting per step */
+pub struct Sweep {
+    settings: Vec<Setting>,
+    rounds: u32,
+    next: usize,
+    best: Option<SettingReport>,
+}
+
+impl Sweep {
+    pub fn new(powers: &[OutputPower], depths: &[ModulationDepth], rounds: u32) -> Sweep {
+        let powers = if powers.is_empty() {
+            &OutputPower::ALL[..]
+        } else {
+            powers
+        };
+        let depths = if depths.is_empty() {
+            &ModulationDepth::ALL[..]
+        } else {
+            depths
+        };
+
+        let mut settings = Vec::new();
+        for power in powers {
+            for depth in depths {
+                settings.push(Setting {
+                    power: *power,
+                    depth: *depth,
+                });
+            }
+        }
+
+        Sweep {
+            settings,
+            rounds,
+            next: 0,
+            best: None,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.settings.len()
+    }
+
+    pub fn completed(&self) -> usize {
+        self.next
+    }
+
+    pub fn best(&self) -> Option<&SettingReport> {
+        self.best.as_ref()
+    }
+
+    /* applies the next setting and runs the inventories, None once all settings are done.
+    a missing tag only counts as a failed read, any other error aborts the sweep */
+    pub async fn step(
+        &mut self,
+        reader: &mut dyn ReaderTraits,
+    ) -> Option<Result<SettingReport, ReaderError>> {
+        let setting = *self.settings.get(self.next)?;
+        self.next += 1;
+
+        if let Err(e) = reader.set_output_power(setting.power).await {
+            return Some(Err(e));
+        }
+        if let Err(e) = reader.set_modulation(setting.depth).await {
+            return Some(Err(e));
+        }
+
+        let mut report = SettingReport::new(setting, self.rounds);
+        for _ in 0..self.rounds {
+            match reader.read_uuid_rssi().await {
+                Ok((_, rssi)) => report.record(rssi),
+                Err(ReaderError::NoMatchingTargets(_)) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let is_best = match &self.best {
+            Some(best) => report.better_than(best),
+            None => true,
+        };
+        if is_best {
+            self.best = Some(report.clone());
+        }
+        Some(Ok(report))
+    }
+
+    /* puts the reader back to its normal operating settings */
+    pub async fn restore(
+        reader: &mut dyn ReaderTraits,
+        profile: &ReaderProfile,
+    ) -> Result<(), ReaderError> {
+        let restored = ReaderProfile {
+            output_power: Some(profile.output_power.unwrap_or(RESTORE_POWER)),
+            modulation: Some(profile.modulation.unwrap_or(RESTORE_DEPTH)),
+        };
+        restored.apply(reader).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::MockReaderTraits;
+    use crate::serial::err::SerialError;
+    use mockall::predicate::eq;
+
+    #[test]
+    fn empty_sweeps_everything() {
+        let sweep = Sweep::new(&[], &[], DEFAULT_ROUNDS);
+        assert_eq!(
+            sweep.total(),
+            OutputPower::ALL.len() * ModulationDepth::ALL.len()
+        );
+        assert_eq!(sweep.completed(), 0);
+    }
+
+    #[tokio::test]
+    async fn counts_reads_and_rssi() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_set_output_power()
+            .with(eq(OutputPower::Full))
+            .times(1)
+            .returning(|_| Ok(()));
+        reader
+            .expect_set_modulation()
+            .with(eq(ModulationDepth::Ask10))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let mut rssi = vec![0x10, 0x30];
+        reader
+            .expect_read_uuid_rssi()
+            .times(3)
+            .returning(move || match rssi.pop() {
+                Some(r) => Ok((String::from("E0BEADDEBEBAFECA"), r)),
+                None => Err(ReaderError::NoMatchingTargets(String::from(""))),
+            });
+
+        let mut sweep = Sweep::new(&[OutputPower::Full], &[ModulationDepth::Ask10], 3);
+        let report = sweep.step(&mut reader).await.unwrap().unwrap();
+        assert!(sweep.step(&mut reader).await.is_none());
+
+        assert_eq!(report.reads, 2);
+        assert_eq!(report.rssi_min, 0x10);
+        assert_eq!(report.rssi_max, 0x30);
+        assert!((report.success_rate() - 2.0 / 3.0).abs() < f32::EPSILON);
+        assert!((report.rssi_avg() - 32.0).abs() < f32::EPSILON);
+        assert_eq!(sweep.completed(), 1);
+    }
+
+    #[tokio::test]
+    async fn serial_error_aborts() {
+        let mut reader = MockReaderTraits::new();
+        reader.expect_set_output_power().returning(|_| Ok(()));
+        reader.expect_set_modulation().returning(|_| Ok(()));
+        reader.expect_read_uuid_rssi().times(1).returning(|| {
+            Err(ReaderError::SerialError(
+                SerialError::NoReplyAfterMultipleTries(vec![]),
+            ))
+        });
+
+        let mut sweep = Sweep::new(&[OutputPower::Half], &[ModulationDepth::Ask7], 5);
+        let res = sweep.step(&mut reader).await.unwrap();
+        assert!(res.is_err());
+        assert!(sweep.best().is_none());
+    }
+
+    #[tokio::test]
+    async fn picks_best_setting() {
+        let mut reader = MockReaderTraits::new();
+        reader.expect_set_output_power().returning(|_| Ok(()));
+        reader.expect_set_modulation().returning(|_| Ok(()));
+
+        let mut calls = 0;
+        reader.expect_read_uuid_rssi().returning(move || {
+            calls += 1;
+            // only the second setting ever sees the tag
+            if calls > 2 {
+                Ok((String::from("E0BEADDEBEBAFECA"), 0x20))
+            } else {
+                Err(ReaderError::NoMatchingTargets(String::from("")))
+            }
+        });
+
+        let mut sweep = Sweep::new(
+            &[OutputPower::Full],
+            &[ModulationDepth::Ask10, ModulationDepth::Ask30],
+            2,
+        );
+        while let Some(res) = sweep.step(&mut reader).await {
+            assert!(res.is_ok());
+        }
+
+        let best = sweep.best().unwrap();
+        assert_eq!(best.setting.depth, ModulationDepth::Ask30);
+        assert_eq!(best.reads, 2);
+    }
+
+    #[tokio::test]
+    async fn restore_ok() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_set_output_power()
+            .with(eq(RESTORE_POWER))
+            .times(1)
+            .returning(|_| Ok(()));
+        reader
+            .expect_set_modulation()
+            .with(eq(RESTORE_DEPTH))
+            .times(1)
+            .returning(|_| Ok(()));
+        assert!(Sweep::restore(&mut reader, &ReaderProfile::default())
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn restore_to_profile() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_set_output_power()
+            .with(eq(OutputPower::Full))
+            .times(1)
+            .returning(|_| Ok(()));
+        reader
+            .expect_set_modulation()
+            .with(eq(RESTORE_DEPTH))
+            .times(1)
+            .returning(|_| Ok(()));
+        let profile = ReaderProfile {
+            output_power: Some(OutputPower::Full),
+            modulation: None,
+        };
+        assert!(Sweep::restore(&mut reader, &profile).await.is_ok());
+    }
+}
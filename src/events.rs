@@ -0,0 +1,622 @@
+use rusqlite::{params, Connection, Row};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, RecvError};
+use tokio::sync::oneshot;
+
+pub mod err;
+
+use err::EventError;
+
+pub const DEFAULT_EVENTS_PATH: &str = "events.db";
+pub const DEFAULT_RETENTION_DAYS: u64 = 30;
+pub const DEFAULT_PAGE_SIZE: u32 = 100;
+pub const MAX_PAGE_SIZE: u32 = 1000;
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+// new events kept for subscribers that fall behind before they go back to the database
+const APPENDED_BUFFER: usize = 256;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        at_ms INTEGER NOT NULL,
+        uid TEXT NOT NULL,
+        operation TEXT NOT NULL,
+        block INTEGER,
+        data_hash TEXT,
+        rssi INTEGER,
+        client TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS events_at ON events (at_ms);
+    CREATE INDEX IF NOT EXISTS events_uid ON events (uid, id);
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Inventory,
+    ReadUuid,
+    ReadBlock,
+}
+
+impl Operation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Operation::Inventory => "inventory",
+            Operation::ReadUuid => "read_uuid",
+            Operation::ReadBlock => "read_block",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Operation> {
+        match s {
+            "inventory" => Some(Operation::Inventory),
+            "read_uuid" => Some(Operation::ReadUuid),
+            "read_block" => Some(Operation::ReadBlock),
+            _ => None,
+        }
+    }
+}
+
+/* one tag seen by the reader */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub uid: String,
+    pub operation: Operation,
+    pub block: Option<u32>,
+    // sha-256 of the data read, in hex, so the log does not keep tag contents
+    pub data_hash: Option<String>,
+    pub rssi: Option<u8>,
+    pub at: SystemTime,
+    // peer address of the caller, or the service itself for background reads
+    pub client: String,
+}
+
+impl Event {
+    pub fn new(operation: Operation, uid: &str, client: &str) -> Event {
+        Event {
+            uid: String::from(uid),
+            operation,
+            block: None,
+            data_hash: None,
+            rssi: None,
+            at: SystemTime::now(),
+            client: String::from(client),
+        }
+    }
+
+    pub fn with_block(mut self, block: u32, data: &str) -> Event {
+        self.block = Some(block);
+        self.data_hash = Some(format!("{:x}", Sha256::digest(data.as_bytes())));
+        self
+    }
+
+    pub fn with_rssi(mut self, rssi: u8) -> Event {
+        self.rssi = Some(rssi);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredEvent {
+    pub id: u64,
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventQuery {
+    pub from: Option<SystemTime>,
+    pub to: Option<SystemTime>,
+    pub uid: Option<String>,
+    // events with a higher id than this, zero starts at the oldest
+    pub after: u64,
+    pub limit: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventPage {
+    pub events: Vec<StoredEvent>,
+    // id to continue after, none on the last page
+    pub next: Option<u64>,
+    // id of the oldest event kept, or of the next one to be recorded when none is
+    pub oldest: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventOptions {
+    pub path: PathBuf,
+    // none keeps events forever
+    pub retention: Option<Duration>,
+    // none keeps any number of events
+    pub max_events: Option<u64>,
+}
+
+enum Job {
+    Record(Event),
+    Query(EventQuery, oneshot::Sender<Result<EventPage, EventError>>),
+}
+
+fn to_millis(t: SystemTime) -> i64 {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as i64,
+        Err(_) => 0,
+    }
+}
+
+fn to_stored(row: &Row) -> rusqlite::Result<StoredEvent> {
+    let operation: String = row.get(3)?;
+    let block: Option<i64> = row.get(4)?;
+    let rssi: Option<i64> = row.get(6)?;
+    let at_ms: i64 = row.get(1)?;
+    let id: i64 = row.get(0)?;
+
+    Ok(StoredEvent {
+        id: id as u64,
+        event: Event {
+            uid: row.get(2)?,
+            // written by this module only, anything else is treated as a plain read
+            operation: Operation::from_str(&operation).unwrap_or(Operation::ReadUuid),
+            block: block.map(|b| b as u32),
+            data_hash: row.get(5)?,
+            rssi: rssi.map(|r| r as u8),
+            at: UNIX_EPOCH + Duration::from_millis(at_ms as u64),
+            client: row.get(7)?,
+        },
+    })
+}
+
+/* the database, only ever touched by the event log thread */
+struct Store {
+    conn: Connection,
+    retention: Option<Duration>,
+    max_events: Option<u64>,
+}
+
+impl Store {
+    fn open(options: &EventOptions) -> Result<Store, EventError> {
+        let conn = Connection::open(&options.path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Store {
+            conn,
+            retention: options.retention,
+            max_events: options.max_events,
+        })
+    }
+
+    fn insert(&self, event: &Event) -> Result<u64, EventError> {
+        self.conn.execute(
+            "INSERT INTO events (at_ms, uid, operation, block, data_hash, rssi, client)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                to_millis(event.at),
+                event.uid,
+                event.operation.as_str(),
+                event.block.map(|b| b as i64),
+                event.data_hash,
+                event.rssi.map(|r| r as i64),
+                event.client,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid() as u64)
+    }
+
+    fn query(&self, query: &EventQuery) -> Result<EventPage, EventError> {
+        let limit = std::cmp::min(query.limit, MAX_PAGE_SIZE);
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, at_ms, uid, operation, block, data_hash, rssi, client FROM events
+             WHERE id > ?1 AND at_ms >= ?2 AND at_ms <= ?3 AND (?4 IS NULL OR uid = ?4)
+             ORDER BY id LIMIT ?5",
+        )?;
+        // one more than asked for tells whether there is another page
+        let rows = stmt.query_map(
+            params![
+                query.after as i64,
+                query.from.map(to_millis).unwrap_or(0),
+                query.to.map(to_millis).unwrap_or(i64::MAX),
+                query.uid,
+                limit as i64 + 1,
+            ],
+            to_stored,
+        )?;
+        let mut events = rows.collect::<rusqlite::Result<Vec<StoredEvent>>>()?;
+
+        let next = if events.len() > limit as usize {
+            events.truncate(limit as usize);
+            events.last().map(|e| e.id)
+        } else {
+            None
+        };
+        // read in the same job as the page, so pruning cannot come in between
+        let oldest: i64 = self.conn.query_row(
+            "SELECT COALESCE(
+                 (SELECT MIN(id) FROM events),
+                 (SELECT seq + 1 FROM sqlite_sequence WHERE name = 'events'),
+                 1)",
+            params![],
+            |row| row.get(0),
+        )?;
+        Ok(EventPage {
+            events,
+            next,
+            oldest: oldest as u64,
+        })
+    }
+
+    fn prune(&self) -> Result<usize, EventError> {
+        let mut removed = 0;
+        if let Some(retention) = self.retention {
+            let oldest = to_millis(SystemTime::now()) - retention.as_millis() as i64;
+            removed += self
+                .conn
+                .execute("DELETE FROM events WHERE at_ms < ?1", params![oldest])?;
+        }
+        if let Some(max_events) = self.max_events {
+            // nothing is removed while there are fewer events than the limit
+            removed += self.conn.execute(
+                "DELETE FROM events WHERE id < \
+                 (SELECT id FROM events ORDER BY id DESC LIMIT 1 OFFSET ?1)",
+                params![max_events as i64 - 1],
+            )?;
+        }
+        Ok(removed)
+    }
+
+    #[cfg(test)]
+    fn count(&self) -> Result<u64, EventError> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM events", params![], |row| row.get(0))?;
+        Ok(count as u64)
+    }
+}
+
+fn prune(store: &Store) {
+    match store.prune() {
+        Ok(0) => {}
+        Ok(n) => log::info!("Removed {} events past retention", n),
+        Err(e) => log::error!("{}", e),
+    }
+}
+
+fn serve(store: Store, jobs: mpsc::Receiver<Job>, appended: broadcast::Sender<StoredEvent>) {
+    prune(&store);
+    loop {
+        match jobs.recv_timeout(PRUNE_INTERVAL) {
+            Ok(Job::Record(event)) => match store.insert(&event) {
+                // nobody may be subscribed
+                Ok(id) => {
+                    let _ = appended.send(StoredEvent { id, event });
+                }
+                Err(e) => log::error!("Unable to record event: {}", e),
+            },
+            Ok(Job::Query(query, reply)) => {
+                // the caller may have given up already
+                let _ = reply.send(store.query(&query));
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => prune(&store),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/* an append only log of every tag seen. writes go to a thread of their own, so a slow
+disk never holds up a read */
+#[derive(Clone)]
+pub struct EventLog {
+    jobs: Arc<Mutex<mpsc::Sender<Job>>>,
+    appended: broadcast::Sender<StoredEvent>,
+}
+
+impl EventLog {
+    pub fn open(options: &EventOptions) -> Result<EventLog, EventError> {
+        let store = Store::open(options)?;
+        let (jobs, rx) = mpsc::channel();
+        let (appended, _) = broadcast::channel(APPENDED_BUFFER);
+        let sender = appended.clone();
+        std::thread::Builder::new()
+            .name(String::from("event-log"))
+            .spawn(move || serve(store, rx, sender))
+            .map_err(|_| EventError::Stopped)?;
+
+        Ok(EventLog {
+            jobs: Arc::new(Mutex::new(jobs)),
+            appended,
+        })
+    }
+
+    pub fn record(&self, event: Event) {
+        if self.jobs.lock().unwrap().send(Job::Record(event)).is_err() {
+            log::error!("{}", EventError::Stopped);
+        }
+    }
+
+    pub async fn query(&self, query: EventQuery) -> Result<EventPage, EventError> {
+        let (reply, page) = oneshot::channel();
+        self.jobs
+            .lock()
+            .unwrap()
+            .send(Job::Query(query, reply))
+            .map_err(|_| EventError::Stopped)?;
+        page.await.map_err(|_| EventError::Stopped)?
+    }
+
+    /* every event with a higher id than after, the ones already stored first. none starts
+    at the oldest event kept */
+    pub fn replay(&self, after: Option<u64>) -> Replay {
+        Replay {
+            log: self.clone(),
+            // subscribed before the first query, so nothing recorded in between is lost
+            appended: self.appended.subscribe(),
+            last: after.unwrap_or(0),
+            pending: VecDeque::new(),
+            catching_up: true,
+            resumed: after.is_some(),
+        }
+    }
+}
+
+/* follows the log in id order without gaps or repeats. stored events are read back a
+page at a time until the replay has caught up, then new events are taken as they are
+recorded. a replay that falls too far behind goes back to reading pages. when the events
+it has to go on with were pruned meanwhile it fails rather than skip them */
+pub struct Replay {
+    log: EventLog,
+    appended: broadcast::Receiver<StoredEvent>,
+    // id of the last event handed out
+    last: u64,
+    pending: VecDeque<StoredEvent>,
+    catching_up: bool,
+    // false while the replay may still start at whatever event is the oldest
+    resumed: bool,
+}
+
+impl Replay {
+    pub async fn next(&mut self) -> Result<StoredEvent, EventError> {
+        loop {
+            if let Some(stored) = self.pending.pop_front() {
+                self.last = stored.id;
+                self.resumed = true;
+                return Ok(stored);
+            }
+
+            if self.catching_up {
+                let page = self
+                    .log
+                    .query(EventQuery {
+                        after: self.last,
+                        limit: MAX_PAGE_SIZE,
+                        ..EventQuery::default()
+                    })
+                    .await?;
+                if self.resumed && page.oldest > self.last + 1 {
+                    return Err(EventError::Pruned(self.last + 1));
+                }
+                self.catching_up = page.next.is_some();
+                self.pending.extend(page.events);
+                continue;
+            }
+
+            match self.appended.recv().await {
+                // already read back from the database
+                Ok(stored) if stored.id <= self.last => continue,
+                Ok(stored) => {
+                    self.last = stored.id;
+                    self.resumed = true;
+                    return Ok(stored);
+                }
+                Err(RecvError::Lagged(_)) => self.catching_up = true,
+                Err(RecvError::Closed) => return Err(EventError::Stopped),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scaffold::scaffold::temp_dir;
+
+    const UID: &str = "CAFEDEADBEEFB0B0";
+    const OTHER: &str = "E004015012345678";
+
+    fn options() -> EventOptions {
+        EventOptions {
+            path: PathBuf::from(":memory:"),
+            retention: None,
+            max_events: None,
+        }
+    }
+
+    fn at(ms: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    fn event(uid: &str, ms: u64) -> Event {
+        Event {
+            at: at(ms),
+            ..Event::new(Operation::ReadUuid, uid, "[::1]:4000")
+        }
+    }
+
+    fn page(store: &Store, query: EventQuery) -> Vec<u64> {
+        let page = store.query(&query).unwrap();
+        page.events
+            .iter()
+            .map(|e| to_millis(e.event.at) as u64)
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let store = Store::open(&options()).unwrap();
+        let event = Event::new(Operation::ReadBlock, UID, "[::1]:4000")
+            .with_block(3, "0102")
+            .with_rssi(0x40);
+        let id = store.insert(&event).unwrap();
+
+        let page = store
+            .query(&EventQuery {
+                limit: DEFAULT_PAGE_SIZE,
+                ..EventQuery::default()
+            })
+            .unwrap();
+        assert_eq!(page.next, None);
+        assert_eq!(page.events.len(), 1);
+        assert_eq!(page.events[0].id, id);
+
+        let stored = &page.events[0].event;
+        assert_eq!(stored.operation, Operation::ReadBlock);
+        assert_eq!(stored.block, Some(3));
+        assert_eq!(stored.rssi, Some(0x40));
+        assert_eq!(stored.client, "[::1]:4000");
+        assert_eq!(stored.data_hash.as_ref().unwrap().len(), 64);
+        // only millisecond precision is kept
+        assert_eq!(to_millis(stored.at), to_millis(event.at));
+    }
+
+    #[test]
+    fn filters_and_pages() {
+        let store = Store::open(&options()).unwrap();
+        for ms in 1..=5 {
+            store.insert(&event(UID, ms * 1000)).unwrap();
+            store.insert(&event(OTHER, ms * 1000 + 1)).unwrap();
+        }
+
+        let query = EventQuery {
+            from: Some(at(2000)),
+            to: Some(at(4000)),
+            uid: Some(String::from(UID)),
+            after: 0,
+            limit: 2,
+        };
+        let first = store.query(&query).unwrap();
+        assert_eq!(page(&store, query.clone()), vec![2000, 3000]);
+
+        let rest = EventQuery {
+            after: first.next.unwrap(),
+            ..query
+        };
+        assert_eq!(page(&store, rest.clone()), vec![4000]);
+        assert_eq!(store.query(&rest).unwrap().next, None);
+    }
+
+    #[test]
+    fn retention_removes_old_events() {
+        let store = Store::open(&EventOptions {
+            retention: Some(Duration::from_secs(60)),
+            ..options()
+        })
+        .unwrap();
+        store.insert(&event(UID, 1000)).unwrap();
+        store
+            .insert(&Event::new(Operation::Inventory, UID, ""))
+            .unwrap();
+
+        assert_eq!(store.prune().unwrap(), 1);
+        assert_eq!(store.count().unwrap(), 1);
+    }
+
+    #[test]
+    fn max_events_keeps_newest() {
+        let store = Store::open(&EventOptions {
+            max_events: Some(3),
+            ..options()
+        })
+        .unwrap();
+        store.insert(&event(UID, 1000)).unwrap();
+        assert_eq!(store.prune().unwrap(), 0);
+
+        for ms in 2..=5 {
+            store.insert(&event(UID, ms * 1000)).unwrap();
+        }
+        assert_eq!(store.prune().unwrap(), 2);
+        let all = EventQuery {
+            limit: DEFAULT_PAGE_SIZE,
+            ..EventQuery::default()
+        };
+        assert_eq!(page(&store, all), vec![3000, 4000, 5000]);
+    }
+
+    #[tokio::test]
+    async fn log_records_in_background() {
+        let log = EventLog::open(&options()).unwrap();
+        log.record(event(UID, 1000));
+        log.record(event(OTHER, 2000));
+
+        let page = log
+            .query(EventQuery {
+                uid: Some(String::from(OTHER)),
+                limit: DEFAULT_PAGE_SIZE,
+                ..EventQuery::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.events.len(), 1);
+        assert_eq!(page.events[0].event.uid, OTHER);
+    }
+
+    #[tokio::test]
+    async fn replay_resumes_without_gaps() {
+        let log = EventLog::open(&options()).unwrap();
+        for ms in 1..=3 {
+            log.record(event(UID, ms * 1000));
+        }
+
+        let mut replay = log.replay(Some(1));
+        assert_eq!(replay.next().await.unwrap().id, 2);
+        log.record(event(OTHER, 4000));
+        assert_eq!(replay.next().await.unwrap().id, 3);
+
+        let live = replay.next().await.unwrap();
+        assert_eq!(live.id, 4);
+        assert_eq!(live.event.uid, OTHER);
+        log.record(event(UID, 5000));
+        assert_eq!(replay.next().await.unwrap().id, 5);
+    }
+
+    #[tokio::test]
+    async fn lagging_replay_reads_back() {
+        let log = EventLog::open(&options()).unwrap();
+        let mut replay = log.replay(None);
+        log.record(event(UID, 1000));
+        assert_eq!(replay.next().await.unwrap().id, 1);
+
+        let total = APPENDED_BUFFER as u64 * 2;
+        for ms in 2..=total {
+            log.record(event(UID, ms * 1000));
+        }
+        for id in 2..=total {
+            assert_eq!(replay.next().await.unwrap().id, id);
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_fails_on_pruned_events() {
+        let dir = temp_dir();
+        let options = EventOptions {
+            path: dir.join("events.db"),
+            max_events: Some(2),
+            ..options()
+        };
+        {
+            let store = Store::open(&options).unwrap();
+            for ms in 1..=5 {
+                store.insert(&event(UID, ms * 1000)).unwrap();
+            }
+        }
+        // pruned down to the last two events on opening
+        let log = EventLog::open(&options).unwrap();
+
+        let mut replay = log.replay(Some(1));
+        match replay.next().await {
+            Err(EventError::Pruned(id)) => assert_eq!(id, 2),
+            res => panic!("Should have been pruned, got {:?}", res),
+        }
+        assert_eq!(log.replay(Some(3)).next().await.unwrap().id, 4);
+        assert_eq!(log.replay(None).next().await.unwrap().id, 4);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
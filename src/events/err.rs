@@ -0,0 +1,33 @@
+use std::fmt;
+use std::fmt::Debug;
+
+#[derive(Debug)]
+pub enum EventError {
+    Sqlite(rusqlite::Error),
+    // the thread owning the database is gone
+    Stopped,
+    InvalidPageToken(String),
+    // a replay was to go on from an event that is no longer kept
+    Pruned(u64),
+}
+
+impl fmt::Display for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EventError::Sqlite(ref e) => write!(f, "Event log error: {}", e),
+            EventError::Stopped => write!(f, "Event log is not running"),
+            EventError::InvalidPageToken(ref token) => {
+                write!(f, "Invalid page token: {}", token)
+            }
+            EventError::Pruned(id) => write!(f, "Event {} is no longer kept", id),
+        }
+    }
+}
+
+impl std::error::Error for EventError {}
+
+impl From<rusqlite::Error> for EventError {
+    fn from(err: rusqlite::Error) -> EventError {
+        EventError::Sqlite(err)
+    }
+}
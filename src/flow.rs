@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+pub const DEFAULT_IDLE_TIMEOUT_MS: u64 = 1000;
+pub const DEFAULT_MAX_CREDITS: u32 = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowOptions {
+    // how long a stream without credits waits for the client before giving up
+    pub idle_timeout: Duration,
+    // reads a client may have granted but not yet received
+    pub max_credits: u32,
+}
+
+impl Default for FlowOptions {
+    fn default() -> FlowOptions {
+        FlowOptions {
+            idle_timeout: Duration::from_millis(DEFAULT_IDLE_TIMEOUT_MS),
+            max_credits: DEFAULT_MAX_CREDITS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlowAction {
+    // grants that many reads, zero grants one like a plain ack
+    Ack(u32),
+    Pause,
+    Resume,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlowState {
+    Ready,
+    // credits are kept, reading goes on after a resume
+    Paused,
+    // out of credits, waiting for the client
+    Starved,
+}
+
+/* the reads a continuous stream may make before it has to wait for the client */
+pub struct Flow {
+    credits: u32,
+    max_credits: u32,
+    paused: bool,
+}
+
+impl Flow {
+    pub fn new(max_credits: u32) -> Flow {
+        Flow {
+            credits: 0,
+            max_credits,
+            paused: false,
+        }
+    }
+
+    pub fn apply(&mut self, action: FlowAction) {
+        match action {
+            FlowAction::Ack(credits) => {
+                let credits = std::cmp::max(credits, 1);
+                self.credits =
+                    std::cmp::min(self.credits.saturating_add(credits), self.max_credits);
+            }
+            FlowAction::Pause => self.paused = true,
+            FlowAction::Resume => self.paused = false,
+        }
+    }
+
+    pub fn state(&self) -> FlowState {
+        if self.paused {
+            FlowState::Paused
+        } else if self.credits == 0 {
+            FlowState::Starved
+        } else {
+            FlowState::Ready
+        }
+    }
+
+    /* gives back the credit of a read the client never got */
+    pub fn refund(&mut self) {
+        self.credits = std::cmp::min(self.credits + 1, self.max_credits);
+    }
+
+    /* uses up a credit for one read, false if the stream may not read now */
+    pub fn spend(&mut self) -> bool {
+        if self.state() != FlowState::Ready {
+            return false;
+        }
+        self.credits -= 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ack_grants_credits() {
+        let mut flow = Flow::new(DEFAULT_MAX_CREDITS);
+        assert_eq!(flow.state(), FlowState::Starved);
+        assert!(!flow.spend());
+
+        flow.apply(FlowAction::Ack(3));
+        for _ in 0..3 {
+            assert!(flow.spend());
+        }
+        assert!(!flow.spend());
+        assert_eq!(flow.state(), FlowState::Starved);
+    }
+
+    #[test]
+    fn plain_ack_grants_one() {
+        let mut flow = Flow::new(DEFAULT_MAX_CREDITS);
+        flow.apply(FlowAction::Ack(0));
+        assert!(flow.spend());
+        assert!(!flow.spend());
+    }
+
+    #[test]
+    fn credits_capped() {
+        let mut flow = Flow::new(2);
+        flow.apply(FlowAction::Ack(u32::MAX));
+        flow.apply(FlowAction::Ack(5));
+        assert!(flow.spend());
+        assert!(flow.spend());
+        assert!(!flow.spend());
+    }
+
+    #[test]
+    fn pause_keeps_credits() {
+        let mut flow = Flow::new(DEFAULT_MAX_CREDITS);
+        flow.apply(FlowAction::Ack(2));
+        flow.apply(FlowAction::Pause);
+        assert_eq!(flow.state(), FlowState::Paused);
+        assert!(!flow.spend());
+
+        flow.apply(FlowAction::Resume);
+        assert!(flow.spend());
+        assert!(flow.spend());
+        assert!(!flow.spend());
+    }
+}
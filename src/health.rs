@@ -0,0 +1,275 @@
+use std::time::Duration;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::watch;
+use tonic::{Request, Response, Status};
+
+use crate::include::health::health_check_response::ServingStatus;
+use crate::include::health::health_server::Health;
+use crate::include::health::{HealthCheckRequest, HealthCheckResponse};
+use crate::queue::Priority;
+use crate::reader::err::ReaderError;
+use crate::supervisor::ReaderLink;
+
+pub const DEFAULT_PROBE_INTERVAL_MS: u64 = 5000;
+
+// the empty name stands for the server as a whole, which is only as good as its reader
+const SERVICES: &[&str] = &["", "rfid.ReadInfo"];
+const WATCH_BUFFER_SIZE: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthOptions {
+    pub probe_interval: Duration,
+}
+
+impl Default for HealthOptions {
+    fn default() -> HealthOptions {
+        HealthOptions {
+            probe_interval: Duration::from_millis(DEFAULT_PROBE_INTERVAL_MS),
+        }
+    }
+}
+
+/* asks the reader for a tag every interval. serving only while the last probe got an
+answer, not finding a tag is an answer too */
+pub struct Prober {
+    link: ReaderLink,
+    options: HealthOptions,
+    serving: watch::Sender<bool>,
+    // kept to hand out to the services
+    watched: watch::Receiver<bool>,
+}
+
+impl Prober {
+    pub fn new(link: ReaderLink, options: HealthOptions) -> Prober {
+        let (serving, watched) = watch::channel(false);
+        Prober {
+            link,
+            options,
+            serving,
+            watched,
+        }
+    }
+
+    pub fn service(&self) -> HealthService {
+        HealthService {
+            serving: self.watched.clone(),
+        }
+    }
+
+    pub async fn run(self) {
+        loop {
+            if let Some(serving) = self.probe().await {
+                if serving != *self.watched.borrow() {
+                    log::info!("Health changed, serving: {}", serving);
+                    if self.serving.broadcast(serving).is_err() {
+                        log::error!("{}", "Unable to publish health");
+                    }
+                }
+            }
+            tokio::time::delay_for(self.options.probe_interval).await;
+        }
+    }
+
+    /* none when the reader was busy for the whole interval, clients are using it so the
+    last answer stands */
+    async fn probe(&self) -> Option<bool> {
+        let mut turn = match self
+            .link
+            .acquire(Priority::Low, Some(self.options.probe_interval))
+            .await
+        {
+            Ok(turn) => turn,
+            Err(e) => {
+                log::debug!("Health probe skipped: {}", e);
+                return None;
+            }
+        };
+        let reader = match turn.as_mut() {
+            Some(reader) => reader,
+            None => return Some(false),
+        };
+
+        match reader.read_uuid().await {
+            Ok(_) | Err(ReaderError::NoMatchingTargets(_)) => Some(true),
+            Err(e) => {
+                log::warn!("Health probe failed: {}", e);
+                self.link.check_lost(&mut turn, &e);
+                Some(false)
+            }
+        }
+    }
+}
+
+fn to_response(serving: bool) -> HealthCheckResponse {
+    let status = if serving {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    };
+    HealthCheckResponse {
+        status: status as i32,
+    }
+}
+
+/* grpc.health.v1.Health, reporting what the prober last saw */
+pub struct HealthService {
+    serving: watch::Receiver<bool>,
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = &request.get_ref().service;
+        if !SERVICES.contains(&service.as_str()) {
+            return Err(Status::not_found(format!("Unknown service {}", service)));
+        }
+        Ok(Response::new(to_response(*self.serving.borrow())))
+    }
+
+    type WatchStream = Receiver<Result<HealthCheckResponse, Status>>;
+
+    /* the current status first, then every change. an unknown service stays unknown */
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let (mut tx, rx): (
+            Sender<Result<HealthCheckResponse, Status>>,
+            Receiver<Result<HealthCheckResponse, Status>>,
+        ) = mpsc::channel(WATCH_BUFFER_SIZE);
+
+        if !SERVICES.contains(&request.get_ref().service.as_str()) {
+            let unknown = HealthCheckResponse {
+                status: ServingStatus::ServiceUnknown as i32,
+            };
+            // the client is gone if this fails, nothing left to do
+            let _ = tx.send(Ok(unknown)).await;
+            return Ok(Response::new(rx));
+        }
+
+        let mut serving = self.serving.clone();
+        tokio::spawn(async move {
+            while let Some(s) = serving.recv().await {
+                if tx.send(Ok(to_response(s))).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(rx))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::MockReaderTraits;
+    use crate::serial::err::SerialError;
+
+    fn status(res: Result<Response<HealthCheckResponse>, Status>) -> i32 {
+        res.unwrap().into_inner().status
+    }
+
+    fn check(name: &str) -> Request<HealthCheckRequest> {
+        Request::new(HealthCheckRequest {
+            service: String::from(name),
+        })
+    }
+
+    #[tokio::test]
+    async fn serving_while_reader_answers() {
+        let mut reader = MockReaderTraits::new();
+        let mut calls = 0;
+        reader.expect_read_uuid().returning(move || {
+            calls += 1;
+            match calls {
+                1 => Err(ReaderError::NoMatchingTargets(String::from("[]"))),
+                2 => Ok(String::from("CAFEDEADBEEFB0B0")),
+                _ => Err(ReaderError::SerialError(SerialError::IoError(
+                    std::io::Error::new(std::io::ErrorKind::TimedOut, "Timed out"),
+                ))),
+            }
+        });
+        let prober = Prober::new(
+            ReaderLink::fixed(Some(Box::new(reader))),
+            HealthOptions::default(),
+        );
+        let service = prober.service();
+        assert_eq!(
+            status(service.check(check("")).await),
+            ServingStatus::NotServing as i32
+        );
+
+        assert_eq!(prober.probe().await, Some(true));
+        assert_eq!(prober.probe().await, Some(true));
+        assert_eq!(prober.probe().await, Some(false));
+    }
+
+    #[tokio::test]
+    async fn not_serving_without_reader() {
+        let prober = Prober::new(ReaderLink::fixed(None), HealthOptions::default());
+        assert_eq!(prober.probe().await, Some(false));
+    }
+
+    #[tokio::test]
+    async fn busy_reader_keeps_status() {
+        let link = ReaderLink::fixed(Some(Box::new(MockReaderTraits::new())));
+        let prober = Prober::new(
+            link.clone(),
+            HealthOptions {
+                probe_interval: Duration::from_millis(10),
+            },
+        );
+        let _turn = link.acquire(Priority::Normal, None).await.unwrap();
+        assert_eq!(prober.probe().await, None);
+    }
+
+    #[tokio::test]
+    async fn watch_follows_probes() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+        let prober = Prober::new(
+            ReaderLink::fixed(Some(Box::new(reader))),
+            HealthOptions {
+                probe_interval: Duration::from_millis(10),
+            },
+        );
+        let service = prober.service();
+        let mut updates = service
+            .watch(check("rfid.ReadInfo"))
+            .await
+            .unwrap()
+            .into_inner();
+        tokio::spawn(prober.run());
+
+        let first = updates.recv().await.unwrap().unwrap();
+        assert_eq!(first.status, ServingStatus::NotServing as i32);
+        let second = updates.recv().await.unwrap().unwrap();
+        assert_eq!(second.status, ServingStatus::Serving as i32);
+        assert_eq!(
+            status(service.check(check("")).await),
+            ServingStatus::Serving as i32
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_service() {
+        let prober = Prober::new(ReaderLink::fixed(None), HealthOptions::default());
+        let service = prober.service();
+        let res = service.check(check("rfid.Unknown")).await;
+        assert_eq!(res.unwrap_err().code(), tonic::Code::NotFound);
+
+        let mut updates = service
+            .watch(check("rfid.Unknown"))
+            .await
+            .unwrap()
+            .into_inner();
+        let only = updates.recv().await.unwrap().unwrap();
+        assert_eq!(only.status, ServingStatus::ServiceUnknown as i32);
+        assert!(updates.recv().await.is_none());
+    }
+}
@@ -1 +1,14 @@
 tonic::include_proto!("rfid");
+
+pub mod health {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+// the variant names are the standard's
+#[allow(clippy::enum_variant_names)]
+pub mod reflection {
+    tonic::include_proto!("grpc.reflection.v1alpha");
+}
+
+// every file of the protos above, for reflection
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/descriptors.bin"));
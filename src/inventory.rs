@@ -0,0 +1,241 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::RecvError;
+
+use crate::events::{Event, EventLog, Operation};
+use crate::metrics;
+use crate::queue::err::QueueError;
+use crate::queue::Priority;
+use crate::reader::err::ReaderError;
+use crate::supervisor::ReaderLink;
+
+pub const DEFAULT_POLL_INTERVAL_MS: u64 = 100;
+pub const DEFAULT_BUFFER: usize = 64;
+
+// client recorded in the event log for reads of the loop
+pub const INVENTORY_CLIENT: &str = "inventory";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InventoryOptions {
+    pub poll_interval: Duration,
+    // reads kept for a subscriber that falls behind before it misses some
+    pub buffer: usize,
+}
+
+impl Default for InventoryOptions {
+    fn default() -> InventoryOptions {
+        InventoryOptions {
+            poll_interval: Duration::from_millis(DEFAULT_POLL_INTERVAL_MS),
+            buffer: DEFAULT_BUFFER,
+        }
+    }
+}
+
+/* the outcome of one poll, uid is none when no tag answered or no reader is connected */
+#[derive(Debug, Clone, PartialEq)]
+pub struct InventoryRead {
+    pub sequence: u64,
+    pub uid: Option<String>,
+    pub rssi: Option<u8>,
+    pub at: SystemTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InventoryEvent {
+    Read(InventoryRead),
+    // reads dropped because the subscriber fell behind
+    Gap(u64),
+}
+
+pub struct Subscription {
+    reads: broadcast::Receiver<InventoryRead>,
+}
+
+impl Subscription {
+    /* none once the hub is gone */
+    pub async fn next(&mut self) -> Option<InventoryEvent> {
+        match self.reads.recv().await {
+            Ok(read) => Some(InventoryEvent::Read(read)),
+            Err(RecvError::Lagged(missed)) => Some(InventoryEvent::Gap(missed)),
+            Err(RecvError::Closed) => None,
+        }
+    }
+}
+
+/* one polling loop shared by every subscriber, so watching clients do not add RF
+traffic. the loop only runs while someone is subscribed and never waits for a slow
+subscriber, those miss reads instead */
+#[derive(Clone)]
+pub struct InventoryHub {
+    link: ReaderLink,
+    options: InventoryOptions,
+    reads: broadcast::Sender<InventoryRead>,
+    // every tag the loop sees is recorded here
+    events: Option<EventLog>,
+    running: Arc<Mutex<bool>>,
+    sequence: Arc<AtomicU64>,
+}
+
+impl InventoryHub {
+    pub fn new(
+        link: ReaderLink,
+        options: InventoryOptions,
+        events: Option<EventLog>,
+    ) -> InventoryHub {
+        let (reads, _) = broadcast::channel(options.buffer);
+        InventoryHub {
+            link,
+            options,
+            reads,
+            events,
+            running: Arc::new(Mutex::new(false)),
+            sequence: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        // subscribed before the check, so a loop about to stop sees the new receiver
+        let reads = self.reads.subscribe();
+        let mut running = self.running.lock().unwrap();
+        if !*running {
+            *running = true;
+            tokio::spawn(self.clone().run());
+        }
+        Subscription { reads }
+    }
+
+    #[cfg(test)]
+    pub fn is_running(&self) -> bool {
+        *self.running.lock().unwrap()
+    }
+
+    async fn run(self) {
+        log::info!("{}", "Inventory loop started");
+        while self.has_subscribers() {
+            match self.poll().await {
+                Ok(seen) => {
+                    if seen.is_some() {
+                        metrics::tag_seen();
+                    }
+                    if let (Some(events), Some((uid, rssi))) = (&self.events, &seen) {
+                        let event = Event::new(Operation::Inventory, uid, INVENTORY_CLIENT);
+                        events.record(event.with_rssi(*rssi));
+                    }
+                    let read = InventoryRead {
+                        sequence: self.sequence.fetch_add(1, Ordering::Relaxed) + 1,
+                        rssi: seen.as_ref().map(|(_, rssi)| *rssi),
+                        uid: seen.map(|(uid, _)| uid),
+                        at: SystemTime::now(),
+                    };
+                    // everybody left, checked before the next poll
+                    let _ = self.reads.send(read);
+                }
+                Err(e) => log::warn!("Inventory poll skipped: {}", e),
+            }
+            tokio::time::delay_for(self.options.poll_interval).await;
+        }
+        log::info!("{}", "Inventory loop stopped, no subscribers left");
+    }
+
+    fn has_subscribers(&self) -> bool {
+        let mut running = self.running.lock().unwrap();
+        *running = self.reads.receiver_count() > 0;
+        *running
+    }
+
+    /* low priority, single reads from clients go first */
+    async fn poll(&self) -> Result<Option<(String, u8)>, QueueError> {
+        let mut turn = self.link.acquire(Priority::Low, None).await?;
+        let reader = match turn.as_mut() {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+
+        match reader.read_uuid_rssi().await {
+            Ok(seen) => Ok(Some(seen)),
+            Err(ReaderError::NoMatchingTargets(_)) => Ok(None),
+            Err(e) => {
+                log::warn!("Inventory read failed: {}", e);
+                self.link.check_lost(&mut turn, &e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::MockReaderTraits;
+
+    const UID: &str = "CAFEDEADBEEFB0B0";
+
+    fn hub(buffer: usize) -> InventoryHub {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid_rssi()
+            .returning(|| Ok((String::from(UID), 0x40)));
+        let options = InventoryOptions {
+            poll_interval: Duration::from_millis(10),
+            buffer,
+        };
+        InventoryHub::new(ReaderLink::fixed(Some(Box::new(reader))), options, None)
+    }
+
+    fn sequence(event: Option<InventoryEvent>) -> u64 {
+        match event {
+            Some(InventoryEvent::Read(read)) => {
+                assert_eq!(read.uid.as_deref(), Some(UID));
+                assert_eq!(read.rssi, Some(0x40));
+                read.sequence
+            }
+            _ => panic!("{}", "Should have been a read"),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_share_reads() {
+        let hub = hub(DEFAULT_BUFFER);
+        let mut first = hub.subscribe();
+        let mut second = hub.subscribe();
+
+        let seq = sequence(first.next().await);
+        assert_eq!(sequence(second.next().await), seq);
+        assert_eq!(sequence(first.next().await), seq + 1);
+        assert_eq!(sequence(second.next().await), seq + 1);
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber_gets_gap() {
+        let hub = hub(2);
+        let mut slow = hub.subscribe();
+        let mut fast = hub.subscribe();
+
+        for _ in 0..6 {
+            sequence(fast.next().await);
+        }
+        match slow.next().await {
+            Some(InventoryEvent::Gap(missed)) => assert!(missed >= 4),
+            _ => panic!("{}", "Should have been a gap"),
+        }
+        sequence(slow.next().await);
+    }
+
+    #[tokio::test]
+    async fn loop_stops_without_subscribers() {
+        let hub = hub(DEFAULT_BUFFER);
+        let mut sub = hub.subscribe();
+        let seq = sequence(sub.next().await);
+        assert!(hub.is_running());
+
+        drop(sub);
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        assert!(!hub.is_running());
+
+        // picks up where it left off
+        let mut sub = hub.subscribe();
+        assert!(sequence(sub.next().await) > seq);
+    }
+}
@@ -0,0 +1,286 @@
+use futures::lock::{Mutex as AsyncMutex, OwnedMutexGuard};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+pub mod err;
+
+use crate::queue::{Priority, ReaderQueue, ReaderTurn};
+use crate::reader::ReaderTraits;
+use err::LeaseError;
+
+pub const DEFAULT_TTL_MS: u64 = 10000;
+pub const MAX_TTL_MS: u64 = 60000;
+
+const TOKEN_CHARS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaseInfo {
+    pub token: String,
+    pub ttl: Duration,
+    pub expires: SystemTime,
+}
+
+struct Held {
+    token: String,
+    ttl: Duration,
+    expires: Instant,
+    // shared with the calls running inside the lease
+    turn: Arc<AsyncMutex<ReaderTurn>>,
+}
+
+impl Held {
+    fn is_valid(&self, token: &str) -> bool {
+        self.token == token && self.expires > Instant::now()
+    }
+
+    fn info(&self) -> LeaseInfo {
+        let left = self.expires.saturating_duration_since(Instant::now());
+        LeaseInfo {
+            token: self.token.clone(),
+            ttl: self.ttl,
+            expires: SystemTime::now() + left,
+        }
+    }
+}
+
+/* the reader as seen by one call, either through the queue or inside a lease */
+pub enum ReaderAccess {
+    Queued(ReaderTurn),
+    Leased(OwnedMutexGuard<ReaderTurn>),
+}
+
+impl Deref for ReaderAccess {
+    type Target = Option<Box<dyn ReaderTraits>>;
+
+    fn deref(&self) -> &Self::Target {
+        match *self {
+            ReaderAccess::Queued(ref turn) => turn,
+            ReaderAccess::Leased(ref guard) => guard,
+        }
+    }
+}
+
+impl DerefMut for ReaderAccess {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match *self {
+            ReaderAccess::Queued(ref mut turn) => turn,
+            ReaderAccess::Leased(ref mut guard) => guard,
+        }
+    }
+}
+
+/* at most one lease exists, its holder keeps the reader's turn until it releases it or
+stops renewing it */
+#[derive(Clone)]
+pub struct Leases {
+    queue: ReaderQueue,
+    held: Arc<Mutex<Option<Held>>>,
+}
+
+impl Leases {
+    pub fn new(queue: ReaderQueue) -> Leases {
+        Leases {
+            queue,
+            held: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /* zero picks the default */
+    pub fn ttl(ms: u64) -> Result<Duration, LeaseError> {
+        match ms {
+            0 => Ok(Duration::from_millis(DEFAULT_TTL_MS)),
+            ms if ms > MAX_TTL_MS => Err(LeaseError::InvalidTtl(ms)),
+            ms => Ok(Duration::from_millis(ms)),
+        }
+    }
+
+    /* waits in the queue like any other call, then keeps the turn */
+    pub async fn acquire(
+        &self,
+        ttl: Duration,
+        deadline: Option<Duration>,
+    ) -> Result<LeaseInfo, LeaseError> {
+        let turn = self.queue.acquire(Priority::High, deadline).await?;
+        let token: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_CHARS)
+            .map(char::from)
+            .collect();
+
+        let held = Held {
+            token: token.clone(),
+            ttl,
+            expires: Instant::now() + ttl,
+            turn: Arc::new(AsyncMutex::new(turn)),
+        };
+        let info = held.info();
+        *self.held.lock().unwrap() = Some(held);
+
+        log::info!("Lease granted for {:?}", ttl);
+        tokio::spawn(self.clone().expire(token));
+        Ok(info)
+    }
+
+    pub fn renew(&self, token: &str, ttl: Duration) -> Result<LeaseInfo, LeaseError> {
+        let mut held = self.held.lock().unwrap();
+        match held.as_mut() {
+            Some(h) if h.is_valid(token) => {
+                h.ttl = ttl;
+                h.expires = Instant::now() + ttl;
+                Ok(h.info())
+            }
+            _ => Err(LeaseError::UnknownLease),
+        }
+    }
+
+    /* calls still running inside the lease finish before the next turn starts */
+    pub fn release(&self, token: &str) -> Result<(), LeaseError> {
+        let mut held = self.held.lock().unwrap();
+        match *held {
+            Some(ref h) if h.token == token => {
+                *held = None;
+                log::info!("{}", "Lease released");
+                Ok(())
+            }
+            _ => Err(LeaseError::UnknownLease),
+        }
+    }
+
+    /* the reader for a call made inside the lease, one call at a time */
+    pub async fn access(&self, token: &str) -> Result<ReaderAccess, LeaseError> {
+        let turn = match *self.held.lock().unwrap() {
+            Some(ref h) if h.is_valid(token) => h.turn.clone(),
+            _ => return Err(LeaseError::UnknownLease),
+        };
+        Ok(ReaderAccess::Leased(turn.lock_owned().await))
+    }
+
+    /* drops the lease once it runs out without being renewed */
+    async fn expire(self, token: String) {
+        while let Some(expires) = self.expiry(&token) {
+            tokio::time::delay_until(tokio::time::Instant::from_std(expires)).await;
+            if self.drop_expired(&token) {
+                log::warn!("{}", "Lease expired, the reader is free again");
+                return;
+            }
+        }
+    }
+
+    fn expiry(&self, token: &str) -> Option<Instant> {
+        match *self.held.lock().unwrap() {
+            Some(ref h) if h.token == token => Some(h.expires),
+            _ => None,
+        }
+    }
+
+    fn drop_expired(&self, token: &str) -> bool {
+        let mut held = self.held.lock().unwrap();
+        let expired = match *held {
+            Some(ref h) => h.token == token && h.expires <= Instant::now(),
+            None => false,
+        };
+        if expired {
+            *held = None;
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::queue::err::QueueError;
+    use crate::queue::QueueOptions;
+
+    fn leases() -> (Leases, ReaderQueue) {
+        let slot = Arc::new(AsyncMutex::new(None));
+        let queue = ReaderQueue::new(slot, QueueOptions::default());
+        (Leases::new(queue.clone()), queue)
+    }
+
+    async fn queue_free(queue: &ReaderQueue) -> bool {
+        queue
+            .acquire(Priority::Normal, Some(Duration::from_millis(20)))
+            .await
+            .is_ok()
+    }
+
+    #[test]
+    fn ttl_limits() {
+        assert_eq!(
+            Leases::ttl(0).unwrap(),
+            Duration::from_millis(DEFAULT_TTL_MS)
+        );
+        assert_eq!(Leases::ttl(500).unwrap(), Duration::from_millis(500));
+        assert!(Leases::ttl(MAX_TTL_MS + 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn lease_holds_the_queue() {
+        let (leases, queue) = leases();
+        let lease = leases.acquire(Duration::from_secs(5), None).await.unwrap();
+
+        match queue
+            .acquire(Priority::High, Some(Duration::from_millis(20)))
+            .await
+        {
+            Err(QueueError::DeadlineExceeded(_)) => {}
+            _ => panic!("{}", "Should have been DeadlineExceeded"),
+        }
+        assert!(leases.access(&lease.token).await.is_ok());
+
+        leases.release(&lease.token).unwrap();
+        assert!(queue_free(&queue).await);
+    }
+
+    #[tokio::test]
+    async fn wrong_token_rejected() {
+        let (leases, _queue) = leases();
+        let lease = leases.acquire(Duration::from_secs(5), None).await.unwrap();
+        assert_eq!(lease.token.len(), TOKEN_CHARS);
+
+        for res in [
+            leases.access("nope").await.map(|_| ()),
+            leases.renew("nope", Duration::from_secs(1)).map(|_| ()),
+            leases.release("nope"),
+        ] {
+            match res {
+                Err(LeaseError::UnknownLease) => {}
+                _ => panic!("{}", "Should have been UnknownLease"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn expires_without_renewal() {
+        let (leases, queue) = leases();
+        let lease = leases
+            .acquire(Duration::from_millis(30), None)
+            .await
+            .unwrap();
+        tokio::time::delay_for(Duration::from_millis(60)).await;
+
+        assert!(leases.access(&lease.token).await.is_err());
+        assert!(queue_free(&queue).await);
+    }
+
+    #[tokio::test]
+    async fn renewal_extends() {
+        let (leases, queue) = leases();
+        let lease = leases
+            .acquire(Duration::from_millis(50), None)
+            .await
+            .unwrap();
+        tokio::time::delay_for(Duration::from_millis(30)).await;
+        leases
+            .renew(&lease.token, Duration::from_millis(200))
+            .unwrap();
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+
+        assert!(leases.access(&lease.token).await.is_ok());
+        assert!(!queue_free(&queue).await);
+    }
+}
@@ -0,0 +1,28 @@
+use std::fmt;
+use std::fmt::Debug;
+
+use crate::queue::err::QueueError;
+
+#[derive(Debug)]
+pub enum LeaseError {
+    Queue(QueueError),
+    // never issued, released or expired
+    UnknownLease,
+    InvalidTtl(u64),
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LeaseError::Queue(ref e) => std::fmt::Display::fmt(&e, f),
+            LeaseError::UnknownLease => write!(f, "Lease unknown or expired"),
+            LeaseError::InvalidTtl(ms) => write!(f, "Invalid lease ttl: {} ms", ms),
+        }
+    }
+}
+
+impl From<QueueError> for LeaseError {
+    fn from(err: QueueError) -> LeaseError {
+        LeaseError::Queue(err)
+    }
+}
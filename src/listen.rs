@@ -0,0 +1,111 @@
+use futures::stream::{Stream, TryStreamExt};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{UnixListener, UnixStream};
+use tonic::transport::server::Connected;
+
+pub const DEFAULT_LISTEN: &str = "[::]:50051";
+
+/* a local client, it has no address the server could report */
+pub struct UnixConnection(UnixStream);
+
+impl Connected for UnixConnection {}
+
+impl AsyncRead for UnixConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UnixConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/* listens on a unix domain socket, replacing one left behind by an earlier run */
+pub fn bind_unix(path: &Path) -> io::Result<impl Stream<Item = io::Result<UnixConnection>>> {
+    match fs::remove_file(path) {
+        Ok(()) => log::info!("Removed stale socket {}", path.display()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    Ok(listener.map_ok(UnixConnection))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::include::read_info_client::ReadInfoClient;
+    use crate::include::read_info_server::ReadInfoServer;
+    use crate::include::Empty;
+    use crate::reader::MockReaderTraits;
+    use crate::rfid::Rfid;
+    use crate::scaffold::scaffold::temp_dir;
+    use futures_util::FutureExt;
+    use std::convert::TryFrom;
+    use tokio::sync::oneshot;
+    use tonic::transport::{Endpoint, Server, Uri};
+    use tonic::Request;
+
+    #[tokio::test]
+    async fn serves_unix_socket() {
+        let dir = temp_dir();
+        let path = dir.join("rfid.sock");
+        // left behind by a crashed server
+        fs::write(&path, "").unwrap();
+
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+        let incoming = bind_unix(&path).unwrap();
+        let (stop, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            Server::builder()
+                .add_service(ReadInfoServer::new(Rfid::new(Box::new(reader))))
+                .serve_with_incoming_shutdown(incoming, rx.map(drop))
+                .await
+                .unwrap();
+        });
+
+        // the uri is not used, every connection goes to the socket
+        let socket = path.clone();
+        let channel = Endpoint::try_from("http://[::]:50051")
+            .unwrap()
+            .connect_with_connector(tower::service_fn(move |_: Uri| {
+                UnixStream::connect(socket.clone())
+            }))
+            .await
+            .unwrap();
+        let res = ReadInfoClient::new(channel)
+            .read_uuid(Request::new(Empty {}))
+            .await;
+        stop.send(()).unwrap();
+        server.await.unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(res.unwrap().into_inner().info, "CAFEDEADBEEFB0B0");
+    }
+}
@@ -0,0 +1,250 @@
+use futures::future::BoxFuture;
+use rand::{thread_rng, Rng};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tonic::codegen::{http, Context, Poll, Service};
+use tonic::transport::NamedService;
+use tonic::Request;
+use tracing::{field, Instrument, Span};
+use tracing_subscriber::EnvFilter;
+
+pub const DEFAULT_LEVEL: &str = "info";
+// taken from the client when it sends one, so its logs and ours line up
+pub const REQUEST_ID_METADATA: &str = "x-request-id";
+
+const REDACTED: &str = "<redacted>";
+
+static REDACT_TAGS: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoggingOptions {
+    // a filter such as "info" or "info,rfid_trf7970_grpc::serial=debug"
+    pub level: String,
+    pub format: LogFormat,
+    // keeps uids and block data out of the logs
+    pub redact_tags: bool,
+}
+
+impl Default for LoggingOptions {
+    fn default() -> LoggingOptions {
+        LoggingOptions {
+            level: String::from(DEFAULT_LEVEL),
+            format: LogFormat::Text,
+            redact_tags: false,
+        }
+    }
+}
+
+pub fn is_valid_level(level: &str) -> bool {
+    EnvFilter::try_new(level).is_ok()
+}
+
+/* the log macros used across the crate end up here as well */
+pub fn init(options: &LoggingOptions) {
+    REDACT_TAGS.store(options.redact_tags, Ordering::Relaxed);
+    let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(&options.level));
+    let res = match options.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .try_init(),
+    };
+    if let Err(e) = res {
+        eprintln!("Logging not set up: {}", e);
+    }
+}
+
+/* tag data as it may be logged */
+pub fn tag_data(data: &str) -> &str {
+    if REDACT_TAGS.load(Ordering::Relaxed) {
+        REDACTED
+    } else {
+        data
+    }
+}
+
+/* the setting is shared by every test in the process, so tests that depend on it take
+turns. it is turned off again once the guard is dropped */
+#[cfg(test)]
+pub struct Redaction {
+    _turn: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+pub fn redaction(redact: bool) -> Redaction {
+    static TURN: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let turn = TURN.lock().unwrap_or_else(|e| e.into_inner());
+    REDACT_TAGS.store(redact, Ordering::Relaxed);
+    Redaction { _turn: turn }
+}
+
+#[cfg(test)]
+impl Drop for Redaction {
+    fn drop(&mut self) {
+        REDACT_TAGS.store(false, Ordering::Relaxed);
+    }
+}
+
+fn request_id(headers: &http::HeaderMap) -> String {
+    match headers
+        .get(REQUEST_ID_METADATA)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(id) if !id.is_empty() => String::from(id),
+        _ => format!("{:016x}", thread_rng().gen::<u64>()),
+    }
+}
+
+/* the span of the call only learns the peer once tonic made a request of it */
+pub fn record_peer<T>(request: &Request<T>) {
+    if let Some(addr) = request.remote_addr() {
+        Span::current().record("peer", field::display(addr));
+    }
+}
+
+/* runs every call to the service it wraps in a span of its own, tagged with a request id
+that is handed back to the client */
+#[derive(Debug, Clone)]
+pub struct Traced<S> {
+    inner: S,
+}
+
+impl<S> Traced<S> {
+    pub fn new(inner: S) -> Traced<S> {
+        Traced { inner }
+    }
+}
+
+impl<S: NamedService> NamedService for Traced<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B, R> Service<http::Request<B>> for Traced<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<R>>,
+    S::Future: Send + 'static,
+    R: 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        let id = request_id(req.headers());
+        let span = tracing::info_span!(
+            "rpc",
+            request_id = %id,
+            method = req.uri().path().rsplit('/').next().unwrap_or_default(),
+            peer = field::Empty,
+        );
+        let value = http::HeaderValue::from_str(&id).ok();
+        if let Some(ref value) = value {
+            req.headers_mut().insert(REQUEST_ID_METADATA, value.clone());
+        }
+
+        let res = span.in_scope(|| self.inner.call(req));
+        Box::pin(
+            async move {
+                let mut res = res.await?;
+                tracing::debug!("{}", "Answered");
+                if let Some(value) = value {
+                    res.headers_mut().insert(REQUEST_ID_METADATA, value);
+                }
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scaffold::scaffold::Captured;
+
+    fn call(id: Option<&str>) -> http::Request<()> {
+        let mut req = http::Request::builder().uri("/rfid.ReadInfo/ReadUuid");
+        if let Some(id) = id {
+            req = req.header(REQUEST_ID_METADATA, id);
+        }
+        req.body(()).unwrap()
+    }
+
+    /* answers every call, logging the request id it was handed */
+    struct Echo;
+
+    impl Service<http::Request<()>> for Echo {
+        type Response = http::Response<()>;
+        type Error = ();
+        type Future = BoxFuture<'static, Result<http::Response<()>, ()>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<()>) -> Self::Future {
+            tracing::info!("handled {:?}", req.headers().get(REQUEST_ID_METADATA));
+            Box::pin(async { Ok(http::Response::new(())) })
+        }
+    }
+
+    fn echo() -> Traced<Echo> {
+        Traced::new(Echo)
+    }
+
+    #[tokio::test]
+    async fn request_ids() {
+        let res = echo().call(call(Some("from-client"))).await.unwrap();
+        assert_eq!(res.headers()[REQUEST_ID_METADATA], "from-client");
+
+        let first = echo().call(call(None)).await.unwrap();
+        let second = echo().call(call(None)).await.unwrap();
+        assert_eq!(first.headers()[REQUEST_ID_METADATA].len(), 16);
+        assert_ne!(
+            first.headers()[REQUEST_ID_METADATA],
+            second.headers()[REQUEST_ID_METADATA]
+        );
+    }
+
+    #[tokio::test]
+    async fn events_carry_the_call() {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_current_span(true)
+            .with_writer(captured.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        echo().call(call(Some("abc123"))).await.unwrap();
+        let text = captured.text();
+        assert!(text.contains("\"request_id\":\"abc123\""));
+        assert!(text.contains("\"method\":\"ReadUuid\""));
+        assert!(text.contains("handled Some(\\\"abc123\\\")"));
+    }
+
+    #[test]
+    fn redacts_tag_data() {
+        {
+            let _redaction = redaction(false);
+            assert_eq!(tag_data("E0040150D2F3A4B5"), "E0040150D2F3A4B5");
+        }
+        {
+            let _redaction = redaction(true);
+            assert_eq!(tag_data("E0040150D2F3A4B5"), REDACTED);
+        }
+        assert!(is_valid_level("info,rfid_trf7970_grpc::serial=debug"));
+        assert!(!is_valid_level("info,serial=loud"));
+    }
+}
@@ -1,30 +1,238 @@
+mod auth;
+mod config;
+mod dedup;
+mod diagnostics;
+mod events;
+mod flow;
+mod health;
 mod include;
+mod inventory;
+mod lease;
+mod listen;
+mod logging;
+mod metrics;
+mod presence;
+mod queue;
 mod reader;
+mod reflection;
 mod rfid;
 mod scaffold;
 mod serial;
+mod supervisor;
+mod tls;
 
+use futures::future::{self, try_join_all};
+use health::Prober;
+use include::health::health_server::HealthServer;
 use include::read_info_server::ReadInfoServer;
+use include::reflection::server_reflection_server::ServerReflectionServer;
+use logging::Traced;
+use metrics::Metered;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
 use tonic::transport::Server;
 
-use reader::Reader;
-use rfid::Rfid;
-use serial::low::SerialCrate;
+use auth::err::AuthError;
+use auth::{Authorize, Policy};
+use config::err::ConfigError;
+use config::Config;
+use events::err::EventError;
+use events::EventLog;
+use reader::err::ReaderError;
+use reader::rf::ReaderProfile;
+use reader::{Reader, ReaderTraits};
+use reflection::Reflection;
+use rfid::{Rfid, RfidOptions};
+use serial::capture::{Replay, Source};
+use serial::err::SerialError;
+use serial::options::SerialOptions;
+use serial::sim::Simulator;
 use serial::RfidSerial;
+use supervisor::{Connector, Supervisor};
+use tls::err::TlsError;
+use tls::Tls;
 
-#[tokio::main]
-async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let addr = "[::]:50051".parse().unwrap();
-    let sc = SerialCrate::new();
+const EXIT_SERVER: i32 = 1;
+const EXIT_CONFIG: i32 = 2;
+const EXIT_NO_READER: i32 = 3;
+const EXIT_EVENTS: i32 = 4;
+const EXIT_TLS: i32 = 5;
+const EXIT_AUTH: i32 = 6;
+
+type ServerFuture = Pin<Box<dyn Future<Output = Result<(), tonic::transport::Error>>>>;
+
+enum StartupError {
+    Config(ConfigError),
+    NoReader(ReaderError),
+    Events(EventError),
+    Tls(TlsError),
+    Auth(AuthError),
+    Capture(std::io::Error),
+    Listen(std::io::Error),
+    Metrics(hyper::Error),
+    Server(tonic::transport::Error),
+}
 
-    let serial = RfidSerial::new(Box::new(sc));
-    let reader = Reader::new(Box::new(serial));
-    let rfid = Rfid::new(Box::new(reader));
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StartupError::Config(ref e) => write!(f, "{}", e),
+            StartupError::NoReader(ref e) => write!(f, "Unable to connect to reader: {}", e),
+            StartupError::Events(ref e) => write!(f, "Unable to open event log: {}", e),
+            StartupError::Tls(ref e) => write!(f, "{}", e),
+            StartupError::Auth(ref e) => write!(f, "{}", e),
+            StartupError::Capture(ref e) => write!(f, "Unable to load serial capture: {}", e),
+            StartupError::Listen(ref e) => write!(f, "Unable to listen: {}", e),
+            StartupError::Metrics(ref e) => write!(f, "Metrics server error: {}", e),
+            StartupError::Server(ref e) => write!(f, "Server error: {}", e),
+        }
+    }
+}
+
+impl StartupError {
+    fn exit_code(&self) -> i32 {
+        match *self {
+            StartupError::Config(_) => EXIT_CONFIG,
+            StartupError::NoReader(_) => EXIT_NO_READER,
+            StartupError::Events(_) => EXIT_EVENTS,
+            StartupError::Tls(_) => EXIT_TLS,
+            StartupError::Auth(_) => EXIT_AUTH,
+            StartupError::Capture(_) => EXIT_CONFIG,
+            StartupError::Listen(_) => EXIT_SERVER,
+            StartupError::Metrics(_) => EXIT_SERVER,
+            StartupError::Server(_) => EXIT_SERVER,
+        }
+    }
+}
 
-    Server::builder()
-        .add_service(ReadInfoServer::new(rfid))
-        .serve(addr)
-        .await?;
+async fn connect(
+    source: Source,
+    options: SerialOptions,
+    profile: ReaderProfile,
+) -> Result<Box<dyn ReaderTraits>, ReaderError> {
+    let lib = source.lib().map_err(SerialError::from)?;
+    let serial = RfidSerial::try_new(lib, &options).await?;
+    let mut reader = Reader::try_new(Box::new(serial)).await?;
+    // written again on every reconnect, the reader forgets it when powered off
+    profile.apply(&mut reader).await?;
+    Ok(Box::new(reader))
+}
 
-    Ok(())
+async fn run() -> Result<(), StartupError> {
+    let config = Config::load().map_err(StartupError::Config)?;
+    logging::init(&config.logging.options().map_err(StartupError::Config)?);
+    let options = config.serial.options().map_err(StartupError::Config)?;
+    let queue = config.queue.options().map_err(StartupError::Config)?;
+    let inventory = config.inventory.options().map_err(StartupError::Config)?;
+    let health = config.health.options().map_err(StartupError::Config)?;
+    let profile = config.reader.profile().map_err(StartupError::Config)?;
+    let service = RfidOptions {
+        presence: config
+            .presence
+            .options(&inventory)
+            .map_err(StartupError::Config)?,
+        flow: config.stream.options().map_err(StartupError::Config)?,
+        dedup: config.dedup.options().map_err(StartupError::Config)?,
+        inventory,
+        profile,
+    };
+    let events = match config.events.options() {
+        Some(ref options) => Some(EventLog::open(options).map_err(StartupError::Events)?),
+        None => None,
+    };
+    let tls = match config.tls.options().map_err(StartupError::Config)? {
+        Some(ref options) => Some(Tls::load(options).map_err(StartupError::Tls)?),
+        None => None,
+    };
+    let policy = match config.auth.policy {
+        Some(ref path) => Some(Policy::load(path).map_err(StartupError::Auth)?),
+        None => None,
+    };
+    let source = match config.serial.replay {
+        Some(ref path) => Source::Replayed(Replay::load(path).map_err(StartupError::Capture)?),
+        None => match config.simulator.tags().map_err(StartupError::Config)? {
+            Some(tags) => Source::Simulated(Simulator::new(tags)),
+            None => Source::Port,
+        },
+    };
+    let source = match config.serial.capture {
+        Some(ref path) => Source::Recorded(Box::new(source), path.clone()),
+        None => source,
+    };
+    let initial = connect(source.clone(), options.clone(), profile).await;
+    match initial {
+        Err(ref e) if config.reader.allow_missing => log::warn!("Starting without reader: {}", e),
+        Err(e) => return Err(StartupError::NoReader(e)),
+        Ok(_) => {}
+    }
+
+    let connector: Connector =
+        Arc::new(move || Box::pin(connect(source.clone(), options.clone(), profile)));
+    let interval = Duration::from_millis(config.reader.connect_interval_ms);
+    let supervisor = Supervisor::new(initial, connector, interval, queue);
+    let rfid = Rfid::supervised(supervisor.link(), service, events);
+    let prober = Prober::new(supervisor.link(), health);
+    let health = HealthServer::new(prober.service());
+    tokio::spawn(supervisor.run());
+    tokio::spawn(prober.run());
+    // written by the build, it always decodes
+    let reflection =
+        ServerReflectionServer::new(Reflection::new(include::FILE_DESCRIPTOR_SET).unwrap());
+
+    // metered outside the policy check, so refused calls are counted too, and all of it
+    // inside the span of the call
+    let service = Traced::new(Metered::new(Authorize::new(
+        ReadInfoServer::with_interceptor(rfid, auth::interceptor(policy)),
+    )));
+    let addr = config.server.listen;
+    // probes and discovery need no credentials, only the reader calls are checked
+    let router = || {
+        Server::builder()
+            .add_service(health.clone())
+            .add_service(reflection.clone())
+            .add_service(service.clone())
+    };
+    let server = router();
+    let mut servers: Vec<ServerFuture> = vec![match tls {
+        Some(tls) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(StartupError::Listen)?;
+            tokio::spawn(tls.clone().watch());
+            Box::pin(server.serve_with_incoming(tls.incoming(listener)))
+        }
+        None => Box::pin(server.serve(addr)),
+    }];
+    // local clients only, so tls is left to the socket permissions
+    if let Some(ref path) = config.server.unix_socket {
+        let incoming = listen::bind_unix(path).map_err(StartupError::Listen)?;
+        let server = router();
+        servers.push(Box::pin(server.serve_with_incoming(incoming)));
+    }
+
+    let scraped: Pin<Box<dyn Future<Output = Result<(), hyper::Error>>>> =
+        match config.metrics.listen {
+            Some(addr) => Box::pin(metrics::serve(
+                addr,
+                metrics::registry(&config.metrics.reader_id),
+            )),
+            None => Box::pin(future::pending()),
+        };
+
+    tokio::select! {
+        res = try_join_all(servers) => res.map(drop).map_err(StartupError::Server),
+        res = scraped => res.map_err(StartupError::Metrics),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("rfid-trf7970-grpc: {}", e);
+        std::process::exit(e.exit_code());
+    }
 }
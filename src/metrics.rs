@@ -0,0 +1,480 @@
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, StatusCode};
+use lazy_static::lazy_static;
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use prost::Message;
+use prost_types::FileDescriptorSet;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tonic::body::BoxBody;
+use tonic::codegen::{http, Context, Poll, Service};
+use tonic::transport::NamedService;
+use tonic::{Code, Status};
+
+use crate::include::FILE_DESCRIPTOR_SET;
+use crate::reader::err::ReaderError;
+
+pub const DEFAULT_READER_ID: &str = "reader";
+pub const METRICS_PATH: &str = "/metrics";
+
+const GRPC_STATUS: &str = "grpc-status";
+// the label of calls to methods the protos do not have, anyone can make those up
+const UNKNOWN_METHOD: &str = "unknown";
+
+lazy_static! {
+    static ref SERIAL_COMMANDS: IntCounterVec = IntCounterVec::new(
+        Opts::new("rfid_serial_commands_total", "Commands sent to the reader"),
+        &["outcome"]
+    )
+    .unwrap();
+    static ref SERIAL_SECONDS: Histogram = Histogram::with_opts(HistogramOpts::new(
+        "rfid_serial_command_seconds",
+        "Time from sending a command to its reply, retries included"
+    ))
+    .unwrap();
+    static ref SERIAL_RETRIES: IntCounter = IntCounter::new(
+        "rfid_serial_retries_total",
+        "Commands sent again after a failed attempt"
+    )
+    .unwrap();
+    static ref READER_ERRORS: IntCounterVec = IntCounterVec::new(
+        Opts::new("rfid_reader_errors_total", "Failed reader calls by kind of error"),
+        &["kind"]
+    )
+    .unwrap();
+    static ref READER_WAIT_SECONDS: Histogram = Histogram::with_opts(HistogramOpts::new(
+        "rfid_reader_wait_seconds",
+        "Time spent waiting for a turn on the reader"
+    ))
+    .unwrap();
+    static ref TAGS_SEEN: IntCounter = IntCounter::new(
+        "rfid_tags_seen_total",
+        "Tags read by calls and the inventory loop"
+    )
+    .unwrap();
+    static ref RPC_CALLS: IntCounterVec = IntCounterVec::new(
+        Opts::new("rfid_rpc_calls_total", "Finished calls by method and status code"),
+        &["method", "code"]
+    )
+    .unwrap();
+    static ref RPC_SECONDS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "rfid_rpc_duration_seconds",
+            "Call latency, for streaming calls how long the stream was open"
+        ),
+        &["method"]
+    )
+    .unwrap();
+    static ref ACTIVE_STREAMS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("rfid_active_streams", "Streaming calls in progress"),
+        &["method"]
+    )
+    .unwrap();
+    static ref PROTOS: FileDescriptorSet = FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).unwrap();
+    // every method of the protos, anything else is labelled as unknown
+    static ref METHODS: HashSet<String> = PROTOS
+        .file
+        .iter()
+        .flat_map(|f| f.service.iter())
+        .flat_map(|s| s.method.iter())
+        .map(|m| String::from(m.name()))
+        .collect();
+    // methods answering with a stream, from the protos
+    static ref STREAMING: HashSet<String> = PROTOS
+        .file
+        .iter()
+        .flat_map(|f| f.service.iter())
+        .flat_map(|s| s.method.iter())
+        .filter(|m| m.server_streaming())
+        .map(|m| String::from(m.name()))
+        .collect();
+}
+
+fn collectors() -> Vec<Box<dyn Collector>> {
+    vec![
+        Box::new(SERIAL_COMMANDS.clone()),
+        Box::new(SERIAL_SECONDS.clone()),
+        Box::new(SERIAL_RETRIES.clone()),
+        Box::new(READER_ERRORS.clone()),
+        Box::new(READER_WAIT_SECONDS.clone()),
+        Box::new(TAGS_SEEN.clone()),
+        Box::new(RPC_CALLS.clone()),
+        Box::new(RPC_SECONDS.clone()),
+        Box::new(ACTIVE_STREAMS.clone()),
+    ]
+}
+
+/* every metric, labelled with the reader the process serves */
+pub fn registry(reader_id: &str) -> Registry {
+    let mut labels = HashMap::new();
+    labels.insert(String::from("reader"), String::from(reader_id));
+    // the label name is valid and every metric is registered once
+    let registry = Registry::new_custom(None, Some(labels)).unwrap();
+    for collector in collectors() {
+        registry.register(collector).unwrap();
+    }
+    registry
+}
+
+pub fn serial_command(ok: bool, took: Duration) {
+    let outcome = if ok { "ok" } else { "error" };
+    SERIAL_COMMANDS.with_label_values(&[outcome]).inc();
+    SERIAL_SECONDS.observe(took.as_secs_f64());
+}
+
+pub fn serial_retry() {
+    SERIAL_RETRIES.inc();
+}
+
+pub fn reader_error(e: &ReaderError) {
+    READER_ERRORS.with_label_values(&[e.kind()]).inc();
+}
+
+pub fn reader_wait(waited: Duration) {
+    READER_WAIT_SECONDS.observe(waited.as_secs_f64());
+}
+
+pub fn tag_seen() {
+    TAGS_SEEN.inc();
+}
+
+fn encode(registry: &Registry) -> Vec<u8> {
+    let mut buf = Vec::new();
+    // writing into a vec does not fail
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut buf)
+        .unwrap();
+    buf
+}
+
+fn respond(registry: &Registry, req: &http::Request<Body>) -> http::Response<Body> {
+    let mut res = http::Response::new(Body::empty());
+    if req.method() != Method::GET || req.uri().path() != METRICS_PATH {
+        *res.status_mut() = StatusCode::NOT_FOUND;
+        return res;
+    }
+    *res.body_mut() = Body::from(encode(registry));
+    let format = TextEncoder::new().format_type().to_owned();
+    if let Ok(value) = http::HeaderValue::from_str(&format) {
+        res.headers_mut().insert(http::header::CONTENT_TYPE, value);
+    }
+    res
+}
+
+/* plain http for the scraper, apart from the grpc server */
+pub async fn serve(addr: SocketAddr, registry: Registry) -> Result<(), hyper::Error> {
+    let server = hyper::Server::try_bind(&addr)?;
+    log::info!("Serving metrics on {}{}", addr, METRICS_PATH);
+    let make = make_service_fn(move |_| {
+        let registry = registry.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let res = respond(&registry, &req);
+                async move { Ok::<_, Infallible>(res) }
+            }))
+        }
+    });
+    server.serve(make).await
+}
+
+/* one call, finished once its status is known */
+struct Call {
+    method: String,
+    streaming: bool,
+    started: Instant,
+}
+
+impl Call {
+    /* the method is taken from the path the client sent, so only known ones get a label
+    of their own */
+    fn start(path: &str) -> Call {
+        let method = path.rsplit('/').next().unwrap_or_default();
+        let method = if METHODS.contains(method) {
+            method
+        } else {
+            UNKNOWN_METHOD
+        };
+        let streaming = STREAMING.contains(method);
+        if streaming {
+            ACTIVE_STREAMS.with_label_values(&[method]).inc();
+        }
+        Call {
+            method: String::from(method),
+            streaming,
+            started: Instant::now(),
+        }
+    }
+
+    fn finish(self, code: Code) {
+        if self.streaming {
+            ACTIVE_STREAMS.with_label_values(&[&self.method]).dec();
+        }
+        RPC_SECONDS
+            .with_label_values(&[&self.method])
+            .observe(self.started.elapsed().as_secs_f64());
+        RPC_CALLS
+            .with_label_values(&[&self.method, &format!("{:?}", code)])
+            .inc();
+    }
+}
+
+fn grpc_code(headers: &http::HeaderMap) -> Option<Code> {
+    let value = headers.get(GRPC_STATUS)?.to_str().ok()?;
+    value.parse::<i32>().ok().map(Code::from)
+}
+
+/* the response body, the status comes with the trailers once everything was sent. a body
+dropped before that is a call the client gave up on */
+struct MeteredBody {
+    inner: BoxBody,
+    call: Option<Call>,
+}
+
+impl http_body::Body for MeteredBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        let res = futures::ready!(Pin::new(&mut self.inner).poll_trailers(cx));
+        if let Some(call) = self.call.take() {
+            let code = match res {
+                Ok(Some(ref trailers)) => grpc_code(trailers).unwrap_or(Code::Unknown),
+                Ok(None) => Code::Unknown,
+                Err(ref e) => e.code(),
+            };
+            call.finish(code);
+        }
+        Poll::Ready(res)
+    }
+}
+
+impl Drop for MeteredBody {
+    fn drop(&mut self) {
+        if let Some(call) = self.call.take() {
+            call.finish(Code::Cancelled);
+        }
+    }
+}
+
+/* counts and times every call to the service it wraps */
+#[derive(Debug, Clone)]
+pub struct Metered<S> {
+    inner: S,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S) -> Metered<S> {
+        Metered { inner }
+    }
+}
+
+impl<S: NamedService> NamedService for Metered<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B> Service<http::Request<B>> for Metered<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let call = Call::start(req.uri().path());
+        let res = self.inner.call(req);
+        Box::pin(async move {
+            let res = match res.await {
+                Ok(res) => res,
+                Err(e) => {
+                    call.finish(Code::Unknown);
+                    return Err(e);
+                }
+            };
+            // failed before sending anything, the status is in the headers
+            if let Some(code) = grpc_code(res.headers()) {
+                call.finish(code);
+                return Ok(res);
+            }
+            Ok(res.map(|inner| {
+                BoxBody::new(MeteredBody {
+                    inner,
+                    call: Some(call),
+                })
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::include::read_info_server::ReadInfoServer;
+    use crate::include::{Empty, StreamPayload};
+    use crate::reader::MockReaderTraits;
+    use crate::rfid::Rfid;
+    use crate::serial::err::SerialError;
+    use futures_util::FutureExt;
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+    use tonic::transport::Server;
+    use tonic::Request;
+
+    fn calls(method: &str, code: &str) -> u64 {
+        RPC_CALLS.with_label_values(&[method, code]).get()
+    }
+
+    // whatever the code
+    fn finished(method: &str) -> u64 {
+        RPC_CALLS.collect()[0]
+            .get_metric()
+            .iter()
+            .filter(|m| m.get_label().iter().any(|l| l.get_value() == method))
+            .map(|m| m.get_counter().get_value() as u64)
+            .sum()
+    }
+
+    #[test]
+    fn labelled_by_reader() {
+        serial_command(true, Duration::from_millis(20));
+        reader_error(&ReaderError::BlockIdxTooLarge(300));
+        let text = String::from_utf8(encode(&registry("dock-1"))).unwrap();
+
+        assert!(text.contains("rfid_serial_commands_total{outcome=\"ok\",reader=\"dock-1\"}"));
+        assert!(text.contains("rfid_serial_command_seconds_count{reader=\"dock-1\"}"));
+        assert!(text
+            .contains("rfid_reader_errors_total{kind=\"block_idx_too_large\",reader=\"dock-1\"}"));
+    }
+
+    #[test]
+    fn unknown_methods_share_a_label() {
+        let before = calls(UNKNOWN_METHOD, "Unimplemented");
+        for made_up in &["/rfid.ReadInfo/Made1", "/rfid.ReadInfo/Made2", "/x"] {
+            Call::start(made_up).finish(Code::Unimplemented);
+        }
+        Call::start("/rfid.ReadInfo/ReadUuid").finish(Code::Unimplemented);
+
+        assert_eq!(calls(UNKNOWN_METHOD, "Unimplemented"), before + 3);
+        assert_eq!(finished("Made1"), 0);
+        assert!(calls("ReadUuid", "Unimplemented") >= 1);
+    }
+
+    #[test]
+    fn streaming_methods() {
+        assert!(STREAMING.contains("WatchInventory"));
+        assert!(STREAMING.contains("ReadUuidContinous"));
+        assert!(!STREAMING.contains("ReadUuid"));
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_over_http() {
+        tag_seen();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        tokio::spawn(serve(addr, registry(DEFAULT_READER_ID)));
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+
+        let client = hyper::Client::new();
+        let uri: http::Uri = format!("http://{}{}", addr, METRICS_PATH).parse().unwrap();
+        let res = client.get(uri).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(String::from_utf8(body.to_vec())
+            .unwrap()
+            .contains("rfid_tags_seen_total{reader=\"reader\"}"));
+
+        let uri: http::Uri = format!("http://{}/other", addr).parse().unwrap();
+        let res = client.get(uri).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn counts_calls_by_code() {
+        let mut reader = MockReaderTraits::new();
+        let mut reads = 0;
+        reader.expect_read_uuid().returning(move || {
+            reads += 1;
+            match reads {
+                1 => Ok(String::from("CAFEDEADBEEFB0B0")),
+                _ => Err(ReaderError::SerialError(SerialError::IoError(
+                    std::io::Error::new(std::io::ErrorKind::TimedOut, "Timed out"),
+                ))),
+            }
+        });
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (stop, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            Server::builder()
+                .add_service(Metered::new(ReadInfoServer::new(Rfid::new(Box::new(
+                    reader,
+                )))))
+                .serve_with_incoming_shutdown(listener, rx.map(drop))
+                .await
+                .unwrap();
+        });
+
+        let ok = calls("ReadUuid", "Ok");
+        let internal = calls("ReadUuid", "Internal");
+        let streams = finished("ReadUuidContinous");
+        let mut client = crate::include::read_info_client::ReadInfoClient::connect(format!(
+            "http://127.0.0.1:{}",
+            port
+        ))
+        .await
+        .unwrap();
+        client.read_uuid(Request::new(Empty {})).await.unwrap();
+        client.read_uuid(Request::new(Empty {})).await.unwrap_err();
+
+        // a stream the client never acks, it ends one way or another
+        let requests = futures::stream::pending::<StreamPayload>();
+        let stream = client
+            .read_uuid_continous(Request::new(requests))
+            .await
+            .unwrap();
+        let active = ACTIVE_STREAMS
+            .with_label_values(&["ReadUuidContinous"])
+            .get();
+        drop(stream);
+        drop(client);
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+        stop.send(()).unwrap();
+        server.await.unwrap();
+
+        assert!(calls("ReadUuid", "Ok") > ok);
+        assert!(calls("ReadUuid", "Internal") > internal);
+        assert!(active >= 1);
+        assert!(finished("ReadUuidContinous") > streams);
+    }
+}
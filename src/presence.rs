@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+pub const DEFAULT_DEPARTURE_TIMEOUT_MS: u64 = 500;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresenceOptions {
+    // a tag that was not read for this long has left the field
+    pub departure_timeout: Duration,
+}
+
+impl Default for PresenceOptions {
+    fn default() -> PresenceOptions {
+        PresenceOptions {
+            departure_timeout: Duration::from_millis(DEFAULT_DEPARTURE_TIMEOUT_MS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagEventKind {
+    Arrived,
+    Departed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagEvent {
+    pub kind: TagEventKind,
+    pub uid: String,
+    pub at: SystemTime,
+}
+
+impl TagEvent {
+    fn new(kind: TagEventKind, uid: &str) -> TagEvent {
+        TagEvent {
+            kind,
+            uid: String::from(uid),
+            at: SystemTime::now(),
+        }
+    }
+}
+
+/* turns the result of every poll into arrivals and departures. a tag missing from a
+few polls in a row is still present until the departure timeout runs out, so a tag
+held at the edge of the field does not flap */
+pub struct Presence {
+    departure_timeout: Duration,
+    // last time each present tag was read
+    present: HashMap<String, Instant>,
+}
+
+impl Presence {
+    pub fn new(departure_timeout: Duration) -> Presence {
+        Presence {
+            departure_timeout,
+            present: HashMap::new(),
+        }
+    }
+
+    /* records one poll, none when no tag answered */
+    pub fn observe(&mut self, uid: Option<&str>, now: Instant) -> Vec<TagEvent> {
+        let mut events = Vec::new();
+
+        if let Some(uid) = uid {
+            if self.present.insert(String::from(uid), now).is_none() {
+                events.push(TagEvent::new(TagEventKind::Arrived, uid));
+            }
+        }
+
+        let timeout = self.departure_timeout;
+        let mut departed: Vec<String> = self
+            .present
+            .iter()
+            .filter(|(_, seen)| now.saturating_duration_since(**seen) >= timeout)
+            .map(|(uid, _)| uid.clone())
+            .collect();
+        departed.sort();
+        for uid in departed {
+            self.present.remove(&uid);
+            events.push(TagEvent::new(TagEventKind::Departed, &uid));
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const UID: &str = "CAFEDEADBEEFB0B0";
+
+    fn kinds(events: &[TagEvent]) -> Vec<(TagEventKind, &str)> {
+        events.iter().map(|e| (e.kind, e.uid.as_str())).collect()
+    }
+
+    #[test]
+    fn arrival_reported_once() {
+        let mut presence = Presence::new(Duration::from_millis(500));
+        let start = Instant::now();
+
+        let events = presence.observe(Some(UID), start);
+        assert_eq!(kinds(&events), vec![(TagEventKind::Arrived, UID)]);
+        assert!(presence
+            .observe(Some(UID), start + Duration::from_millis(100))
+            .is_empty());
+    }
+
+    #[test]
+    fn missed_reads_within_timeout_ignored() {
+        let mut presence = Presence::new(Duration::from_millis(500));
+        let start = Instant::now();
+        presence.observe(Some(UID), start);
+
+        assert!(presence
+            .observe(None, start + Duration::from_millis(300))
+            .is_empty());
+        assert!(presence
+            .observe(Some(UID), start + Duration::from_millis(400))
+            .is_empty());
+        assert!(presence
+            .observe(None, start + Duration::from_millis(800))
+            .is_empty());
+    }
+
+    #[test]
+    fn departure_after_timeout() {
+        let mut presence = Presence::new(Duration::from_millis(500));
+        let start = Instant::now();
+        presence.observe(Some(UID), start);
+
+        let events = presence.observe(None, start + Duration::from_millis(500));
+        assert_eq!(kinds(&events), vec![(TagEventKind::Departed, UID)]);
+        assert!(presence
+            .observe(None, start + Duration::from_millis(600))
+            .is_empty());
+    }
+
+    #[test]
+    fn swapped_tags() {
+        let mut presence = Presence::new(Duration::from_millis(500));
+        let start = Instant::now();
+        presence.observe(Some(UID), start);
+
+        let events = presence.observe(Some("E004015012345678"), start + Duration::from_secs(1));
+        assert_eq!(
+            kinds(&events),
+            vec![
+                (TagEventKind::Arrived, "E004015012345678"),
+                (TagEventKind::Departed, UID)
+            ]
+        );
+    }
+}
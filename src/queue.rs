@@ -0,0 +1,418 @@
+use futures::lock::OwnedMutexGuard;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+pub mod err;
+
+use crate::metrics;
+use crate::reader::ReaderTraits;
+use crate::supervisor::ReaderSlot;
+use err::QueueError;
+
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+/* only used to order waiting requests when the queue is ordered by priority */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueueOrder {
+    Fifo,
+    Priority,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueOptions {
+    // requests allowed to wait for the reader, not counting the one using it
+    pub max_depth: usize,
+    pub order: QueueOrder,
+}
+
+impl Default for QueueOptions {
+    fn default() -> QueueOptions {
+        QueueOptions {
+            max_depth: DEFAULT_MAX_DEPTH,
+            order: QueueOrder::Fifo,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueueStats {
+    pub granted: u64,
+    pub rejected: u64,
+    pub expired: u64,
+    pub waiting: usize,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+}
+
+impl QueueStats {
+    pub fn avg_wait(&self) -> Duration {
+        if self.granted == 0 {
+            return Duration::from_millis(0);
+        }
+        self.total_wait.div_f64(self.granted as f64)
+    }
+}
+
+struct Waiter {
+    priority: Priority,
+    seq: u64,
+    wake: oneshot::Sender<()>,
+}
+
+// higher priority first, then first come first served
+impl Ord for Waiter {
+    fn cmp(&self, other: &Waiter) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Waiter) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Waiter) -> bool {
+        self.seq == other.seq
+    }
+}
+
+impl Eq for Waiter {}
+
+#[derive(Default)]
+struct State {
+    // someone holds the turn, nobody waits while this is false
+    busy: bool,
+    waiting: BinaryHeap<Waiter>,
+    next_seq: u64,
+    stats: QueueStats,
+}
+
+impl State {
+    /* hands the turn to the next waiter that is still there, or frees the reader */
+    fn pass_on(&mut self) {
+        while let Some(waiter) = self.waiting.pop() {
+            if waiter.wake.send(()).is_ok() {
+                return;
+            }
+        }
+        self.busy = false;
+    }
+
+    /* gives up a place, or the turn if it was handed over in the meantime */
+    fn leave(&mut self, seq: u64) {
+        let before = self.waiting.len();
+        self.waiting.retain(|w| w.seq != seq);
+        if self.waiting.len() == before {
+            self.pass_on();
+        }
+    }
+}
+
+/* a place in the queue, given up if the request goes away before its turn is used */
+struct Place {
+    seq: u64,
+    state: Arc<Mutex<State>>,
+    settled: bool,
+}
+
+impl Drop for Place {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.state.lock().unwrap().leave(self.seq);
+        }
+    }
+}
+
+struct Token {
+    state: Arc<Mutex<State>>,
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().pass_on();
+    }
+}
+
+/* exclusive use of the reader, the next request gets its turn once this is dropped */
+pub struct ReaderTurn {
+    guard: OwnedMutexGuard<Option<Box<dyn ReaderTraits>>>,
+    // dropped after the guard, so the next turn finds the slot unlocked
+    _token: Token,
+}
+
+impl Deref for ReaderTurn {
+    type Target = Option<Box<dyn ReaderTraits>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl DerefMut for ReaderTurn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+/* hands out turns on the reader, one at a time, in fifo or priority order */
+#[derive(Clone)]
+pub struct ReaderQueue {
+    slot: ReaderSlot,
+    state: Arc<Mutex<State>>,
+    options: QueueOptions,
+}
+
+impl ReaderQueue {
+    pub fn new(slot: ReaderSlot, options: QueueOptions) -> ReaderQueue {
+        ReaderQueue {
+            slot,
+            state: Arc::new(Mutex::new(State::default())),
+            options,
+        }
+    }
+
+    /* waits for the reader, at most for the deadline if there is one */
+    pub async fn acquire(
+        &self,
+        priority: Priority,
+        deadline: Option<Duration>,
+    ) -> Result<ReaderTurn, QueueError> {
+        let start = Instant::now();
+        let waiting = {
+            let mut state = self.state.lock().unwrap();
+            if !state.busy {
+                state.busy = true;
+                None
+            } else if state.waiting.len() >= self.options.max_depth {
+                state.stats.rejected += 1;
+                return Err(QueueError::QueueFull(self.options.max_depth));
+            } else {
+                let priority = match self.options.order {
+                    QueueOrder::Fifo => Priority::Normal,
+                    QueueOrder::Priority => priority,
+                };
+                let seq = state.next_seq;
+                state.next_seq += 1;
+
+                let (wake, woken) = oneshot::channel();
+                state.waiting.push(Waiter {
+                    priority,
+                    seq,
+                    wake,
+                });
+                let place = Place {
+                    seq,
+                    state: self.state.clone(),
+                    settled: false,
+                };
+                Some((place, woken))
+            }
+        };
+
+        if let Some((mut place, mut woken)) = waiting {
+            let granted = match deadline {
+                Some(deadline) => tokio::time::timeout(deadline, &mut woken).await.is_ok(),
+                // the sender only goes away with a grant, the queue outlives the place
+                None => (&mut woken).await.is_ok(),
+            };
+            if !granted {
+                self.state.lock().unwrap().stats.expired += 1;
+                return Err(QueueError::DeadlineExceeded(start.elapsed()));
+            }
+            place.settled = true;
+        }
+
+        let token = Token {
+            state: self.state.clone(),
+        };
+        let waited = start.elapsed();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.stats.granted += 1;
+            state.stats.total_wait += waited;
+            state.stats.max_wait = std::cmp::max(state.stats.max_wait, waited);
+        }
+        let stats = self.stats();
+        log::debug!(
+            "Reader turn after {:?}, {} waiting, average wait {:?}",
+            waited,
+            stats.waiting,
+            stats.avg_wait()
+        );
+
+        let guard = self.slot.clone().lock_owned().await;
+        metrics::reader_wait(start.elapsed());
+        Ok(ReaderTurn {
+            guard,
+            _token: token,
+        })
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let state = self.state.lock().unwrap();
+        QueueStats {
+            waiting: state.waiting.len(),
+            ..state.stats.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::lock::Mutex as AsyncMutex;
+
+    fn queue(order: QueueOrder, max_depth: usize) -> ReaderQueue {
+        ReaderQueue::new(
+            Arc::new(AsyncMutex::new(None)),
+            QueueOptions { max_depth, order },
+        )
+    }
+
+    /* queues a request that records when it got its turn */
+    fn enqueue(
+        q: &ReaderQueue,
+        priority: Priority,
+        id: u32,
+        order: &Arc<Mutex<Vec<u32>>>,
+    ) -> tokio::task::JoinHandle<()> {
+        let q = q.clone();
+        let order = order.clone();
+        tokio::spawn(async move {
+            let _turn = q.acquire(priority, None).await.unwrap();
+            order.lock().unwrap().push(id);
+        })
+    }
+
+    async fn settle() {
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+    }
+
+    #[tokio::test]
+    async fn fifo_ignores_priority() {
+        let q = queue(QueueOrder::Fifo, DEFAULT_MAX_DEPTH);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let turn = q.acquire(Priority::Normal, None).await.unwrap();
+
+        let low = enqueue(&q, Priority::Low, 1, &order);
+        settle().await;
+        let high = enqueue(&q, Priority::High, 2, &order);
+        settle().await;
+
+        drop(turn);
+        low.await.unwrap();
+        high.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn priority_order() {
+        let q = queue(QueueOrder::Priority, DEFAULT_MAX_DEPTH);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let turn = q.acquire(Priority::Normal, None).await.unwrap();
+
+        let first = enqueue(&q, Priority::Low, 1, &order);
+        settle().await;
+        let second = enqueue(&q, Priority::Normal, 2, &order);
+        settle().await;
+        let third = enqueue(&q, Priority::High, 3, &order);
+        settle().await;
+
+        drop(turn);
+        first.await.unwrap();
+        second.await.unwrap();
+        third.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), vec![3, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn full_queue_rejected() {
+        let q = queue(QueueOrder::Fifo, 1);
+        let _turn = q.acquire(Priority::Normal, None).await.unwrap();
+        let waiting = q.clone();
+        tokio::spawn(async move {
+            let _ = waiting.acquire(Priority::Normal, None).await;
+        });
+        settle().await;
+
+        match q.acquire(Priority::Normal, None).await {
+            Err(QueueError::QueueFull(1)) => {}
+            _ => panic!("{}", "Should have been QueueFull"),
+        }
+        assert_eq!(q.stats().rejected, 1);
+        assert_eq!(q.stats().waiting, 1);
+    }
+
+    #[tokio::test]
+    async fn deadline_leaves_queue() {
+        let q = queue(QueueOrder::Fifo, DEFAULT_MAX_DEPTH);
+        let turn = q.acquire(Priority::Normal, None).await.unwrap();
+
+        let res = q
+            .acquire(Priority::Normal, Some(Duration::from_millis(10)))
+            .await;
+        match res {
+            Err(QueueError::DeadlineExceeded(_)) => {}
+            _ => panic!("{}", "Should have been DeadlineExceeded"),
+        }
+        assert_eq!(q.stats().waiting, 0);
+        assert_eq!(q.stats().expired, 1);
+
+        // the expired request must not keep the reader once the turn is released
+        drop(turn);
+        assert!(q
+            .acquire(Priority::Normal, Some(Duration::from_millis(10)))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiter_passes_turn_on() {
+        let q = queue(QueueOrder::Fifo, DEFAULT_MAX_DEPTH);
+        let turn = q.acquire(Priority::Normal, None).await.unwrap();
+
+        let mut cancelled = Box::pin(q.acquire(Priority::Normal, None));
+        assert!(futures::poll!(&mut cancelled).is_pending());
+        // the turn is handed over, but the request goes away before it runs again
+        drop(turn);
+        drop(cancelled);
+
+        assert!(q
+            .acquire(Priority::Normal, Some(Duration::from_millis(50)))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn wait_time_recorded() {
+        let q = queue(QueueOrder::Fifo, DEFAULT_MAX_DEPTH);
+        let turn = q.acquire(Priority::Normal, None).await.unwrap();
+        let waiting = q.clone();
+        let handle = tokio::spawn(async move {
+            let _turn = waiting.acquire(Priority::Normal, None).await.unwrap();
+        });
+        tokio::time::delay_for(Duration::from_millis(30)).await;
+        drop(turn);
+        handle.await.unwrap();
+
+        let stats = q.stats();
+        assert_eq!(stats.granted, 2);
+        assert!(stats.max_wait >= Duration::from_millis(30));
+        assert!(stats.avg_wait() >= Duration::from_millis(15));
+    }
+}
@@ -0,0 +1,28 @@
+use std::fmt;
+use std::fmt::Debug;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum QueueError {
+    // holds the depth limit that was hit
+    QueueFull(usize),
+    // holds how long the request waited
+    DeadlineExceeded(Duration),
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueueError::QueueFull(depth) => {
+                write!(f, "Reader busy, {} requests already waiting", depth)
+            }
+            QueueError::DeadlineExceeded(waited) => {
+                write!(
+                    f,
+                    "Reader not available within deadline, waited {:?}",
+                    waited
+                )
+            }
+        }
+    }
+}
@@ -1,48 +1,65 @@
 #[cfg(test)]
 use mockall::automock;
 
-use regex::Regex;
+use async_trait::async_trait;
 
 use crate::serial::err::SerialError;
 use crate::serial::RfidSerialTraits;
 
 pub mod constants;
 pub mod err;
+pub mod parse;
+pub mod rf;
 
 use constants::{
-    AGC, AGC_RES, AGC_RES_2, AM, AM_RES, AM_RES_2, BLOCK_CHARS, EXT_ANT, EXT_ANT_RES, INV_REQ, ISO,
-    ISO_RES, RF_HALF_DATA, RF_HALF_DATA_RES, SINGLE_BLK_CHARS, SINGLE_BLK_OFFSET, SINGLE_BLK_REGEX,
-    SINGLE_BLK_REQ, SINGLE_BLK_REQ_END, SINGLE_BLK_START, UUID_CHARS, UUID_REGEX, UUID_START,
+    AGC, AGC_RES, AGC_RES_2, AM, AM_RES, AM_RES_2, BLOCK_CHARS, CHIP_STATUS_REG, EXT_ANT,
+    EXT_ANT_RES, INV_REQ, ISO, ISO_RES, MODULATOR_REG, REG_WRITE, REG_WRITE_END, REG_WRITE_RES,
+    RF_HALF_DATA, RF_HALF_DATA_RES, SINGLE_BLK_REGEX, SINGLE_BLK_REQ, SINGLE_BLK_REQ_END,
+    UUID_REGEX,
 };
 use err::ReaderError;
+use rf::{ModulationDepth, OutputPower};
 
 /* for mocking of reader functions */
 #[cfg_attr(test, automock)]
+#[async_trait]
 pub trait ReaderTraits: Send + Sync {
-    fn read_uuid(&mut self) -> Result<String, ReaderError>;
-    //returns a single block of data of information
-    fn read_single_block(&mut self, block_idx: u32) -> Result<String, ReaderError>;
+    async fn read_uuid(&mut self) -> Result<String, ReaderError>;
+    //returns the uuid together with the rssi byte reported by the inventory
+    async fn read_uuid_rssi(&mut self) -> Result<(String, u8), ReaderError>;
+    //returns the uuid of the tag read together with a single block of its data
+    async fn read_single_block(&mut self, block_idx: u32) -> Result<(String, String), ReaderError>;
     // returns num chars of data
-    fn read_multiple_block(
+    #[allow(dead_code)]
+    async fn read_multiple_block(
         &mut self,
         block_idx: u32,
         num_blocks: u32,
     ) -> Result<String, ReaderError>;
+    async fn set_output_power(&mut self, power: OutputPower) -> Result<(), ReaderError>;
+    async fn set_modulation(&mut self, depth: ModulationDepth) -> Result<(), ReaderError>;
 }
 
 pub struct Reader {
     serial: Box<dyn RfidSerialTraits>,
 }
 
+#[async_trait]
 impl ReaderTraits for Reader {
-    fn read_uuid(&mut self) -> Result<String, ReaderError> {
-        let raw_uuid = self.read_raw_uuid()?;
-        let reversed = reverse_uuid(&raw_uuid);
-        Ok(reversed)
+    async fn read_uuid(&mut self) -> Result<String, ReaderError> {
+        let raw_uuid = self.read_raw_uuid().await?;
+        reverse_uuid(&raw_uuid)
     }
 
-    fn read_single_block(&mut self, block_idx: u32) -> Result<String, ReaderError> {
-        let raw_uuid = self.read_raw_uuid()?;
+    async fn read_uuid_rssi(&mut self) -> Result<(String, u8), ReaderError> {
+        let res = self.send_read_regex(INV_REQ, &[UUID_REGEX]).await?;
+        let reversed = reverse_uuid(&get_uuid(&res)?)?;
+        let rssi = parse::rssi(&res).ok_or(ReaderError::InvalidReply(res))?;
+        Ok((reversed, rssi))
+    }
+
+    async fn read_single_block(&mut self, block_idx: u32) -> Result<(String, String), ReaderError> {
+        let raw_uuid = self.read_raw_uuid().await?;
         //get the block representation in hex
         let block_hex = format!("{:02X}", block_idx);
         if block_hex.len() != BLOCK_CHARS {
@@ -54,123 +71,106 @@ impl ReaderTraits for Reader {
             SINGLE_BLK_REQ, raw_uuid, block_hex, SINGLE_BLK_REQ_END
         );
 
-        let raw_data = self.send_read_regex(&cmd, &[SINGLE_BLK_REGEX])?;
-
-        let start = raw_data.find(SINGLE_BLK_START).unwrap() + SINGLE_BLK_OFFSET;
-        let end = start + SINGLE_BLK_CHARS;
-        let data = &raw_data[start..end];
-
-        Ok(String::from(data))
+        let raw_data = self.send_read_regex(&cmd, &[SINGLE_BLK_REGEX]).await?;
+        let data = parse::single_block(&raw_data).ok_or(ReaderError::InvalidReply(raw_data))?;
+        Ok((reverse_uuid(&raw_uuid)?, data))
     }
 
     //TODO
-    fn read_multiple_block(
+    async fn read_multiple_block(
         &mut self,
         _block_idx: u32,
         _num_blocks: u32,
     ) -> Result<String, ReaderError> {
         Err(ReaderError::SerialError(SerialError::NoSerialPortsFound))
     }
-}
-
-fn get_uuid(raw_str: &str) -> String {
-    // let it panic, if uuid regex matched but not the UUID start
-    let start_idx = raw_str.find(UUID_START).unwrap();
 
-    let s: Vec<char> = raw_str.chars().collect();
-    let start = start_idx - (UUID_CHARS - 2);
-    let mut uuid = String::new();
+    async fn set_output_power(&mut self, power: OutputPower) -> Result<(), ReaderError> {
+        self.write_register(CHIP_STATUS_REG, power.register_value())
+            .await
+    }
 
-    for i in 0..(UUID_CHARS / 2) {
-        let char_idx = start + i * 2;
-        uuid.push(s[char_idx]);
-        uuid.push(s[char_idx + 1]);
+    async fn set_modulation(&mut self, depth: ModulationDepth) -> Result<(), ReaderError> {
+        self.write_register(MODULATOR_REG, depth.register_value())
+            .await
     }
-    uuid
 }
 
-fn reverse_uuid(uuid: &str) -> String {
-    assert!(uuid.len() == UUID_CHARS);
-
-    let s: Vec<char> = uuid.chars().collect();
-    let mut reversed = String::new();
+fn get_uuid(raw_str: &str) -> Result<String, ReaderError> {
+    parse::uuid(raw_str).ok_or_else(|| ReaderError::InvalidReply(String::from(raw_str)))
+}
 
-    for i in 1..(uuid.len() / 2) + 1 {
-        let char_idx = uuid.len() - i * 2;
-        reversed.push(s[char_idx]);
-        reversed.push(s[char_idx + 1]);
-    }
-    reversed
+fn reverse_uuid(uuid: &str) -> Result<String, ReaderError> {
+    parse::reverse_uuid(uuid).ok_or_else(|| ReaderError::InvalidReply(String::from(uuid)))
 }
 
 impl Reader {
-    pub fn new(serial: Box<dyn RfidSerialTraits>) -> Reader {
+    pub async fn try_new(serial: Box<dyn RfidSerialTraits>) -> Result<Reader, ReaderError> {
         let mut reader = Reader { serial };
-
-        if let Err(e) = reader.initialize() {
-            log::error!("{}", e);
-            panic!("{}", e.to_string());
-        }
-        reader
+        reader.initialize().await?;
+        Ok(reader)
     }
 
-    fn read_raw_uuid(&mut self) -> Result<String, ReaderError> {
-        let res = self.send_read_regex(INV_REQ, &[UUID_REGEX])?;
-        let raw_uuid = get_uuid(&res);
-        Ok(raw_uuid)
+    async fn read_raw_uuid(&mut self) -> Result<String, ReaderError> {
+        let res = self.send_read_regex(INV_REQ, &[UUID_REGEX]).await?;
+        get_uuid(&res)
     }
 
     //send a command, and check whether output matches any of the regex
-    fn send_read_regex(&mut self, cmd: &str, regex: &[&str]) -> Result<String, ReaderError> {
-        let read = self.serial.send_recv(cmd)?;
-
-        for r in regex.iter() {
-            //panic if any regex is invalid
-            let re = match Regex::new(r) {
-                Ok(re) => re,
-                Err(_) => {
-                    log::error!("{}", ReaderError::InvalidRegex(r.to_string()));
-                    panic!("{}", ReaderError::InvalidRegex(r.to_string()).to_string());
-                }
-            };
-            if let Some(cap) = re.captures(&read) {
-                return Ok(String::from(cap.get(0).unwrap().as_str()));
+    async fn send_read_regex(&mut self, cmd: &str, regex: &[&str]) -> Result<String, ReaderError> {
+        let read = self.serial.send_recv(cmd).await?;
+
+        match parse::first_match(&read, regex) {
+            Ok(Some(found)) => Ok(String::from(found)),
+            Ok(None) => Err(ReaderError::NoMatchingTargets(read)),
+            Err(r) => {
+                log::error!("{}", ReaderError::InvalidRegex(r.to_string()));
+                Err(ReaderError::InvalidRegex(r.to_string()))
             }
         }
-        Err(ReaderError::NoMatchingTargets(read))
     }
 
-    fn initialize(&mut self) -> Result<(), ReaderError> {
-        self.set_iso()?;
-        self.set_half_data()?;
-        self.set_agc()?;
-        self.set_am()?;
-        self.set_antenna()?;
+    async fn initialize(&mut self) -> Result<(), ReaderError> {
+        self.set_iso().await?;
+        self.set_half_data().await?;
+        self.set_agc().await?;
+        self.set_am().await?;
+        self.set_antenna().await?;
+        Ok(())
+    }
+
+    async fn set_iso(&mut self) -> Result<(), ReaderError> {
+        self.send_read_regex(ISO, &[ISO_RES]).await?;
         Ok(())
     }
 
-    fn set_iso(&mut self) -> Result<(), ReaderError> {
-        self.send_read_regex(ISO, &[ISO_RES])?;
+    async fn set_half_data(&mut self) -> Result<(), ReaderError> {
+        self.send_read_regex(RF_HALF_DATA, &[RF_HALF_DATA_RES])
+            .await?;
         Ok(())
     }
 
-    fn set_half_data(&mut self) -> Result<(), ReaderError> {
-        self.send_read_regex(RF_HALF_DATA, &[RF_HALF_DATA_RES])?;
+    async fn set_agc(&mut self) -> Result<(), ReaderError> {
+        self.send_read_regex(AGC, &[AGC_RES, AGC_RES_2]).await?;
         Ok(())
     }
 
-    fn set_agc(&mut self) -> Result<(), ReaderError> {
-        self.send_read_regex(AGC, &[AGC_RES, AGC_RES_2])?;
+    async fn set_am(&mut self) -> Result<(), ReaderError> {
+        self.send_read_regex(AM, &[AM_RES, AM_RES_2]).await?;
         Ok(())
     }
 
-    fn set_am(&mut self) -> Result<(), ReaderError> {
-        self.send_read_regex(AM, &[AM_RES, AM_RES_2])?;
+    async fn set_antenna(&mut self) -> Result<(), ReaderError> {
+        self.send_read_regex(EXT_ANT, &[EXT_ANT_RES]).await?;
         Ok(())
     }
 
-    fn set_antenna(&mut self) -> Result<(), ReaderError> {
-        self.send_read_regex(EXT_ANT, &[EXT_ANT_RES])?;
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), ReaderError> {
+        let cmd = format!(
+            "{}{:02X}{:02X}{}",
+            REG_WRITE, register, value, REG_WRITE_END
+        );
+        self.send_read_regex(&cmd, &[REG_WRITE_RES]).await?;
         Ok(())
     }
 }
@@ -180,35 +180,44 @@ mod test {
     use super::*;
     use crate::serial::MockRfidSerialTraits;
     use mockall::predicate::eq;
+    use regex::Regex;
 
     mod init {
         use super::*;
 
-        #[test]
-        #[should_panic(expected = "Serial device did not respond to read request")]
-        fn serial_port_error() {
+        #[tokio::test]
+        async fn serial_port_error() {
             let mut serial = MockRfidSerialTraits::new();
             serial
                 .expect_send_recv()
                 .with(eq(ISO))
-                .returning(|_| Err(SerialError::NoReplyAfterMultipleTries));
-            let mut _reader = Reader::new(Box::new(serial));
+                .returning(|_| Err(SerialError::NoReplyAfterMultipleTries(vec![])));
+            let res = Reader::try_new(Box::new(serial)).await;
+            match res {
+                Ok(_) => panic!("{}", "Should have been an error"),
+                Err(e) => assert!(e
+                    .to_string()
+                    .contains("Serial device did not respond to read request")),
+            }
         }
 
-        #[test]
-        #[should_panic(expected = "Targets not found")]
-        fn mismatch_output() {
+        #[tokio::test]
+        async fn mismatch_output() {
             let mut serial = MockRfidSerialTraits::new();
             serial
                 .expect_send_recv()
                 .with(eq(ISO))
                 .returning(|_| Ok(String::from("Gibberish")));
-            let mut _reader = Reader::new(Box::new(serial));
+            let res = Reader::try_new(Box::new(serial)).await;
+            match res {
+                Ok(_) => panic!("{}", "Should have been an error"),
+                Err(e) => assert!(e.to_string().contains("Targets not found")),
+            }
         }
 
         //tests whether reader can initialize even with the alternate reply for agc
-        #[test]
-        fn agc_res_two_ok() {
+        #[tokio::test]
+        async fn agc_res_two_ok() {
             let mut serial = MockRfidSerialTraits::new();
             serial
                 .expect_send_recv()
@@ -230,12 +239,12 @@ mod test {
                 .expect_send_recv()
                 .with(eq(EXT_ANT))
                 .returning(|_| Ok(String::from(EXT_ANT_RES)));
-            let mut _reader = Reader::new(Box::new(serial));
+            let mut _reader = Reader::try_new(Box::new(serial)).await.unwrap();
         }
 
         //tests whether reader can initialize even with the alternate reply for am
-        #[test]
-        fn am_res_two_ok() {
+        #[tokio::test]
+        async fn am_res_two_ok() {
             let mut serial = MockRfidSerialTraits::new();
             serial
                 .expect_send_recv()
@@ -257,11 +266,11 @@ mod test {
                 .expect_send_recv()
                 .with(eq(EXT_ANT))
                 .returning(|_| Ok(String::from(EXT_ANT_RES)));
-            let mut _reader = Reader::new(Box::new(serial));
+            let mut _reader = Reader::try_new(Box::new(serial)).await.unwrap();
         }
 
-        #[test]
-        fn init_ok() {
+        #[tokio::test]
+        async fn init_ok() {
             let mut serial = MockRfidSerialTraits::new();
             serial
                 .expect_send_recv()
@@ -283,7 +292,7 @@ mod test {
                 .expect_send_recv()
                 .with(eq(EXT_ANT))
                 .returning(|_| Ok(String::from(EXT_ANT_RES)));
-            let mut _reader = Reader::new(Box::new(serial));
+            let mut _reader = Reader::try_new(Box::new(serial)).await.unwrap();
         }
     }
 
@@ -426,16 +435,16 @@ mod test {
 
     mod uuid {
         use super::*;
-        #[test]
-        fn non_matching() {
+        #[tokio::test]
+        async fn non_matching() {
             let mut serial = MockRfidSerialTraits::new();
             init_helper(&mut serial);
             serial
                 .expect_send_recv()
                 .with(eq(INV_REQ))
                 .returning(|_| Ok(String::from("[CAFE,FF]")));
-            let mut reader = Reader::new(Box::new(serial));
-            let res = reader.read_uuid();
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            let res = reader.read_uuid().await;
             assert!(res.is_err());
 
             if let Err(e) = res {
@@ -446,89 +455,118 @@ mod test {
             }
         }
 
-        #[test]
-        fn serial_error() {
+        #[tokio::test]
+        async fn serial_error() {
             let mut serial = MockRfidSerialTraits::new();
             init_helper(&mut serial);
             serial
                 .expect_send_recv()
                 .with(eq(INV_REQ))
-                .returning(|_| Err(SerialError::NoReplyAfterMultipleTries));
-            let mut reader = Reader::new(Box::new(serial));
-            let res = reader.read_uuid();
+                .returning(|_| Err(SerialError::NoReplyAfterMultipleTries(vec![])));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            let res = reader.read_uuid().await;
             assert!(res.is_err());
 
             if let Err(e) = res {
                 assert_eq!(
                     e.to_string(),
-                    ReaderError::SerialError(SerialError::NoReplyAfterMultipleTries).to_string()
+                    ReaderError::SerialError(SerialError::NoReplyAfterMultipleTries(vec![]))
+                        .to_string()
                 );
             }
         }
 
-        #[test]
-        fn matching() {
+        #[tokio::test]
+        async fn matching() {
             let mut serial = MockRfidSerialTraits::new();
             init_helper(&mut serial);
             serial
                 .expect_send_recv()
                 .with(eq(INV_REQ))
                 .returning(|_| Ok(String::from("[CAFEBABEDEADBEE0,FF]")));
-            let mut reader = Reader::new(Box::new(serial));
-            let res = reader.read_uuid();
-            assert!(!res.is_err());
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            let res = reader.read_uuid().await;
+            assert!(res.is_ok());
             assert_eq!(res.unwrap().to_string(), "E0BEADDEBEBAFECA");
         }
 
-        #[test]
-        fn chars_in_front_matching() {
+        #[tokio::test]
+        async fn chars_in_front_matching() {
             let mut serial = MockRfidSerialTraits::new();
             init_helper(&mut serial);
             serial
                 .expect_send_recv()
                 .with(eq(INV_REQ))
                 .returning(|_| Ok(String::from("XXXXXXXX[CAFEBABEDEADBEE0,FF]")));
-            let mut reader = Reader::new(Box::new(serial));
-            let res = reader.read_uuid();
-            assert!(!res.is_err());
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            let res = reader.read_uuid().await;
+            assert!(res.is_ok());
             assert_eq!(res.unwrap().to_string(), "E0BEADDEBEBAFECA");
         }
 
-        #[test]
-        fn chars_at_the_back_matching() {
+        #[tokio::test]
+        async fn chars_at_the_back_matching() {
             let mut serial = MockRfidSerialTraits::new();
             init_helper(&mut serial);
             serial
                 .expect_send_recv()
                 .with(eq(INV_REQ))
                 .returning(|_| Ok(String::from("[CAFEBABEDEADBEE0,FF]XXXXXX")));
-            let mut reader = Reader::new(Box::new(serial));
-            let res = reader.read_uuid();
-            assert!(!res.is_err());
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            let res = reader.read_uuid().await;
+            assert!(res.is_ok());
             assert_eq!(res.unwrap().to_string(), "E0BEADDEBEBAFECA");
         }
 
-        #[test]
-        fn chars_at_the_front_and_back_matching() {
+        #[tokio::test]
+        async fn chars_at_the_front_and_back_matching() {
             let mut serial = MockRfidSerialTraits::new();
             init_helper(&mut serial);
             serial
                 .expect_send_recv()
                 .with(eq(INV_REQ))
                 .returning(|_| Ok(String::from("XXXXXXXX[CAFEBABEDEADBEE0,FF]XXXXXX")));
-            let mut reader = Reader::new(Box::new(serial));
-            let res = reader.read_uuid();
-            assert!(!res.is_err());
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            let res = reader.read_uuid().await;
+            assert!(res.is_ok());
             assert_eq!(res.unwrap().to_string(), "E0BEADDEBEBAFECA");
         }
+
+        // used to underflow looking for E0 before the last byte
+        #[tokio::test]
+        async fn e0_inside_uid() {
+            let mut serial = MockRfidSerialTraits::new();
+            init_helper(&mut serial);
+            serial
+                .expect_send_recv()
+                .with(eq(INV_REQ))
+                .returning(|_| Ok(String::from("[0E0E0E0E0E0E0E0E,E0]")));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            assert_eq!(
+                reader.read_uuid_rssi().await.unwrap(),
+                (String::from("0E0E0E0E0E0E0E0E"), 0xE0)
+            );
+        }
+
+        #[tokio::test]
+        async fn lowercase_uid() {
+            let mut serial = MockRfidSerialTraits::new();
+            init_helper(&mut serial);
+            serial
+                .expect_send_recv()
+                .with(eq(INV_REQ))
+                .returning(|_| Ok(String::from("[cafebabedeadbee0,ff]")));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            assert_eq!(reader.read_uuid().await.unwrap(), "e0beaddebebafeca");
+        }
     }
 
     mod single_block {
 
         use super::*;
 
-        #[test]
-        fn serial_error_on_data_call() {
+        #[tokio::test]
+        async fn serial_error_on_data_call() {
             let expected_cmd = "0113000304182220CAFEDEADBEEFB0E0FF0000";
 
             let mut serial = MockRfidSerialTraits::new();
@@ -541,23 +579,24 @@ mod test {
             serial
                 .expect_send_recv()
                 .with(eq(expected_cmd))
-                .returning(|_| Err(SerialError::NoReplyAfterMultipleTries));
+                .returning(|_| Err(SerialError::NoReplyAfterMultipleTries(vec![])));
 
-            let mut reader = Reader::new(Box::new(serial));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
             let block_idx = 255;
-            let res = reader.read_single_block(block_idx);
+            let res = reader.read_single_block(block_idx).await;
 
             assert!(res.is_err());
             if let Err(e) = res {
                 assert_eq!(
                     e.to_string(),
-                    ReaderError::SerialError(SerialError::NoReplyAfterMultipleTries).to_string()
+                    ReaderError::SerialError(SerialError::NoReplyAfterMultipleTries(vec![]))
+                        .to_string()
                 );
             }
         }
 
-        #[test]
-        fn non_matching_uuid_on_data_call() {
+        #[tokio::test]
+        async fn non_matching_uuid_on_data_call() {
             let expected_cmd = "0113000304182220CAFEDEADBEEFB0E0FF0000";
 
             let mut serial = MockRfidSerialTraits::new();
@@ -570,11 +609,11 @@ mod test {
             serial
                 .expect_send_recv()
                 .with(eq(expected_cmd))
-                .returning(|_| Err(SerialError::NoReplyAfterMultipleTries));
+                .returning(|_| Err(SerialError::NoReplyAfterMultipleTries(vec![])));
 
-            let mut reader = Reader::new(Box::new(serial));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
             let block_idx = 255;
-            let res = reader.read_single_block(block_idx);
+            let res = reader.read_single_block(block_idx).await;
 
             assert!(res.is_err());
             if let Err(e) = res {
@@ -585,8 +624,8 @@ mod test {
             }
         }
 
-        #[test]
-        fn non_matching_data_on_data_calll() {
+        #[tokio::test]
+        async fn non_matching_data_on_data_calll() {
             let expected_cmd = "0113000304182220CAFEDEADBEEFB0E0FF0000";
 
             let mut serial = MockRfidSerialTraits::new();
@@ -601,9 +640,9 @@ mod test {
                 .with(eq(expected_cmd))
                 .returning(|_| Ok(String::from("[00]")));
 
-            let mut reader = Reader::new(Box::new(serial));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
             let block_idx = 255;
-            let res = reader.read_single_block(block_idx);
+            let res = reader.read_single_block(block_idx).await;
 
             assert!(res.is_err());
             if let Err(e) = res {
@@ -614,8 +653,8 @@ mod test {
             }
         }
 
-        #[test]
-        fn ok() {
+        #[tokio::test]
+        async fn ok() {
             let expected_cmd = "0113000304182220CAFEDEADBEEFB0E0FF0000";
 
             let mut serial = MockRfidSerialTraits::new();
@@ -630,11 +669,79 @@ mod test {
                 .with(eq(expected_cmd))
                 .returning(|_| Ok(String::from("[0012345678]")));
 
-            let mut reader = Reader::new(Box::new(serial));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
             let block_idx = 255;
-            let res = reader.read_single_block(block_idx);
-            assert!(!res.is_err());
-            assert_eq!(res.unwrap(), "12345678");
+            let res = reader.read_single_block(block_idx).await;
+            assert!(res.is_ok());
+            assert_eq!(
+                res.unwrap(),
+                (String::from("E0B0EFBEADDEFECA"), String::from("12345678"))
+            );
+        }
+    }
+
+    mod rf {
+
+        use super::*;
+
+        #[tokio::test]
+        async fn uuid_rssi_ok() {
+            let mut serial = MockRfidSerialTraits::new();
+            init_helper(&mut serial);
+            serial
+                .expect_send_recv()
+                .with(eq(INV_REQ))
+                .returning(|_| Ok(String::from("XX[CAFEBABEDEADBEE0,4A]XX")));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            let res = reader.read_uuid_rssi().await;
+            assert!(res.is_ok());
+            assert_eq!(res.unwrap(), (String::from("E0BEADDEBEBAFECA"), 0x4A));
+        }
+
+        #[tokio::test]
+        async fn output_power_ok() {
+            let mut serial = MockRfidSerialTraits::new();
+            init_helper(&mut serial);
+            serial
+                .expect_send_recv()
+                .with(eq("010A0003041000210000"))
+                .times(1)
+                .returning(|_| Ok(String::from(REG_WRITE_RES)));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            assert!(reader.set_output_power(OutputPower::Full).await.is_ok());
+        }
+
+        #[tokio::test]
+        async fn modulation_ok() {
+            let mut serial = MockRfidSerialTraits::new();
+            init_helper(&mut serial);
+            serial
+                .expect_send_recv()
+                .with(eq("010A0003041009360000"))
+                .times(1)
+                .returning(|_| Ok(String::from(REG_WRITE_RES)));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            assert!(reader.set_modulation(ModulationDepth::Ask22).await.is_ok());
+        }
+
+        #[tokio::test]
+        async fn modulation_non_matching() {
+            let mut serial = MockRfidSerialTraits::new();
+            init_helper(&mut serial);
+            serial
+                .expect_send_recv()
+                .with(eq("010A0003041009300000"))
+                .returning(|_| Ok(String::from("Gibberish")));
+            let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+            let res = reader.set_modulation(ModulationDepth::Ask10).await;
+
+            assert!(res.is_err());
+            if let Err(e) = res {
+                assert_eq!(
+                    e.to_string(),
+                    ReaderError::NoMatchingTargets(String::from("Gibberish")).to_string()
+                );
+            }
         }
     }
 }
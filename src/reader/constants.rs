@@ -29,6 +29,20 @@ pub const SINGLE_BLK_START: &str = "[";
 pub const SINGLE_BLK_OFFSET: usize = 3;
 pub const SINGLE_BLK_CHARS: usize = 8;
 
-pub const UUID_START: &str = "E0";
 pub const UUID_CHARS: usize = 16;
 pub const BLOCK_CHARS: usize = 2;
+
+pub const REG_WRITE: &str = "010A00030410";
+pub const REG_WRITE_END: &str = "0000";
+pub const REG_WRITE_RES: &str = "Register write request.";
+
+pub const CHIP_STATUS_REG: u8 = 0x00;
+pub const CHIP_STATUS_FULL_POWER: u8 = 0x21;
+pub const CHIP_STATUS_HALF_POWER: u8 = 0x31;
+
+pub const MODULATOR_REG: u8 = 0x09;
+// keep SYS_CLK output at 13.56MHz, lower three bits select the depth
+pub const MODULATOR_CLK: u8 = 0x30;
+
+pub const RSSI_START: &str = ",";
+pub const RSSI_CHARS: usize = 2;
@@ -10,6 +10,8 @@ pub enum ReaderError {
     NoMatchingTargets(String),
     InvalidRegex(String),
     BlockIdxTooLarge(u32),
+    //a reply that matched but could not be taken apart
+    InvalidReply(String),
 }
 
 impl fmt::Display for ReaderError {
@@ -30,6 +32,7 @@ impl fmt::Display for ReaderError {
                 let s = format!("Block index is too large: {}", e);
                 write!(f, "{}", s)
             }
+            ReaderError::InvalidReply(ref e) => write!(f, "Invalid reply: {}", e),
         }
     }
 }
@@ -39,3 +42,23 @@ impl From<SerialError> for ReaderError {
         ReaderError::SerialError(err)
     }
 }
+
+impl ReaderError {
+    /* a short name for the metrics */
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            ReaderError::SerialError(_) => "serial",
+            ReaderError::NoMatchingTargets(_) => "no_matching_targets",
+            ReaderError::InvalidRegex(_) => "invalid_regex",
+            ReaderError::BlockIdxTooLarge(_) => "block_idx_too_large",
+            ReaderError::InvalidReply(_) => "invalid_reply",
+        }
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        match *self {
+            ReaderError::SerialError(ref e) => e.is_device_lost(),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,142 @@
+/* picks the fields out of what the firmware prints. odd output gives none instead of a
+panic, the fuzz targets include this file as well so it only depends on the constants */
+use regex::Regex;
+
+use super::constants::{
+    RSSI_CHARS, RSSI_START, SINGLE_BLK_CHARS, SINGLE_BLK_OFFSET, SINGLE_BLK_START, UUID_CHARS,
+};
+
+const UUID_OPEN: &str = "[";
+
+/* the first match of any of the regexes, tried in order. an invalid regex is returned */
+pub fn first_match<'a, 'r>(reply: &'a str, regex: &[&'r str]) -> Result<Option<&'a str>, &'r str> {
+    for r in regex.iter() {
+        let re = Regex::new(r).map_err(|_| *r)?;
+        if let Some(found) = re.find(reply) {
+            return Ok(Some(found.as_str()));
+        }
+    }
+    Ok(None)
+}
+
+fn hex_at(s: &str, start: usize, len: usize) -> Option<&str> {
+    let end = start.checked_add(len)?;
+    let field = s.get(start..end)?;
+    if field.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(field)
+    } else {
+        None
+    }
+}
+
+/* the uid of an inventory slot as the reader prints it, least significant byte first */
+pub fn uuid(slot: &str) -> Option<String> {
+    let start = slot.find(UUID_OPEN)? + UUID_OPEN.len();
+    hex_at(slot, start, UUID_CHARS).map(String::from)
+}
+
+pub fn rssi(slot: &str) -> Option<u8> {
+    let start = slot.find(RSSI_START)? + RSSI_START.len();
+    u8::from_str_radix(hex_at(slot, start, RSSI_CHARS)?, 16).ok()
+}
+
+/* the data of a single block reply, after the response flags */
+pub fn single_block(reply: &str) -> Option<String> {
+    let start = reply.find(SINGLE_BLK_START)? + SINGLE_BLK_OFFSET;
+    hex_at(reply, start, SINGLE_BLK_CHARS).map(String::from)
+}
+
+/* swaps the byte order of a uid, so it reads as printed on the tag */
+pub fn reverse_uuid(uuid: &str) -> Option<String> {
+    if uuid.len() != UUID_CHARS || !uuid.is_ascii() {
+        return None;
+    }
+    let bytes: Vec<&str> = (0..UUID_CHARS)
+        .step_by(2)
+        .rev()
+        .map(|i| &uuid[i..i + 2])
+        .collect();
+    Some(bytes.concat())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::constants::{SINGLE_BLK_REGEX, UUID_REGEX};
+    use proptest::prelude::*;
+
+    #[test]
+    fn invalid_regex_returned() {
+        assert_eq!(first_match("[00]", &["[0"]), Err("[0"));
+        assert_eq!(first_match("[00]", &["x", r"\[00\]"]), Ok(Some("[00]")));
+    }
+
+    #[test]
+    fn uuid_without_e0() {
+        // the old parser looked for the E0 of the manufacturer code
+        assert_eq!(
+            uuid("[E0E0E0E0E0E0E0E0,E0]").as_deref(),
+            Some("E0E0E0E0E0E0E0E0")
+        );
+        assert_eq!(
+            uuid("[FFFFFFFFFFFFFFFF,00]").as_deref(),
+            Some("FFFFFFFFFFFFFFFF")
+        );
+        assert_eq!(uuid("[0E0,FF]"), None);
+    }
+
+    #[test]
+    fn short_block() {
+        assert_eq!(single_block("[00]"), None);
+        assert_eq!(single_block("[00CAFEBAB"), None);
+        assert_eq!(single_block("[00CAFEBABE]").as_deref(), Some("CAFEBABE"));
+    }
+
+    proptest! {
+        #[test]
+        fn any_reply_parses_without_panic(reply in "\\PC*") {
+            if let Ok(Some(slot)) = first_match(&reply, &[UUID_REGEX]) {
+                // a matching slot always has both fields
+                let uid = uuid(slot).unwrap();
+                prop_assert!(reverse_uuid(&uid).is_some());
+                prop_assert!(rssi(slot).is_some());
+            }
+            if let Ok(Some(data)) = first_match(&reply, &[SINGLE_BLK_REGEX]) {
+                prop_assert!(single_block(data).is_some());
+            }
+            let _ = uuid(&reply).map(|uid| reverse_uuid(&uid));
+            let _ = rssi(&reply);
+            let _ = single_block(&reply);
+            let _ = reverse_uuid(&reply);
+        }
+
+        #[test]
+        fn inventory_slot_round_trips(
+            uid in any::<u64>(),
+            signal in any::<u8>(),
+            before in "[^\\[]*",
+            after in ".*",
+        ) {
+            let sent: String = uid.to_le_bytes().iter().map(|b| format!("{:02X}", b)).collect();
+            let reply = format!("{}[{},{:02X}]{}", before, sent, signal, after);
+
+            let slot = first_match(&reply, &[UUID_REGEX]).unwrap().unwrap();
+            let parsed = uuid(slot).unwrap();
+            prop_assert_eq!(reverse_uuid(&parsed).unwrap(), format!("{:016X}", uid));
+            prop_assert_eq!(rssi(slot), Some(signal));
+        }
+
+        #[test]
+        fn single_block_round_trips(data in any::<u32>(), before in "[^\\[]*", after in ".*") {
+            let reply = format!("{}Request mode.\r\n[00{:08X}]{}", before, data, after);
+            let found = first_match(&reply, &[SINGLE_BLK_REGEX]).unwrap().unwrap();
+            prop_assert_eq!(single_block(found), Some(format!("{:08X}", data)));
+        }
+
+        #[test]
+        fn reversing_twice_is_identity(uid in "[0-9A-F]{16}") {
+            let reversed = reverse_uuid(&uid).unwrap();
+            prop_assert_eq!(reverse_uuid(&reversed).unwrap(), uid);
+        }
+    }
+}
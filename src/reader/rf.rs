@@ -0,0 +1,142 @@
+use super::constants::{CHIP_STATUS_FULL_POWER, CHIP_STATUS_HALF_POWER, MODULATOR_CLK};
+use super::err::ReaderError;
+use super::ReaderTraits;
+
+/* rf output power, written to the chip status control register */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputPower {
+    Full,
+    Half,
+}
+
+/* modulation depth, the lower three bits of the modulator control register */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModulationDepth {
+    Ask10,
+    Ook100,
+    Ask7,
+    Ask8_5,
+    Ask13,
+    Ask16,
+    Ask22,
+    Ask30,
+}
+
+impl OutputPower {
+    pub const ALL: [OutputPower; 2] = [OutputPower::Full, OutputPower::Half];
+
+    pub fn register_value(self) -> u8 {
+        match self {
+            OutputPower::Full => CHIP_STATUS_FULL_POWER,
+            OutputPower::Half => CHIP_STATUS_HALF_POWER,
+        }
+    }
+
+    /* the name used in the config file and on the command line */
+    pub fn from_name(name: &str) -> Option<OutputPower> {
+        match name {
+            "full" => Some(OutputPower::Full),
+            "half" => Some(OutputPower::Half),
+            _ => None,
+        }
+    }
+}
+
+impl ModulationDepth {
+    pub const ALL: [ModulationDepth; 8] = [
+        ModulationDepth::Ask10,
+        ModulationDepth::Ook100,
+        ModulationDepth::Ask7,
+        ModulationDepth::Ask8_5,
+        ModulationDepth::Ask13,
+        ModulationDepth::Ask16,
+        ModulationDepth::Ask22,
+        ModulationDepth::Ask30,
+    ];
+
+    pub fn register_value(self) -> u8 {
+        MODULATOR_CLK | self as u8
+    }
+
+    pub fn from_name(name: &str) -> Option<ModulationDepth> {
+        match name {
+            "ask10" => Some(ModulationDepth::Ask10),
+            "ook100" => Some(ModulationDepth::Ook100),
+            "ask7" => Some(ModulationDepth::Ask7),
+            "ask8_5" => Some(ModulationDepth::Ask8_5),
+            "ask13" => Some(ModulationDepth::Ask13),
+            "ask16" => Some(ModulationDepth::Ask16),
+            "ask22" => Some(ModulationDepth::Ask22),
+            "ask30" => Some(ModulationDepth::Ask30),
+            _ => None,
+        }
+    }
+}
+
+/* rf settings written whenever the reader connects, none keeps what the reader starts
+with */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReaderProfile {
+    pub output_power: Option<OutputPower>,
+    pub modulation: Option<ModulationDepth>,
+}
+
+impl ReaderProfile {
+    pub async fn apply(&self, reader: &mut dyn ReaderTraits) -> Result<(), ReaderError> {
+        if let Some(power) = self.output_power {
+            reader.set_output_power(power).await?;
+        }
+        if let Some(depth) = self.modulation {
+            reader.set_modulation(depth).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::MockReaderTraits;
+    use mockall::predicate::eq;
+
+    #[test]
+    fn power_register_values() {
+        assert_eq!(OutputPower::Full.register_value(), 0x21);
+        assert_eq!(OutputPower::Half.register_value(), 0x31);
+    }
+
+    #[test]
+    fn modulation_register_values() {
+        assert_eq!(ModulationDepth::Ask10.register_value(), 0x30);
+        assert_eq!(ModulationDepth::Ook100.register_value(), 0x31);
+        assert_eq!(ModulationDepth::Ask30.register_value(), 0x37);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(OutputPower::from_name("half"), Some(OutputPower::Half));
+        assert_eq!(
+            ModulationDepth::from_name("ask8_5"),
+            Some(ModulationDepth::Ask8_5)
+        );
+        assert_eq!(ModulationDepth::from_name("ASK10"), None);
+    }
+
+    #[tokio::test]
+    async fn profile_only_writes_what_is_set() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_set_output_power()
+            .with(eq(OutputPower::Full))
+            .times(1)
+            .returning(|_| Ok(()));
+        reader.expect_set_modulation().times(0);
+
+        let profile = ReaderProfile {
+            output_power: Some(OutputPower::Full),
+            modulation: None,
+        };
+        assert!(profile.apply(&mut reader).await.is_ok());
+        assert!(ReaderProfile::default().apply(&mut reader).await.is_ok());
+    }
+}
@@ -0,0 +1,270 @@
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::stream::StreamExt;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tonic::{Code, Request, Response, Status, Streaming};
+
+use crate::include::reflection::server_reflection_request::MessageRequest;
+use crate::include::reflection::server_reflection_response::MessageResponse;
+use crate::include::reflection::server_reflection_server::ServerReflection;
+use crate::include::reflection::{
+    ErrorResponse, ExtensionNumberResponse, FileDescriptorResponse, ListServiceResponse,
+    ServerReflectionRequest, ServerReflectionResponse, ServiceResponse,
+};
+
+const REPLY_BUFFER_SIZE: usize = 4;
+
+/* the files the server was built from, by name, and where each symbol is declared */
+struct Descriptors {
+    files: HashMap<String, FileDescriptorProto>,
+    symbols: HashMap<String, String>,
+    services: Vec<String>,
+}
+
+fn qualified(package: &str, name: &str) -> String {
+    if package.is_empty() {
+        String::from(name)
+    } else {
+        format!("{}.{}", package, name)
+    }
+}
+
+fn add_message(
+    symbols: &mut HashMap<String, String>,
+    scope: &str,
+    file: &str,
+    message: &DescriptorProto,
+) {
+    let name = qualified(scope, message.name());
+    for nested in &message.nested_type {
+        add_message(symbols, &name, file, nested);
+    }
+    for e in &message.enum_type {
+        symbols.insert(qualified(&name, e.name()), String::from(file));
+    }
+    symbols.insert(name, String::from(file));
+}
+
+impl Descriptors {
+    fn new(set: FileDescriptorSet) -> Descriptors {
+        let mut symbols = HashMap::new();
+        let mut services = Vec::new();
+        for file in &set.file {
+            let package = file.package();
+            for message in &file.message_type {
+                add_message(&mut symbols, package, file.name(), message);
+            }
+            for e in &file.enum_type {
+                symbols.insert(qualified(package, e.name()), String::from(file.name()));
+            }
+            for service in &file.service {
+                let name = qualified(package, service.name());
+                for method in &service.method {
+                    symbols.insert(qualified(&name, method.name()), String::from(file.name()));
+                }
+                symbols.insert(name.clone(), String::from(file.name()));
+                services.push(name);
+            }
+        }
+
+        Descriptors {
+            files: set
+                .file
+                .into_iter()
+                .map(|f| (String::from(f.name()), f))
+                .collect(),
+            symbols,
+            services,
+        }
+    }
+
+    /* the file with everything it imports, as clients need all of them to make sense of it */
+    fn file_with_imports(&self, name: &str) -> Option<Vec<Vec<u8>>> {
+        let mut pending = vec![name];
+        let mut sent: Vec<&str> = Vec::new();
+        let mut encoded = Vec::new();
+        while let Some(name) = pending.pop() {
+            if sent.contains(&name) {
+                continue;
+            }
+            let file = self.files.get(name)?;
+            let mut buf = Vec::new();
+            // encoding into a vec cannot run out of space
+            file.encode(&mut buf).unwrap();
+            encoded.push(buf);
+            sent.push(name);
+            pending.extend(file.dependency.iter().map(String::as_str));
+        }
+        Some(encoded)
+    }
+
+    fn answer(&self, request: &MessageRequest) -> MessageResponse {
+        let not_found = |what: &str| {
+            MessageResponse::ErrorResponse(ErrorResponse {
+                error_code: Code::NotFound as i32,
+                error_message: format!("{} not found", what),
+            })
+        };
+        let files = |name: &str| match self.file_with_imports(name) {
+            Some(file_descriptor_proto) => {
+                MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+                    file_descriptor_proto,
+                })
+            }
+            None => not_found(name),
+        };
+
+        match *request {
+            MessageRequest::FileByFilename(ref name) => files(name),
+            MessageRequest::FileContainingSymbol(ref symbol) => match self.symbols.get(symbol) {
+                Some(file) => files(file),
+                None => not_found(symbol),
+            },
+            // the protos declare no extensions
+            MessageRequest::FileContainingExtension(ref extension) => not_found(&format!(
+                "Extension {} of {}",
+                extension.extension_number, extension.containing_type
+            )),
+            MessageRequest::AllExtensionNumbersOfType(ref name) => {
+                if self.symbols.contains_key(name) {
+                    MessageResponse::AllExtensionNumbersResponse(ExtensionNumberResponse {
+                        base_type_name: name.clone(),
+                        extension_number: vec![],
+                    })
+                } else {
+                    not_found(name)
+                }
+            }
+            MessageRequest::ListServices(_) => {
+                MessageResponse::ListServicesResponse(ListServiceResponse {
+                    service: self
+                        .services
+                        .iter()
+                        .map(|name| ServiceResponse { name: name.clone() })
+                        .collect(),
+                })
+            }
+        }
+    }
+}
+
+/* grpc.reflection.v1alpha.ServerReflection, so tools like grpcurl can find the services
+without the protos */
+pub struct Reflection {
+    descriptors: Arc<Descriptors>,
+}
+
+impl Reflection {
+    pub fn new(encoded: &[u8]) -> Result<Reflection, prost::DecodeError> {
+        let set = FileDescriptorSet::decode(encoded)?;
+        Ok(Reflection {
+            descriptors: Arc::new(Descriptors::new(set)),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl ServerReflection for Reflection {
+    type ServerReflectionInfoStream = Receiver<Result<ServerReflectionResponse, Status>>;
+
+    /* one reply per request, until the client closes its side */
+    async fn server_reflection_info(
+        &self,
+        request: Request<Streaming<ServerReflectionRequest>>,
+    ) -> Result<Response<Self::ServerReflectionInfoStream>, Status> {
+        let (mut tx, rx): (
+            Sender<Result<ServerReflectionResponse, Status>>,
+            Receiver<Result<ServerReflectionResponse, Status>>,
+        ) = mpsc::channel(REPLY_BUFFER_SIZE);
+
+        let descriptors = self.descriptors.clone();
+        let mut requests = request.into_inner();
+        tokio::spawn(async move {
+            while let Some(Ok(request)) = requests.next().await {
+                let message_response = match request.message_request {
+                    Some(ref message) => descriptors.answer(message),
+                    None => MessageResponse::ErrorResponse(ErrorResponse {
+                        error_code: Code::InvalidArgument as i32,
+                        error_message: String::from("Empty request"),
+                    }),
+                };
+                let reply = ServerReflectionResponse {
+                    valid_host: request.host.clone(),
+                    original_request: Some(request),
+                    message_response: Some(message_response),
+                };
+                if tx.send(Ok(reply)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(rx))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::include::FILE_DESCRIPTOR_SET;
+
+    fn answer(request: MessageRequest) -> MessageResponse {
+        let reflection = Reflection::new(FILE_DESCRIPTOR_SET).unwrap();
+        reflection.descriptors.answer(&request)
+    }
+
+    fn file_names(response: MessageResponse) -> Vec<String> {
+        match response {
+            MessageResponse::FileDescriptorResponse(files) => files
+                .file_descriptor_proto
+                .iter()
+                .map(|f| String::from(FileDescriptorProto::decode(f.as_slice()).unwrap().name()))
+                .collect(),
+            _ => panic!("{}", "Should have been a file"),
+        }
+    }
+
+    #[test]
+    fn lists_services() {
+        match answer(MessageRequest::ListServices(String::new())) {
+            MessageResponse::ListServicesResponse(list) => {
+                let names: Vec<String> = list.service.into_iter().map(|s| s.name).collect();
+                assert!(names.contains(&String::from("rfid.ReadInfo")));
+                assert!(names.contains(&String::from("grpc.health.v1.Health")));
+                assert!(names.contains(&String::from("grpc.reflection.v1alpha.ServerReflection")));
+            }
+            _ => panic!("{}", "Should have listed the services"),
+        }
+    }
+
+    #[test]
+    fn files_by_symbol() {
+        for symbol in &[
+            "rfid.ReadInfo",
+            "rfid.ReadInfo.ReadUuid",
+            "rfid.StreamPayload",
+            "rfid.ClientActions",
+        ] {
+            let res = answer(MessageRequest::FileContainingSymbol(String::from(*symbol)));
+            assert_eq!(file_names(res), vec!["rfid.proto"]);
+        }
+        let res = answer(MessageRequest::FileContainingSymbol(String::from(
+            "grpc.health.v1.HealthCheckResponse.ServingStatus",
+        )));
+        assert_eq!(file_names(res), vec!["health.proto"]);
+
+        let res = answer(MessageRequest::FileByFilename(String::from("rfid.proto")));
+        assert_eq!(file_names(res), vec!["rfid.proto"]);
+    }
+
+    #[test]
+    fn unknown_symbol() {
+        match answer(MessageRequest::FileContainingSymbol(String::from(
+            "rfid.Nothing",
+        ))) {
+            MessageResponse::ErrorResponse(e) => assert_eq!(e.error_code, Code::NotFound as i32),
+            _ => panic!("{}", "Should have been an error"),
+        }
+    }
+}
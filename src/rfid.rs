@@ -1,88 +1,456 @@
 use super::include::read_info_server::ReadInfo;
-use super::include::{ClientActions, Empty, Payload, SingleBlockRequest, StreamPayload};
-use std::sync::Arc;
-use std::time::Duration;
+use super::include::{
+    ClientActions, DiagnosticsProgress, DiagnosticsRequest, DiagnosticsResult, Empty,
+    InventoryRequest, InventoryUpdate, LeaseRenewal, LeaseRequest, LeaseToken, LoggedEvent,
+    Payload, QueryEventsRequest, QueryEventsResponse, ReaderState, ReaderStatus,
+    SingleBlockRequest, StreamPayload, SubscribeRequest, TagEventKind,
+};
+use futures::future::poll_fn;
+use futures::FutureExt;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tonic::{Request, Response, Status, Streaming};
-
+use tracing::{Instrument, Span};
+
+use super::auth::IDENTITY_METADATA;
+use super::dedup::{Dedup, DedupMode, DedupOptions};
+use super::diagnostics::{SettingReport, Sweep, DEFAULT_ROUNDS, MAX_ROUNDS};
+use super::events::err::EventError;
+use super::events::{Event, EventLog, EventQuery, Operation, StoredEvent, DEFAULT_PAGE_SIZE};
+use super::flow::{Flow, FlowAction, FlowOptions, FlowState};
+use super::include;
+use super::inventory::{InventoryEvent, InventoryHub, InventoryOptions};
+use super::lease::err::LeaseError;
+use super::lease::{LeaseInfo, Leases, ReaderAccess};
+use super::logging;
+use super::metrics;
+use super::presence::{Presence, PresenceOptions, TagEvent};
+use super::queue::err::QueueError;
+use super::queue::Priority;
+use super::reader::err::ReaderError;
+use super::reader::rf::{ModulationDepth, OutputPower, ReaderProfile};
 use super::reader::ReaderTraits;
-use futures::lock::Mutex;
+use super::supervisor::{ConnectionStatus, ReaderLink};
 
 const MPSC_BUFFER_SIZE: usize = 0xFFFF;
+// kept small, a slow client should miss reads instead of queueing them here
+const INVENTORY_BUFFER_SIZE: usize = 16;
+const NO_READER: &str = "No reader connected";
+const LEASE_METADATA: &str = "rfid-lease";
 
-macro_rules! get_reader {
-    ($var:ident) => {{
-        match $var.reader.try_lock() {
-            Some(ret) => ret,
-            None => return Err(Status::internal("Unable to obtain reader")),
-        }
-    }};
-}
+type Result<T> = std::result::Result<T, Status>;
 
-macro_rules! get_reader_async {
-    ($var:ident) => {{
-        match $var.try_lock() {
-            Some(ret) => ret,
-            None => return Err(Status::internal("Unable to obtain reader")),
-        }
-    }};
+/* settings of the streaming calls */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RfidOptions {
+    pub inventory: InventoryOptions,
+    pub presence: PresenceOptions,
+    pub flow: FlowOptions,
+    // used unless a stream asks for its own
+    pub dedup: DedupOptions,
+    // what diagnostics leave the reader with
+    pub profile: ReaderProfile,
 }
 
-type Result<T> = std::result::Result<T, Status>;
-
 pub struct Rfid {
-    reader: Arc<Mutex<Box<dyn ReaderTraits>>>,
+    link: ReaderLink,
+    inventory: InventoryHub,
+    options: RfidOptions,
+    // none when the event log is disabled
+    events: Option<EventLog>,
 }
 
 impl Rfid {
+    #[cfg(test)]
     pub fn new(reader: Box<dyn ReaderTraits>) -> Rfid {
+        Rfid::supervised(
+            ReaderLink::fixed(Some(reader)),
+            RfidOptions::default(),
+            None,
+        )
+    }
+
+    #[cfg(test)]
+    pub fn with_events(reader: Box<dyn ReaderTraits>, events: EventLog) -> Rfid {
+        let link = ReaderLink::fixed(Some(reader));
+        Rfid::supervised(link, RfidOptions::default(), Some(events))
+    }
+
+    /* starts without hardware, every call is unavailable until a reader is put in the slot */
+    #[cfg(test)]
+    pub fn without_reader() -> Rfid {
+        Rfid::supervised(ReaderLink::fixed(None), RfidOptions::default(), None)
+    }
+
+    /* the reader is swapped in and out by a supervisor */
+    pub fn supervised(link: ReaderLink, options: RfidOptions, events: Option<EventLog>) -> Rfid {
         Rfid {
-            reader: Arc::new(Mutex::new(reader)),
+            inventory: InventoryHub::new(link.clone(), options.inventory.clone(), events.clone()),
+            link,
+            options,
+            events,
+        }
+    }
+
+    fn event_log(&self) -> Result<&EventLog> {
+        match self.events {
+            Some(ref events) => Ok(events),
+            None => Err(Status::failed_precondition("Event log is not enabled")),
         }
     }
+
+    #[cfg(test)]
+    pub fn reader_slot(&self) -> super::supervisor::ReaderSlot {
+        self.link.slot.clone()
+    }
+}
+
+fn connected(reader: &mut Option<Box<dyn ReaderTraits>>) -> Result<&mut Box<dyn ReaderTraits>> {
+    match reader.as_mut() {
+        Some(reader) => Ok(reader),
+        None => Err(Status::unavailable(NO_READER)),
+    }
+}
+
+fn queue_status(e: &QueueError) -> Status {
+    match *e {
+        QueueError::QueueFull(_) => Status::resource_exhausted(e.to_string()),
+        QueueError::DeadlineExceeded(_) => Status::deadline_exceeded(e.to_string()),
+    }
+}
+
+fn lease_status(e: &LeaseError) -> Status {
+    match *e {
+        LeaseError::Queue(ref e) => queue_status(e),
+        LeaseError::UnknownLease => Status::failed_precondition(e.to_string()),
+        LeaseError::InvalidTtl(_) => Status::invalid_argument(e.to_string()),
+    }
+}
+
+//waits in line for the reader, failing fast when the line is too long.
+//calls inside a lease skip the line, the lease already holds the reader
+async fn take_turn(
+    link: &ReaderLink,
+    lease: Option<&str>,
+    priority: Priority,
+    deadline: Option<Duration>,
+) -> Result<ReaderAccess> {
+    match lease {
+        Some(token) => link
+            .leases
+            .access(token)
+            .await
+            .map_err(|e| lease_status(&e)),
+        None => match link.acquire(priority, deadline).await {
+            Ok(turn) => Ok(ReaderAccess::Queued(turn)),
+            Err(e) => Err(queue_status(&e)),
+        },
+    }
 }
 
-//wait for message from client within certain timeout
+//the lease token the call runs in, if any
+fn request_lease<T>(request: &Request<T>) -> Option<String> {
+    let token = request.metadata().get(LEASE_METADATA)?.to_str().ok()?;
+    Some(String::from(token))
+}
+
+/* every tag a call reads passes through here */
+fn record(events: Option<&EventLog>, event: Event) {
+    metrics::tag_seen();
+    tracing::debug!(uid = logging::tag_data(&event.uid), "{}", "Tag read");
+    if let Some(events) = events {
+        events.record(event);
+    }
+}
+
+//who made the call, as recorded in the event log. the peer address when not authenticated
+fn client_identity<T>(request: &Request<T>) -> String {
+    let caller = request
+        .metadata()
+        .get(IDENTITY_METADATA)
+        .and_then(|v| v.to_str().ok());
+    if let Some(caller) = caller {
+        return String::from(caller);
+    }
+    match request.remote_addr() {
+        Some(addr) => addr.to_string(),
+        None => String::from("unknown"),
+    }
+}
+
+//the timeout the client set on the call, if any
+fn request_deadline<T>(request: &Request<T>) -> Option<Duration> {
+    let raw = request.metadata().get("grpc-timeout")?.to_str().ok()?;
+    if raw.len() < 2 {
+        return None;
+    }
+    let (value, unit) = raw.split_at(raw.len() - 1);
+    let value: u64 = value.parse().ok()?;
+
+    match unit {
+        "H" => Some(Duration::from_secs(value.saturating_mul(3600))),
+        "M" => Some(Duration::from_secs(value.saturating_mul(60))),
+        "S" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_millis(value)),
+        "u" => Some(Duration::from_micros(value)),
+        "n" => Some(Duration::from_nanos(value)),
+        _ => None,
+    }
+}
+
+//wait for message from client, within the timeout if there is one.
+//none once the client closed its side of the stream
 pub async fn get_client_message(
     request: &mut Streaming<StreamPayload>,
-    timeout_ms: u64,
-) -> Result<StreamPayload> {
-    let delayer = async {
-        match request.message().await {
-            Ok(payload) => match payload {
-                Some(msg) => Ok(msg),
-                None => Err(Status::invalid_argument("No message from client")),
-            },
-            Err(e) => Err(e),
+    timeout: Option<Duration>,
+) -> Result<Option<StreamPayload>> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return request.message().await,
+    };
+
+    match tokio::time::timeout(timeout, request.message()).await {
+        Ok(message) => message,
+        Err(_) => Err(Status::deadline_exceeded(
+            "No message received within deadline",
+        )),
+    }
+}
+
+//a lost device is unavailable until the supervisor reconnects it
+fn reader_status(e: &ReaderError) -> Status {
+    if e.is_device_lost() {
+        Status::unavailable(e.to_string())
+    } else {
+        Status::internal(e.to_string())
+    }
+}
+
+fn to_reader_status(status: &ConnectionStatus) -> ReaderStatus {
+    let state = if status.connected {
+        ReaderState::Connected
+    } else {
+        ReaderState::Disconnected
+    };
+    let timestamp = match status.since.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as u64,
+        Err(_) => 0,
+    };
+
+    ReaderStatus {
+        state: state as i32,
+        message: status.message.clone(),
+        timestamp,
+    }
+}
+
+fn to_lease(info: &LeaseInfo) -> include::Lease {
+    let expires_at = match info.expires.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as u64,
+        Err(_) => 0,
+    };
+
+    include::Lease {
+        token: info.token.clone(),
+        ttl_ms: info.ttl.as_millis() as u32,
+        expires_at,
+    }
+}
+
+fn to_dedup_options(settings: &include::Dedup, default: &DedupOptions) -> Result<DedupOptions> {
+    use include::DedupMode as Proto;
+
+    let mode = match Proto::from_i32(settings.mode) {
+        Some(Proto::Default) => default.mode,
+        Some(Proto::Off) => DedupMode::Off,
+        Some(Proto::Window) => DedupMode::Window,
+        Some(Proto::OnChange) => DedupMode::OnChange,
+        None => return Err(Status::invalid_argument("Unknown dedup mode")),
+    };
+    let window = match settings.window_ms {
+        0 => default.window,
+        ms => Duration::from_millis(ms as u64),
+    };
+    Ok(DedupOptions { mode, window })
+}
+
+fn to_inventory_update(event: &InventoryEvent, suppressed: u64) -> InventoryUpdate {
+    match *event {
+        InventoryEvent::Read(ref read) => {
+            let timestamp = match read.at.duration_since(UNIX_EPOCH) {
+                Ok(d) => d.as_millis() as u64,
+                Err(_) => 0,
+            };
+            InventoryUpdate {
+                sequence: read.sequence,
+                uid: read.uid.clone().unwrap_or_default(),
+                rssi: read.rssi.unwrap_or(0) as u32,
+                timestamp,
+                missed: 0,
+                suppressed,
+            }
         }
+        InventoryEvent::Gap(missed) => InventoryUpdate {
+            missed,
+            ..InventoryUpdate::default()
+        },
+    }
+}
+
+fn to_event_query(req: &QueryEventsRequest) -> Result<EventQuery> {
+    let after = match req.page_token.as_str() {
+        "" => 0,
+        token => token.parse().map_err(|_| {
+            let e = EventError::InvalidPageToken(String::from(token));
+            Status::invalid_argument(e.to_string())
+        })?,
+    };
+    let at = |ms: u64| match ms {
+        0 => None,
+        ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
     };
 
-    let timeout = Duration::from_millis(timeout_ms);
-    match tokio::time::timeout(timeout, delayer).await {
-        Ok(message) => match message {
-            Ok(ret) => return Ok(ret),
-            Err(e) => return Err(e),
+    Ok(EventQuery {
+        from: at(req.from),
+        to: at(req.to),
+        uid: Some(req.uid.clone()).filter(|uid| !uid.is_empty()),
+        after,
+        limit: match req.page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            size => size,
         },
-        Err(_) => {
-            return Err(Status::deadline_exceeded(
-                "No message received within deadline",
-            ))
+    })
+}
+
+fn to_logged_event(stored: &StoredEvent) -> LoggedEvent {
+    let event = &stored.event;
+    let operation = match event.operation {
+        Operation::Inventory => include::Operation::Inventory,
+        Operation::ReadUuid => include::Operation::ReadUuid,
+        Operation::ReadBlock => include::Operation::ReadBlock,
+    };
+    let timestamp = match event.at.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as u64,
+        Err(_) => 0,
+    };
+
+    LoggedEvent {
+        id: stored.id,
+        uid: event.uid.clone(),
+        operation: operation as i32,
+        block: event.block.unwrap_or(0),
+        data_hash: event.data_hash.clone().unwrap_or_default(),
+        rssi: event.rssi.unwrap_or(0) as u32,
+        timestamp,
+        client: event.client.clone(),
+    }
+}
+
+fn to_tag_event(event: &TagEvent) -> include::TagEvent {
+    let kind = match event.kind {
+        super::presence::TagEventKind::Arrived => TagEventKind::TagArrived,
+        super::presence::TagEventKind::Departed => TagEventKind::TagDeparted,
+    };
+    let timestamp = match event.at.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as u64,
+        Err(_) => 0,
+    };
+
+    include::TagEvent {
+        kind: kind as i32,
+        uid: event.uid.clone(),
+        timestamp,
+    }
+}
+
+fn to_output_power(power: i32) -> Result<OutputPower> {
+    match include::OutputPower::from_i32(power) {
+        Some(include::OutputPower::Full) => Ok(OutputPower::Full),
+        Some(include::OutputPower::Half) => Ok(OutputPower::Half),
+        None => Err(Status::invalid_argument("Unknown output power")),
+    }
+}
+
+fn to_modulation_depth(depth: i32) -> Result<ModulationDepth> {
+    use include::ModulationDepth as Proto;
+
+    match Proto::from_i32(depth) {
+        Some(Proto::Ask10) => Ok(ModulationDepth::Ask10),
+        Some(Proto::Ook100) => Ok(ModulationDepth::Ook100),
+        Some(Proto::Ask7) => Ok(ModulationDepth::Ask7),
+        Some(Proto::Ask85) => Ok(ModulationDepth::Ask8_5),
+        Some(Proto::Ask13) => Ok(ModulationDepth::Ask13),
+        Some(Proto::Ask16) => Ok(ModulationDepth::Ask16),
+        Some(Proto::Ask22) => Ok(ModulationDepth::Ask22),
+        Some(Proto::Ask30) => Ok(ModulationDepth::Ask30),
+        None => Err(Status::invalid_argument("Unknown modulation depth")),
+    }
+}
+
+fn to_diagnostics_result(report: &SettingReport) -> DiagnosticsResult {
+    let power = match report.setting.power {
+        OutputPower::Full => include::OutputPower::Full,
+        OutputPower::Half => include::OutputPower::Half,
+    };
+    let depth = {
+        use include::ModulationDepth as Proto;
+
+        match report.setting.depth {
+            ModulationDepth::Ask10 => Proto::Ask10,
+            ModulationDepth::Ook100 => Proto::Ook100,
+            ModulationDepth::Ask7 => Proto::Ask7,
+            ModulationDepth::Ask8_5 => Proto::Ask85,
+            ModulationDepth::Ask13 => Proto::Ask13,
+            ModulationDepth::Ask16 => Proto::Ask16,
+            ModulationDepth::Ask22 => Proto::Ask22,
+            ModulationDepth::Ask30 => Proto::Ask30,
         }
     };
+
+    DiagnosticsResult {
+        power: power as i32,
+        depth: depth as i32,
+        rounds: report.rounds,
+        reads: report.reads,
+        success_rate: report.success_rate(),
+        rssi_min: report.rssi_min as u32,
+        rssi_max: report.rssi_max as u32,
+        rssi_avg: report.rssi_avg(),
+    }
 }
 
 #[tonic::async_trait]
 impl ReadInfo for Rfid {
     type ReadUuidContinousStream = mpsc::Receiver<Result<Payload>>;
     type ReadBlockContinousStream = mpsc::Receiver<Result<Payload>>;
-
-    async fn read_uuid(&self, _request: Request<Empty>) -> Result<Response<Payload>> {
-        let mut reader = get_reader!(self);
-
-        match reader.read_uuid() {
-            Ok(uuid) => return Ok(Response::new(Payload { info: uuid })),
-            Err(e) => return Err(Status::internal(e.to_string())),
+    type RunAntennaDiagnosticsStream = mpsc::Receiver<Result<DiagnosticsProgress>>;
+    type WatchReaderStatusStream = mpsc::Receiver<Result<ReaderStatus>>;
+    type WatchTagsStream = mpsc::Receiver<Result<include::TagEvent>>;
+    type WatchInventoryStream = mpsc::Receiver<Result<InventoryUpdate>>;
+    type SubscribeStream = mpsc::Receiver<Result<LoggedEvent>>;
+
+    async fn read_uuid(&self, request: Request<Empty>) -> Result<Response<Payload>> {
+        let deadline = request_deadline(&request);
+        let lease = request_lease(&request);
+        let mut turn = take_turn(&self.link, lease.as_deref(), Priority::High, deadline).await?;
+        let reader = connected(&mut turn)?;
+
+        match reader.read_uuid().await {
+            Ok(uuid) => {
+                record(
+                    self.events.as_ref(),
+                    Event::new(Operation::ReadUuid, &uuid, &client_identity(&request)),
+                );
+                Ok(Response::new(Payload {
+                    info: uuid,
+                    suppressed: 0,
+                }))
+            }
+            Err(e) => {
+                self.link.check_lost(&mut turn, &e);
+                Err(reader_status(&e))
+            }
         }
     }
 
@@ -90,15 +458,29 @@ impl ReadInfo for Rfid {
         &self,
         request: Request<SingleBlockRequest>,
     ) -> Result<Response<Payload>> {
-        let mut reader = get_reader!(self);
-
-        match reader.read_single_block(request.get_ref().block_index) {
-            Ok(data) => return Ok(Response::new(Payload { info: data })),
-            Err(e) => return Err(Status::internal(e.to_string())),
+        let deadline = request_deadline(&request);
+        let lease = request_lease(&request);
+        let mut turn = take_turn(&self.link, lease.as_deref(), Priority::High, deadline).await?;
+        let reader = connected(&mut turn)?;
+
+        let block = request.get_ref().block_index;
+        match reader.read_single_block(block).await {
+            Ok((uuid, data)) => {
+                let event = Event::new(Operation::ReadBlock, &uuid, &client_identity(&request));
+                record(self.events.as_ref(), event.with_block(block, &data));
+                Ok(Response::new(Payload {
+                    info: data,
+                    suppressed: 0,
+                }))
+            }
+            Err(e) => {
+                self.link.check_lost(&mut turn, &e);
+                Err(reader_status(&e))
+            }
         }
     }
 
-    //bi-directional stream, wait for user to ack for after every read
+    //bi-directional stream, reads as many times as the client granted with its acks
     async fn read_uuid_continous(
         &self,
         mut request: Request<Streaming<StreamPayload>>,
@@ -106,56 +488,141 @@ impl ReadInfo for Rfid {
         let (mut tx, rx): (Sender<Result<Payload>>, Receiver<Result<Payload>>) =
             mpsc::channel(MPSC_BUFFER_SIZE);
 
-        let reader_arc = self.reader.clone();
-        tokio::spawn(async move {
-            loop {
-                /* wait for ack prior to starting read */
-                match get_client_message(request.get_mut(), 1000).await {
-                    Ok(message) => match message.action {
-                        act if act == ClientActions::Cancel as i32 => {
-                            let e = Status::cancelled("Cancelled by user");
-                            log::error!("{}", e.to_string());
-                            if let Err(send_err) = tx.send(Err(e)).await {
-                                log::error!("{}", send_err.to_string());
+        let lease = request_lease(&request);
+        let link = self.link.clone();
+        let options = self.options.clone();
+        let events = self.events.clone();
+        let client = client_identity(&request);
+        tokio::spawn(
+            async move {
+                let mut flow = Flow::new(options.flow.max_credits);
+                let mut dedup = Dedup::new(options.dedup.clone());
+                // the client closed its side, the reads already granted are still made
+                let mut closed = false;
+                loop {
+                    /* take in one message of the client per read, only waiting when there is
+                    nothing to read */
+                    let next = match flow.state() {
+                        _ if closed => None,
+                        FlowState::Ready => request.get_mut().message().now_or_never(),
+                        FlowState::Paused => {
+                            Some(get_client_message(request.get_mut(), None).await)
+                        }
+                        FlowState::Starved => Some(
+                            get_client_message(request.get_mut(), Some(options.flow.idle_timeout))
+                                .await,
+                        ),
+                    };
+                    match next {
+                        Some(Ok(Some(message))) => {
+                            if let Some(ref settings) = message.dedup {
+                                match to_dedup_options(settings, &options.dedup) {
+                                    Ok(o) => dedup = Dedup::new(o),
+                                    Err(e) => {
+                                        if let Err(send_err) = tx.send(Err(e)).await {
+                                            log::error!("{}", send_err);
+                                        }
+                                        break;
+                                    }
+                                }
+                            }
+                            match ClientActions::from_i32(message.action) {
+                                Some(ClientActions::Ack) => {
+                                    flow.apply(FlowAction::Ack(message.credits))
+                                }
+                                Some(ClientActions::Pause) => flow.apply(FlowAction::Pause),
+                                Some(ClientActions::Resume) => flow.apply(FlowAction::Resume),
+                                Some(ClientActions::Cancel) => {
+                                    let e = Status::cancelled("Cancelled by user");
+                                    log::info!("{}", e);
+                                    if let Err(send_err) = tx.send(Err(e)).await {
+                                        log::error!("{}", send_err);
+                                    }
+                                    break;
+                                }
+                                Some(ClientActions::Unknown) | None => {
+                                    let e = Status::invalid_argument("Unknown user action");
+                                    log::error!("{}", e);
+                                    if let Err(send_err) = tx.send(Err(e)).await {
+                                        log::error!("{}", send_err);
+                                    }
+                                    break;
+                                }
                             }
-                            break;
                         }
-                        act if act == ClientActions::Unknown as i32 => {
-                            let e = Status::invalid_argument("Unknown user action");
-                            log::error!("{}", e.to_string());
-                            if let Err(send_err) = tx.send(Err(e)).await {
-                                log::error!("{}", send_err.to_string());
+                        Some(Ok(None)) => closed = true,
+                        Some(Err(e)) => {
+                            if let Err(err) = tx.send(Err(e)).await {
+                                log::error!("{}", err);
                             }
                             break;
                         }
-                        _ => {}
-                    },
-                    Err(e) => {
-                        if let Err(err) = tx.send(Err(e)).await {
-                            log::error!("{}", err.to_string());
-                        }
-                        break;
+                        None => {}
                     }
-                }
 
-                let mut reader = get_reader_async!(reader_arc);
-                match reader.read_uuid() {
-                    Ok(uuid) => {
-                        if let Err(e) = tx.send(Ok(Payload { info: uuid })).await {
-                            log::error!("{}", e.to_string());
-                            return Err(Status::internal(e.to_string()));
+                    if !flow.spend() {
+                        if closed {
+                            break;
                         }
+                        continue;
                     }
-                    Err(e) => {
-                        if let Err(e) = tx.send(Err(Status::internal(e.to_string()))).await {
-                            log::error!("{}", e.to_string());
+
+                    /* one turn per read, so other clients get the reader in between */
+                    let mut turn =
+                        match take_turn(&link, lease.as_deref(), Priority::Normal, None).await {
+                            Ok(turn) => turn,
+                            Err(e) => {
+                                if let Err(send_err) = tx.send(Err(e.clone())).await {
+                                    log::error!("{}", send_err);
+                                }
+                                return Err(e);
+                            }
+                        };
+                    let reader = match connected(&mut turn) {
+                        Ok(reader) => reader,
+                        Err(e) => {
+                            if let Err(send_err) = tx.send(Err(e.clone())).await {
+                                log::error!("{}", send_err);
+                            }
+                            return Err(e);
+                        }
+                    };
+                    match reader.read_uuid().await {
+                        Ok(uuid) => {
+                            record(
+                                events.as_ref(),
+                                Event::new(Operation::ReadUuid, &uuid, &client),
+                            );
+                            /* a suppressed read does not use up the client's credit */
+                            let suppressed = match dedup.check(Some(&uuid), Instant::now()) {
+                                Some(suppressed) => suppressed,
+                                None => {
+                                    flow.refund();
+                                    continue;
+                                }
+                            };
+                            let payload = Payload {
+                                info: uuid,
+                                suppressed,
+                            };
+                            if let Err(e) = tx.send(Ok(payload)).await {
+                                log::error!("{}", e);
+                                return Err(Status::internal(e.to_string()));
+                            }
+                        }
+                        Err(e) => {
+                            link.check_lost(&mut turn, &e);
+                            if let Err(e) = tx.send(Err(reader_status(&e))).await {
+                                log::error!("{}", e);
+                            }
+                            return Err(reader_status(&e));
                         }
-                        return Err(Status::internal(e.to_string()));
                     }
                 }
+                Ok(())
             }
-            Ok(())
-        });
+            .instrument(Span::current()),
+        );
 
         Ok(Response::new(rx))
     }
@@ -167,12 +634,330 @@ impl ReadInfo for Rfid {
         //TODO: will be similar to the read uuid continous version
         Err(Status::invalid_argument("123"))
     }
+
+    //current connection state first, then every change
+    async fn watch_reader_status(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::WatchReaderStatusStream>> {
+        let (mut tx, rx): (Sender<Result<ReaderStatus>>, Receiver<Result<ReaderStatus>>) =
+            mpsc::channel(MPSC_BUFFER_SIZE);
+
+        let mut status = self.link.status();
+        tokio::spawn(
+            async move {
+                while let Some(s) = status.recv().await {
+                    if let Err(e) = tx.send(Ok(to_reader_status(&s))).await {
+                        log::error!("{}", e);
+                        break;
+                    }
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        Ok(Response::new(rx))
+    }
+
+    //every read of the shared inventory loop, with a gap message if the client falls behind
+    async fn watch_inventory(
+        &self,
+        request: Request<InventoryRequest>,
+    ) -> Result<Response<Self::WatchInventoryStream>> {
+        let dedup = match request.get_ref().dedup {
+            Some(ref settings) => to_dedup_options(settings, &self.options.dedup)?,
+            None => self.options.dedup.clone(),
+        };
+        let mut dedup = Dedup::new(dedup);
+
+        let (mut tx, rx): (
+            Sender<Result<InventoryUpdate>>,
+            Receiver<Result<InventoryUpdate>>,
+        ) = mpsc::channel(INVENTORY_BUFFER_SIZE);
+
+        let mut inventory = self.inventory.subscribe();
+        tokio::spawn(
+            async move {
+                while let Some(event) = inventory.next().await {
+                    let suppressed = match event {
+                        InventoryEvent::Read(ref read) => {
+                            match dedup.check(read.uid.as_deref(), Instant::now()) {
+                                Some(suppressed) => suppressed,
+                                None => continue,
+                            }
+                        }
+                        InventoryEvent::Gap(_) => 0,
+                    };
+                    if let Err(e) = tx.send(Ok(to_inventory_update(&event, suppressed))).await {
+                        log::error!("{}", e);
+                        break;
+                    }
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        Ok(Response::new(rx))
+    }
+
+    //follows the shared inventory loop, sending a message whenever a tag arrives or leaves
+    async fn watch_tags(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::WatchTagsStream>> {
+        let (mut tx, rx): (
+            Sender<Result<include::TagEvent>>,
+            Receiver<Result<include::TagEvent>>,
+        ) = mpsc::channel(MPSC_BUFFER_SIZE);
+
+        let mut inventory = self.inventory.subscribe();
+        let mut presence = Presence::new(self.options.presence.departure_timeout);
+        tokio::spawn(
+            async move {
+                /* unsubscribe once the client is gone, even if no tag ever shows up */
+                while poll_fn(|cx| tx.poll_ready(cx)).await.is_ok() {
+                    let uid = match inventory.next().await {
+                        Some(InventoryEvent::Read(read)) => read.uid,
+                        // missed reads only delay a departure
+                        Some(InventoryEvent::Gap(missed)) => {
+                            log::debug!("Tag watcher missed {} reads", missed);
+                            continue;
+                        }
+                        None => break,
+                    };
+
+                    for event in presence.observe(uid.as_deref(), Instant::now()) {
+                        if let Err(e) = tx.send(Ok(to_tag_event(&event))).await {
+                            log::error!("{}", e);
+                            return;
+                        }
+                    }
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        Ok(Response::new(rx))
+    }
+
+    //pages through the event log, oldest first
+    async fn query_events(
+        &self,
+        request: Request<QueryEventsRequest>,
+    ) -> Result<Response<QueryEventsResponse>> {
+        let query = to_event_query(request.get_ref())?;
+
+        match self.event_log()?.query(query).await {
+            Ok(page) => Ok(Response::new(QueryEventsResponse {
+                events: page.events.iter().map(to_logged_event).collect(),
+                next_page_token: page.next.map(|id| id.to_string()).unwrap_or_default(),
+            })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    //streams the event log from a sequence on, then every new event as it is recorded
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>> {
+        // sequences start at one, zero replays everything kept
+        let after = match request.get_ref().from_sequence {
+            0 => None,
+            from => Some(from - 1),
+        };
+        let mut replay = self.event_log()?.replay(after);
+
+        let (mut tx, rx): (Sender<Result<LoggedEvent>>, Receiver<Result<LoggedEvent>>) =
+            mpsc::channel(INVENTORY_BUFFER_SIZE);
+        tokio::spawn(
+            async move {
+                while poll_fn(|cx| tx.poll_ready(cx)).await.is_ok() {
+                    let message = match replay.next().await {
+                        Ok(stored) => Ok(to_logged_event(&stored)),
+                        Err(e @ EventError::Pruned(_)) => Err(Status::out_of_range(e.to_string())),
+                        Err(e) => Err(Status::unavailable(e.to_string())),
+                    };
+                    let failed = message.is_err();
+                    if let Err(e) = tx.send(message).await {
+                        log::error!("{}", e);
+                        return;
+                    }
+                    if failed {
+                        return;
+                    }
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        Ok(Response::new(rx))
+    }
+
+    //holds the reader for the caller until released or no longer renewed
+    async fn acquire_lease(
+        &self,
+        request: Request<LeaseRequest>,
+    ) -> Result<Response<include::Lease>> {
+        let deadline = request_deadline(&request);
+        let ttl = Leases::ttl(request.get_ref().ttl_ms as u64).map_err(|e| lease_status(&e))?;
+
+        match self.link.leases.acquire(ttl, deadline).await {
+            Ok(info) => Ok(Response::new(to_lease(&info))),
+            Err(e) => Err(lease_status(&e)),
+        }
+    }
+
+    async fn renew_lease(
+        &self,
+        request: Request<LeaseRenewal>,
+    ) -> Result<Response<include::Lease>> {
+        let req = request.get_ref();
+        let ttl = Leases::ttl(req.ttl_ms as u64).map_err(|e| lease_status(&e))?;
+
+        match self.link.leases.renew(&req.token, ttl) {
+            Ok(info) => Ok(Response::new(to_lease(&info))),
+            Err(e) => Err(lease_status(&e)),
+        }
+    }
+
+    async fn release_lease(&self, request: Request<LeaseToken>) -> Result<Response<Empty>> {
+        match self.link.leases.release(&request.get_ref().token) {
+            Ok(()) => Ok(Response::new(Empty {})),
+            Err(e) => Err(lease_status(&e)),
+        }
+    }
+
+    //sweeps the rf settings, sending a progress message after every setting
+    async fn run_antenna_diagnostics(
+        &self,
+        request: Request<DiagnosticsRequest>,
+    ) -> Result<Response<Self::RunAntennaDiagnosticsStream>> {
+        let lease = request_lease(&request);
+        let req = request.into_inner();
+        let rounds = match req.rounds {
+            0 => DEFAULT_ROUNDS,
+            r if r > MAX_ROUNDS => {
+                return Err(Status::invalid_argument(format!(
+                    "Rounds must not exceed {}",
+                    MAX_ROUNDS
+                )))
+            }
+            r => r,
+        };
+        let powers = req
+            .powers
+            .iter()
+            .map(|p| to_output_power(*p))
+            .collect::<Result<Vec<OutputPower>>>()?;
+        let depths = req
+            .depths
+            .iter()
+            .map(|d| to_modulation_depth(*d))
+            .collect::<Result<Vec<ModulationDepth>>>()?;
+        let mut sweep = Sweep::new(&powers, &depths, rounds);
+
+        let (mut tx, rx): (
+            Sender<Result<DiagnosticsProgress>>,
+            Receiver<Result<DiagnosticsProgress>>,
+        ) = mpsc::channel(MPSC_BUFFER_SIZE);
+
+        let link = self.link.clone();
+        let profile = self.options.profile;
+        tokio::spawn(
+            async move {
+                /* the whole sweep runs in one turn, nobody else may see the changed settings */
+                let mut turn = match take_turn(&link, lease.as_deref(), Priority::Low, None).await {
+                    Ok(turn) => turn,
+                    Err(e) => {
+                        if let Err(send_err) = tx.send(Err(e)).await {
+                            log::error!("{}", send_err);
+                        }
+                        return;
+                    }
+                };
+                let reader = match connected(&mut turn) {
+                    Ok(reader) => reader,
+                    Err(e) => {
+                        if let Err(send_err) = tx.send(Err(e)).await {
+                            log::error!("{}", send_err);
+                        }
+                        return;
+                    }
+                };
+
+                let total = sweep.total() as u32;
+                let mut aborted = false;
+                let mut failure = None;
+                while let Some(res) = sweep.step(&mut **reader).await {
+                    let msg = match res {
+                        Ok(report) => Ok(DiagnosticsProgress {
+                            completed: sweep.completed() as u32,
+                            total,
+                            result: Some(to_diagnostics_result(&report)),
+                            done: false,
+                            best: None,
+                        }),
+                        Err(e) => {
+                            let status = reader_status(&e);
+                            failure = Some(e);
+                            Err(status)
+                        }
+                    };
+                    aborted = msg.is_err();
+
+                    /* stop sweeping once the client is gone */
+                    if let Err(e) = tx.send(msg).await {
+                        log::error!("{}", e);
+                        aborted = true;
+                    }
+                    if aborted {
+                        break;
+                    }
+                }
+
+                /* nothing left to restore on a device that is gone */
+                if let Some(e) = failure.filter(|e| e.is_device_lost()) {
+                    link.check_lost(&mut turn, &e);
+                    return;
+                }
+
+                if let Err(e) = Sweep::restore(&mut **reader, &profile).await {
+                    log::error!("{}", e);
+                    link.check_lost(&mut turn, &e);
+                    if !aborted {
+                        if let Err(send_err) = tx.send(Err(Status::internal(e.to_string()))).await {
+                            log::error!("{}", send_err);
+                        }
+                    }
+                    return;
+                }
+
+                if !aborted {
+                    let done = DiagnosticsProgress {
+                        completed: total,
+                        total,
+                        result: None,
+                        done: true,
+                        best: sweep.best().map(to_diagnostics_result),
+                    };
+                    if let Err(e) = tx.send(Ok(done)).await {
+                        log::error!("{}", e);
+                    }
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        Ok(Response::new(rx))
+    }
 }
 
 #[cfg(test)]
 mod test {
 
     use super::*;
+    use crate::events::EventOptions;
     use crate::reader::err::ReaderError;
     use crate::reader::MockReaderTraits;
     use crate::scaffold::scaffold::*;
@@ -181,22 +966,21 @@ mod test {
     use mockall::{predicate::eq, Sequence};
     use rand::distributions::Alphanumeric;
     use rand::{thread_rng, Rng};
-    use serial_test::*;
+    use std::path::PathBuf;
 
     #[tokio::test]
-    #[serial]
     async fn read_uuid_serial_error() {
         let mut reader = MockReaderTraits::new();
         reader.expect_read_uuid().returning(|| {
             Err(ReaderError::SerialError(
-                SerialError::NoReplyAfterMultipleTries,
+                SerialError::NoReplyAfterMultipleTries(vec![]),
             ))
         });
 
         let rfid = Rfid::new(Box::new(reader));
 
         let ts = TestStruct::new(rfid).await;
-        let mut client = start_client().await;
+        let mut client = ts.client().await;
 
         let res = client.read_uuid(Request::new(Empty {})).await;
         ts.end().await;
@@ -208,7 +992,6 @@ mod test {
     }
 
     #[tokio::test]
-    #[serial]
     async fn read_uuid_ok() {
         let mut reader = MockReaderTraits::new();
         reader
@@ -218,17 +1001,16 @@ mod test {
         let rfid = Rfid::new(Box::new(reader));
 
         let ts = TestStruct::new(rfid).await;
-        let mut client = start_client().await;
+        let mut client = ts.client().await;
 
         let res = client.read_uuid(Request::new(Empty {})).await;
         ts.end().await;
 
-        assert!(!res.is_err());
+        assert!(res.is_ok());
         assert_eq!(res.unwrap().get_ref().info, "CAFEDEADBEEFB0B0");
     }
 
     #[tokio::test]
-    #[serial]
     async fn read_single_block_serial_error() {
         let block_idx: u32 = 255;
         let mut reader = MockReaderTraits::new();
@@ -238,14 +1020,14 @@ mod test {
             .with(eq(block_idx))
             .returning(|_| {
                 Err(ReaderError::SerialError(
-                    SerialError::NoReplyAfterMultipleTries,
+                    SerialError::NoReplyAfterMultipleTries(vec![]),
                 ))
             });
 
         let rfid = Rfid::new(Box::new(reader));
 
         let ts = TestStruct::new(rfid).await;
-        let mut client = start_client().await;
+        let mut client = ts.client().await;
 
         let res = client
             .read_single_block(Request::new(SingleBlockRequest {
@@ -262,7 +1044,6 @@ mod test {
     }
 
     #[tokio::test]
-    #[serial]
     async fn read_single_block_ok() {
         let block_idx: u32 = 255;
         let mut reader = MockReaderTraits::new();
@@ -270,12 +1051,12 @@ mod test {
         reader
             .expect_read_single_block()
             .with(eq(block_idx))
-            .returning(|_| Ok(String::from("12345678")));
+            .returning(|_| Ok((String::from("CAFEDEADBEEFB0B0"), String::from("12345678"))));
 
         let rfid = Rfid::new(Box::new(reader));
 
         let ts = TestStruct::new(rfid).await;
-        let mut client = start_client().await;
+        let mut client = ts.client().await;
 
         let res = client
             .read_single_block(Request::new(SingleBlockRequest {
@@ -285,13 +1066,12 @@ mod test {
 
         ts.end().await;
 
-        assert!(!res.is_err());
+        assert!(res.is_ok());
         assert_eq!(res.unwrap().get_ref().info, "12345678");
     }
 
     /* tests 1000 calls with correct acks*/
     #[tokio::test]
-    #[serial]
     async fn read_uuid_continuous_ok() {
         let mut reader = MockReaderTraits::new();
         let mut v: Vec<String> = Vec::new();
@@ -315,13 +1095,15 @@ mod test {
         let rfid = Rfid::new(Box::new(reader));
 
         let ts = TestStruct::new(rfid).await;
-        let mut client = start_client().await;
+        let mut client = ts.client().await;
 
         let mut requests: Vec<StreamPayload> = Vec::new();
         for _ in 0..n {
             let sp = StreamPayload {
                 action: ClientActions::Ack as i32,
                 request: 0,
+                credits: 0,
+                dedup: None,
             };
             requests.push(sp)
         }
@@ -335,17 +1117,9 @@ mod test {
         let mut payloads: Vec<Payload> = Vec::new();
         ts.end().await;
 
-        loop {
-            match res.message().await {
-                Ok(val) => {
-                    if let Some(payload) = val {
-                        payloads.push(payload);
-                    }
-                    continue;
-                }
-                Err(_) => {
-                    break;
-                }
+        while let Ok(val) = res.message().await {
+            if let Some(payload) = val {
+                payloads.push(payload);
             }
         }
 
@@ -353,56 +1127,206 @@ mod test {
         assert_eq!(infos, v);
     }
 
-    #[tokio::test]
-    #[serial]
-    async fn read_uuid_unknown_action_at_start() {
-        let reader = MockReaderTraits::new();
+    fn ack(credits: u32) -> StreamPayload {
+        StreamPayload {
+            action: ClientActions::Ack as i32,
+            request: 0,
+            credits,
+            dedup: None,
+        }
+    }
 
-        let rfid = Rfid::new(Box::new(reader));
+    fn action(action: ClientActions) -> StreamPayload {
+        StreamPayload {
+            action: action as i32,
+            request: 0,
+            credits: 0,
+            dedup: None,
+        }
+    }
 
-        let ts = TestStruct::new(rfid).await;
-        let mut client = start_client().await;
+    #[tokio::test]
+    async fn read_uuid_continuous_credits() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .times(5)
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
 
-        let sp = StreamPayload {
-            action: ClientActions::Unknown as i32,
-            request: 0,
-        };
-        let stream = stream::iter(vec![sp]);
+        let ts = TestStruct::new(Rfid::new(Box::new(reader))).await;
+        let mut client = ts.client().await;
 
+        // one ack for five reads, then the client goes quiet without closing the stream
+        let (mut acks, stream) = mpsc::channel(4);
+        acks.send(ack(5)).await.unwrap();
         let mut res = client
             .read_uuid_continous(Request::new(stream))
             .await
             .unwrap()
             .into_inner();
 
-        ts.end().await;
-
-        loop {
+        let mut reads = 0;
+        let end = loop {
             match res.message().await {
-                Ok(_) => {
-                    continue;
-                }
-                Err(e) => {
-                    assert_eq!(e.code(), tonic::Code::InvalidArgument);
-                    assert!(e.message().contains("Unknown user action"));
-                    break;
-                }
+                Ok(Some(_)) => reads += 1,
+                Ok(None) => panic!("{}", "Should have ended with an error"),
+                Err(e) => break e,
             }
-        }
+        };
+        ts.end().await;
+
+        assert_eq!(reads, 5);
+        assert_eq!(end.code(), tonic::Code::DeadlineExceeded);
+    }
+
+    #[tokio::test]
+    async fn read_uuid_continuous_pause_resume() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .times(2)
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+
+        let ts = TestStruct::new(Rfid::new(Box::new(reader))).await;
+        let mut client = ts.client().await;
+
+        let (mut actions, stream) = mpsc::channel(4);
+        actions.send(action(ClientActions::Pause)).await.unwrap();
+        actions.send(ack(2)).await.unwrap();
+        let mut res = client
+            .read_uuid_continous(Request::new(stream))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // paused streams outlive the idle timeout
+        let idle = FlowOptions::default().idle_timeout + Duration::from_millis(200);
+        assert!(tokio::time::timeout(idle, res.message()).await.is_err());
+
+        actions.send(action(ClientActions::Resume)).await.unwrap();
+        assert!(res.message().await.unwrap().is_some());
+        assert!(res.message().await.unwrap().is_some());
+
+        actions.send(action(ClientActions::Cancel)).await.unwrap();
+        let end = res.message().await.unwrap_err();
+        ts.end().await;
+
+        assert_eq!(end.code(), tonic::Code::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn read_uuid_cancel_stops_granted_reads() {
+        // a single read at most, the cancel is taken in right after it
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .times(0..=1)
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+
+        let ts = TestStruct::new(Rfid::new(Box::new(reader))).await;
+        let mut client = ts.client().await;
+
+        let stream = stream::iter(vec![ack(1000), action(ClientActions::Cancel)]);
+        let mut res = client
+            .read_uuid_continous(Request::new(stream))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let mut reads = 0;
+        let end = loop {
+            match res.message().await {
+                Ok(Some(_)) => reads += 1,
+                Ok(None) => panic!("{}", "Should have been cancelled"),
+                Err(e) => break e,
+            }
+        };
+        ts.end().await;
+
+        assert!(reads <= 1);
+        assert_eq!(end.code(), tonic::Code::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn read_uuid_half_close_ends_stream() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .times(3)
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+
+        let ts = TestStruct::new(Rfid::new(Box::new(reader))).await;
+        let mut client = ts.client().await;
+
+        // the granted reads are still made after the client closed its side
+        let stream = stream::iter(vec![ack(3)]);
+        let mut res = client
+            .read_uuid_continous(Request::new(stream))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let mut reads = 0;
+        while res.message().await.unwrap().is_some() {
+            reads += 1;
+        }
+        ts.end().await;
+
+        assert_eq!(reads, 3);
+    }
+
+    #[tokio::test]
+    async fn read_uuid_unknown_action_at_start() {
+        let reader = MockReaderTraits::new();
+
+        let rfid = Rfid::new(Box::new(reader));
+
+        let ts = TestStruct::new(rfid).await;
+        let mut client = ts.client().await;
+
+        let sp = StreamPayload {
+            action: ClientActions::Unknown as i32,
+            request: 0,
+            credits: 0,
+            dedup: None,
+        };
+        let stream = stream::iter(vec![sp]);
+
+        let mut res = client
+            .read_uuid_continous(Request::new(stream))
+            .await
+            .unwrap()
+            .into_inner();
+
+        ts.end().await;
+
+        loop {
+            match res.message().await {
+                Ok(_) => {
+                    continue;
+                }
+                Err(e) => {
+                    assert_eq!(e.code(), tonic::Code::InvalidArgument);
+                    assert!(e.message().contains("Unknown user action"));
+                    break;
+                }
+            }
+        }
     }
 
     #[tokio::test]
-    #[serial]
     async fn read_uuid_cancelled_at_start() {
         let reader = MockReaderTraits::new();
         let rfid = Rfid::new(Box::new(reader));
 
         let ts = TestStruct::new(rfid).await;
-        let mut client = start_client().await;
+        let mut client = ts.client().await;
 
         let sp = StreamPayload {
             action: ClientActions::Cancel as i32,
             request: 0,
+            credits: 0,
+            dedup: None,
         };
         let stream = stream::iter(vec![sp]);
 
@@ -430,7 +1354,6 @@ mod test {
 
     /* tests 1000 calls with correct acks with cancellation request at the end*/
     #[tokio::test]
-    #[serial]
     async fn read_uuid_n_packets_cancel_end() {
         let mut reader = MockReaderTraits::new();
         let mut v: Vec<String> = Vec::new();
@@ -454,13 +1377,15 @@ mod test {
         let rfid = Rfid::new(Box::new(reader));
 
         let ts = TestStruct::new(rfid).await;
-        let mut client = start_client().await;
+        let mut client = ts.client().await;
 
         let mut requests: Vec<StreamPayload> = Vec::new();
         for _ in 0..n {
             let sp = StreamPayload {
                 action: ClientActions::Ack as i32,
                 request: 0,
+                credits: 0,
+                dedup: None,
             };
             requests.push(sp)
         }
@@ -469,6 +1394,8 @@ mod test {
         requests.push(StreamPayload {
             action: ClientActions::Cancel as i32,
             request: 0,
+            credits: 0,
+            dedup: None,
         });
 
         let stream = stream::iter(requests);
@@ -500,4 +1427,640 @@ mod test {
         let infos: Vec<String> = payloads.into_iter().map(|p| p.info).collect();
         assert_eq!(infos, v);
     }
+
+    #[tokio::test]
+    async fn antenna_diagnostics_ok() {
+        let mut reader = MockReaderTraits::new();
+        reader.expect_set_output_power().returning(|_| Ok(()));
+        reader.expect_set_modulation().returning(|_| Ok(()));
+        reader
+            .expect_read_uuid_rssi()
+            .times(4)
+            .returning(|| Ok((String::from("CAFEDEADBEEFB0B0"), 0x12)));
+
+        let rfid = Rfid::new(Box::new(reader));
+
+        let ts = TestStruct::new(rfid).await;
+        let mut client = ts.client().await;
+
+        let mut res = client
+            .run_antenna_diagnostics(Request::new(DiagnosticsRequest {
+                rounds: 2,
+                powers: vec![include::OutputPower::Full as i32],
+                depths: vec![
+                    include::ModulationDepth::Ask10 as i32,
+                    include::ModulationDepth::Ask30 as i32,
+                ],
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        ts.end().await;
+
+        let mut progress: Vec<DiagnosticsProgress> = Vec::new();
+        while let Ok(Some(msg)) = res.message().await {
+            progress.push(msg);
+        }
+
+        assert_eq!(progress.len(), 3);
+        assert_eq!(progress[0].completed, 1);
+        assert_eq!(progress[0].total, 2);
+
+        let first = progress[0].result.as_ref().unwrap();
+        assert_eq!(first.reads, 2);
+        assert_eq!(first.rssi_max, 0x12);
+        assert_eq!(first.depth, include::ModulationDepth::Ask10 as i32);
+
+        assert!(progress[2].done);
+        assert!(progress[2].best.is_some());
+    }
+
+    #[tokio::test]
+    async fn antenna_diagnostics_too_many_rounds() {
+        let reader = MockReaderTraits::new();
+        let rfid = Rfid::new(Box::new(reader));
+
+        let ts = TestStruct::new(rfid).await;
+        let mut client = ts.client().await;
+
+        let res = client
+            .run_antenna_diagnostics(Request::new(DiagnosticsRequest {
+                rounds: MAX_ROUNDS + 1,
+                powers: vec![],
+                depths: vec![],
+            }))
+            .await;
+        ts.end().await;
+
+        match res {
+            Ok(_) => panic!("{}", "Should have been an error"),
+            Err(e) => assert_eq!(e.code(), tonic::Code::InvalidArgument),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_uuid_without_reader() {
+        let rfid = Rfid::without_reader();
+
+        let ts = TestStruct::new(rfid).await;
+        let mut client = ts.client().await;
+
+        let res = client.read_uuid(Request::new(Empty {})).await;
+        ts.end().await;
+
+        match res {
+            Ok(_) => panic!("{}", "Should have been an error"),
+            Err(e) => assert_eq!(e.code(), tonic::Code::Unavailable),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_uuid_waits_for_turn() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+
+        let rfid = Rfid::new(Box::new(reader));
+        let turn = rfid.link.acquire(Priority::Normal, None).await.unwrap();
+
+        let ts = TestStruct::new(rfid).await;
+        let mut client = ts.client().await;
+
+        // another request holds the reader for a while
+        tokio::spawn(async move {
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            drop(turn);
+        });
+        let res = client.read_uuid(Request::new(Empty {})).await;
+        ts.end().await;
+
+        assert_eq!(res.unwrap().get_ref().info, "CAFEDEADBEEFB0B0");
+    }
+
+    // the tonic client strips grpc-timeout, so the handler is called directly
+    #[tokio::test]
+    async fn read_uuid_deadline_while_queued() {
+        let rfid = Rfid::new(Box::new(MockReaderTraits::new()));
+        let turn = rfid.link.acquire(Priority::Normal, None).await.unwrap();
+
+        let mut request = Request::new(Empty {});
+        request
+            .metadata_mut()
+            .insert("grpc-timeout", "50m".parse().unwrap());
+        let res = rfid.read_uuid(request).await;
+        drop(turn);
+
+        assert_eq!(res.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+        assert_eq!(rfid.link.queue_stats().expired, 1);
+    }
+
+    #[test]
+    fn request_deadline_units() {
+        let with_timeout = |raw: &str| {
+            let mut request = Request::new(Empty {});
+            request
+                .metadata_mut()
+                .insert("grpc-timeout", raw.parse().unwrap());
+            request_deadline(&request)
+        };
+        assert_eq!(with_timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(with_timeout("2S"), Some(Duration::from_secs(2)));
+        assert_eq!(with_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(with_timeout("10x"), None);
+        assert_eq!(with_timeout("m"), None);
+        assert_eq!(request_deadline(&Request::new(Empty {})), None);
+    }
+
+    #[tokio::test]
+    async fn lease_skips_the_queue() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .times(1)
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+
+        let ts = TestStruct::new(Rfid::new(Box::new(reader))).await;
+        let mut client = ts.client().await;
+
+        let lease = client
+            .acquire_lease(Request::new(LeaseRequest { ttl_ms: 5000 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(lease.ttl_ms, 5000);
+
+        let mut inside = Request::new(Empty {});
+        inside
+            .metadata_mut()
+            .insert(LEASE_METADATA, lease.token.parse().unwrap());
+        let res = client.read_uuid(inside).await;
+        assert_eq!(res.unwrap().get_ref().info, "CAFEDEADBEEFB0B0");
+
+        client
+            .release_lease(Request::new(LeaseToken {
+                token: lease.token.clone(),
+            }))
+            .await
+            .unwrap();
+        let renewed = client
+            .renew_lease(Request::new(LeaseRenewal {
+                token: lease.token,
+                ttl_ms: 5000,
+            }))
+            .await;
+        ts.end().await;
+
+        assert_eq!(renewed.unwrap_err().code(), tonic::Code::FailedPrecondition);
+    }
+
+    // the tonic client strips grpc-timeout, so the handler is called directly
+    #[tokio::test]
+    async fn leased_reader_waits_for_others() {
+        let rfid = Rfid::new(Box::new(MockReaderTraits::new()));
+        let lease = rfid
+            .acquire_lease(Request::new(LeaseRequest { ttl_ms: 0 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(lease.ttl_ms as u64, crate::lease::DEFAULT_TTL_MS);
+
+        let mut request = Request::new(Empty {});
+        request
+            .metadata_mut()
+            .insert("grpc-timeout", "50m".parse().unwrap());
+        let res = rfid.read_uuid(request).await;
+
+        assert_eq!(res.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+    }
+
+    #[tokio::test]
+    async fn lease_errors() {
+        let rfid = Rfid::new(Box::new(MockReaderTraits::new()));
+
+        let mut request = Request::new(Empty {});
+        request
+            .metadata_mut()
+            .insert(LEASE_METADATA, "nope".parse().unwrap());
+        let res = rfid.read_uuid(request).await;
+        assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+
+        let res = rfid
+            .acquire_lease(Request::new(LeaseRequest {
+                ttl_ms: crate::lease::MAX_TTL_MS as u32 + 1,
+            }))
+            .await;
+        assert_eq!(res.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn read_uuid_after_reader_attached() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+
+        let rfid = Rfid::without_reader();
+        let slot = rfid.reader_slot();
+
+        let ts = TestStruct::new(rfid).await;
+        let mut client = ts.client().await;
+
+        let before = client.read_uuid(Request::new(Empty {})).await;
+        *slot.lock().await = Some(Box::new(reader));
+        let after = client.read_uuid(Request::new(Empty {})).await;
+        ts.end().await;
+
+        assert_eq!(before.unwrap_err().code(), tonic::Code::Unavailable);
+        assert_eq!(after.unwrap().get_ref().info, "CAFEDEADBEEFB0B0");
+    }
+
+    #[tokio::test]
+    async fn read_uuid_device_lost() {
+        let mut reader = MockReaderTraits::new();
+        reader.expect_read_uuid().times(1).returning(|| {
+            Err(ReaderError::SerialError(SerialError::IoError(
+                std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Broken pipe"),
+            )))
+        });
+
+        let rfid = Rfid::new(Box::new(reader));
+        let slot = rfid.reader_slot();
+
+        let ts = TestStruct::new(rfid).await;
+        let mut client = ts.client().await;
+
+        let first = client.read_uuid(Request::new(Empty {})).await;
+        let second = client.read_uuid(Request::new(Empty {})).await;
+        ts.end().await;
+
+        assert_eq!(first.unwrap_err().code(), tonic::Code::Unavailable);
+        assert!(second
+            .unwrap_err()
+            .message()
+            .contains("No reader connected"));
+        assert!(slot.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn watch_reader_status_fixed() {
+        let rfid = Rfid::new(Box::new(MockReaderTraits::new()));
+
+        let ts = TestStruct::new(rfid).await;
+        let mut client = ts.client().await;
+
+        let mut res = client
+            .watch_reader_status(Request::new(Empty {}))
+            .await
+            .unwrap()
+            .into_inner();
+        ts.end().await;
+
+        let mut states: Vec<ReaderStatus> = Vec::new();
+        while let Ok(Some(status)) = res.message().await {
+            states.push(status);
+        }
+
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].state, ReaderState::Connected as i32);
+    }
+
+    #[tokio::test]
+    async fn watch_tags_arrival_and_departure() {
+        let mut reader = MockReaderTraits::new();
+        let mut polls = 0;
+        reader.expect_read_uuid_rssi().returning(move || {
+            polls += 1;
+            match polls {
+                1..=3 => Ok((String::from("CAFEDEADBEEFB0B0"), 0x40)),
+                _ => Err(ReaderError::NoMatchingTargets(String::from(""))),
+            }
+        });
+
+        let ts = TestStruct::new(Rfid::new(Box::new(reader))).await;
+        let mut client = ts.client().await;
+
+        let mut res = client
+            .watch_tags(Request::new(Empty {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let arrived = res.message().await.unwrap().unwrap();
+        let departed = res.message().await.unwrap().unwrap();
+        drop(res);
+        ts.end().await;
+
+        assert_eq!(arrived.kind, TagEventKind::TagArrived as i32);
+        assert_eq!(arrived.uid, "CAFEDEADBEEFB0B0");
+        assert_eq!(departed.kind, TagEventKind::TagDeparted as i32);
+        assert_eq!(departed.uid, "CAFEDEADBEEFB0B0");
+        assert!(departed.timestamp - arrived.timestamp >= 500);
+    }
+
+    #[tokio::test]
+    async fn watch_inventory_shared() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid_rssi()
+            .returning(|| Ok((String::from("CAFEDEADBEEFB0B0"), 0x40)));
+
+        let ts = TestStruct::new(Rfid::new(Box::new(reader))).await;
+        let mut first_client = ts.client().await;
+        let mut second_client = ts.client().await;
+
+        let mut first = first_client
+            .watch_inventory(Request::new(InventoryRequest { dedup: None }))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut second = second_client
+            .watch_inventory(Request::new(InventoryRequest { dedup: None }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // the second client may have subscribed a read later
+        let a = first.message().await.unwrap().unwrap();
+        let b = first.message().await.unwrap().unwrap();
+        let c = second.message().await.unwrap().unwrap();
+        drop(first);
+        drop(second);
+        ts.end().await;
+
+        assert_eq!(a.uid, "CAFEDEADBEEFB0B0");
+        assert_eq!(a.rssi, 0x40);
+        assert_eq!(b.sequence, a.sequence + 1);
+        assert!(c.sequence == a.sequence || c.sequence == b.sequence);
+        assert_eq!(c.missed, 0);
+    }
+
+    /* continuous reads and the inventory loop each see the same tags */
+    fn changing_reader() -> MockReaderTraits {
+        let uid = |reads: u32| match reads {
+            1..=3 => String::from("CAFEDEADBEEFB0B0"),
+            _ => String::from("E004015012345678"),
+        };
+        let mut reader = MockReaderTraits::new();
+        let mut reads = 0;
+        reader.expect_read_uuid().returning(move || {
+            reads += 1;
+            Ok(uid(reads))
+        });
+        let mut polls = 0;
+        reader.expect_read_uuid_rssi().returning(move || {
+            polls += 1;
+            Ok((uid(polls), 0x40))
+        });
+        reader
+    }
+
+    fn on_change() -> Option<include::Dedup> {
+        Some(include::Dedup {
+            mode: include::DedupMode::OnChange as i32,
+            window_ms: 0,
+        })
+    }
+
+    #[tokio::test]
+    async fn read_uuid_continuous_dedup() {
+        let ts = TestStruct::new(Rfid::new(Box::new(changing_reader()))).await;
+        let mut client = ts.client().await;
+
+        let (mut acks, stream) = mpsc::channel(4);
+        acks.send(StreamPayload {
+            dedup: on_change(),
+            ..ack(2)
+        })
+        .await
+        .unwrap();
+        let mut res = client
+            .read_uuid_continous(Request::new(stream))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let first = res.message().await.unwrap().unwrap();
+        let second = res.message().await.unwrap().unwrap();
+        drop(acks);
+        ts.end().await;
+
+        assert_eq!(first.info, "CAFEDEADBEEFB0B0");
+        assert_eq!(first.suppressed, 0);
+        // the repeats did not use up the second credit
+        assert_eq!(second.info, "E004015012345678");
+        assert_eq!(second.suppressed, 2);
+    }
+
+    #[tokio::test]
+    async fn watch_inventory_dedup() {
+        let ts = TestStruct::new(Rfid::new(Box::new(changing_reader()))).await;
+        let mut client = ts.client().await;
+
+        let mut res = client
+            .watch_inventory(Request::new(InventoryRequest { dedup: on_change() }))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = res.message().await.unwrap().unwrap();
+        let second = res.message().await.unwrap().unwrap();
+        drop(res);
+        ts.end().await;
+
+        assert_eq!(first.uid, "CAFEDEADBEEFB0B0");
+        assert_eq!(second.uid, "E004015012345678");
+        assert_eq!(second.suppressed, 2);
+        assert_eq!(second.sequence, first.sequence + 3);
+    }
+
+    fn events() -> EventLog {
+        EventLog::open(&EventOptions {
+            path: PathBuf::from(":memory:"),
+            retention: None,
+            max_events: None,
+        })
+        .unwrap()
+    }
+
+    fn query(page_size: u32, page_token: &str) -> Request<QueryEventsRequest> {
+        Request::new(QueryEventsRequest {
+            from: 0,
+            to: 0,
+            uid: String::from(""),
+            page_size,
+            page_token: String::from(page_token),
+        })
+    }
+
+    #[tokio::test]
+    async fn query_events_ok() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+        reader
+            .expect_read_single_block()
+            .returning(|_| Ok((String::from("CAFEDEADBEEFB0B0"), String::from("01020304"))));
+
+        let ts = TestStruct::new(Rfid::with_events(Box::new(reader), events())).await;
+        let mut client = ts.client().await;
+
+        client.read_uuid(Request::new(Empty {})).await.unwrap();
+        client
+            .read_single_block(Request::new(SingleBlockRequest { block_index: 2 }))
+            .await
+            .unwrap();
+        let first = client
+            .query_events(query(1, ""))
+            .await
+            .unwrap()
+            .into_inner();
+        let rest = client
+            .query_events(query(0, &first.next_page_token))
+            .await
+            .unwrap()
+            .into_inner();
+        let bad_token = client.query_events(query(0, "page")).await;
+        ts.end().await;
+
+        assert_eq!(first.events.len(), 1);
+        let read = &first.events[0];
+        assert_eq!(read.operation, include::Operation::ReadUuid as i32);
+        assert_eq!(read.uid, "CAFEDEADBEEFB0B0");
+        assert!(read.client.contains("127.0.0.1") || read.client.contains("::1"));
+
+        assert_eq!(rest.next_page_token, "");
+        assert_eq!(rest.events.len(), 1);
+        let block = &rest.events[0];
+        assert_eq!(block.operation, include::Operation::ReadBlock as i32);
+        assert_eq!(block.uid, "CAFEDEADBEEFB0B0");
+        assert_eq!(block.block, 2);
+        assert_eq!(block.data_hash.len(), 64);
+        assert!(block.id > read.id);
+
+        assert_eq!(bad_token.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn query_events_block_read_by_uid() {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .returning(|| Ok(String::from("E004015012345678")));
+        reader
+            .expect_read_single_block()
+            .returning(|_| Ok((String::from("CAFEDEADBEEFB0B0"), String::from("01020304"))));
+
+        let ts = TestStruct::new(Rfid::with_events(Box::new(reader), events())).await;
+        let mut client = ts.client().await;
+
+        client.read_uuid(Request::new(Empty {})).await.unwrap();
+        client
+            .read_single_block(Request::new(SingleBlockRequest { block_index: 2 }))
+            .await
+            .unwrap();
+        let mut by_uid = query(0, "");
+        by_uid.get_mut().uid = String::from("CAFEDEADBEEFB0B0");
+        let found = client.query_events(by_uid).await.unwrap().into_inner();
+        ts.end().await;
+
+        assert_eq!(found.events.len(), 1);
+        assert_eq!(
+            found.events[0].operation,
+            include::Operation::ReadBlock as i32
+        );
+        assert_eq!(found.events[0].block, 2);
+    }
+
+    #[tokio::test]
+    async fn query_events_disabled() {
+        let ts = TestStruct::new(Rfid::new(Box::new(MockReaderTraits::new()))).await;
+        let mut client = ts.client().await;
+
+        let res = client.query_events(query(0, "")).await;
+        ts.end().await;
+
+        assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn subscribe_resumes_from_sequence() {
+        let mut reader = MockReaderTraits::new();
+        let mut reads = 0;
+        reader.expect_read_uuid().returning(move || {
+            reads += 1;
+            Ok(format!("CAFEDEADBEEF{:04}", reads))
+        });
+
+        let ts = TestStruct::new(Rfid::with_events(Box::new(reader), events())).await;
+        let mut client = ts.client().await;
+
+        for _ in 0..3 {
+            client.read_uuid(Request::new(Empty {})).await.unwrap();
+        }
+        let mut res = client
+            .subscribe(Request::new(SubscribeRequest { from_sequence: 2 }))
+            .await
+            .unwrap()
+            .into_inner();
+        let second = res.message().await.unwrap().unwrap();
+        let third = res.message().await.unwrap().unwrap();
+        client.read_uuid(Request::new(Empty {})).await.unwrap();
+        let live = res.message().await.unwrap().unwrap();
+        drop(res);
+        ts.end().await;
+
+        assert_eq!(second.id, 2);
+        assert_eq!(second.uid, "CAFEDEADBEEF0002");
+        assert_eq!(third.id, 3);
+        assert_eq!(live.id, 4);
+        assert_eq!(live.uid, "CAFEDEADBEEF0004");
+    }
+
+    #[tokio::test]
+    async fn subscribe_from_pruned_sequence() {
+        let dir = temp_dir();
+        let options = EventOptions {
+            path: dir.join("events.db"),
+            retention: None,
+            max_events: Some(2),
+        };
+        // five reads, the first three are pruned once the log is opened again
+        {
+            let log = EventLog::open(&options).unwrap();
+            let mut reader = MockReaderTraits::new();
+            reader
+                .expect_read_uuid()
+                .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+            let ts = TestStruct::new(Rfid::with_events(Box::new(reader), log)).await;
+            let mut client = ts.client().await;
+            for _ in 0..5 {
+                client.read_uuid(Request::new(Empty {})).await.unwrap();
+            }
+            // queued behind the records, so they are all written once it answers
+            client.query_events(query(0, "")).await.unwrap();
+            ts.end().await;
+        }
+        let log = EventLog::open(&options).unwrap();
+        let ts = TestStruct::new(Rfid::with_events(Box::new(MockReaderTraits::new()), log)).await;
+        let mut client = ts.client().await;
+
+        let mut gone = client
+            .subscribe(Request::new(SubscribeRequest { from_sequence: 2 }))
+            .await
+            .unwrap()
+            .into_inner();
+        let gone = gone.message().await;
+        let mut kept = client
+            .subscribe(Request::new(SubscribeRequest { from_sequence: 0 }))
+            .await
+            .unwrap()
+            .into_inner();
+        let oldest = kept.message().await.unwrap().unwrap();
+        drop(kept);
+        ts.end().await;
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(gone.unwrap_err().code(), tonic::Code::OutOfRange);
+        assert_eq!(oldest.id, 4);
+    }
 }
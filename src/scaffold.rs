@@ -1,28 +1,48 @@
 #[cfg(test)]
+pub mod pty;
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
 pub mod scaffold {
 
     use crate::include::read_info_client::ReadInfoClient;
     use crate::include::read_info_server::ReadInfoServer;
     use crate::rfid::Rfid;
+    use crate::tls::{TlsOptions, DEFAULT_RELOAD_INTERVAL_MS};
     use futures_util::FutureExt;
+    use rand::distributions::Alphanumeric;
+    use rand::{thread_rng, Rng};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa};
+    use std::fs;
+    use std::io;
+    use std::net::SocketAddr;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
     use std::time::Duration;
+    use tokio::net::TcpListener;
     use tokio::sync::oneshot;
     use tokio::task::JoinHandle;
-    use tonic::transport::Server;
-
-    const IP_ADDR: &'static str = "http://[::]:50051";
+    use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity, Server};
+    use tracing_subscriber::fmt::MakeWriter;
 
     pub struct TestStruct {
         tx: oneshot::Sender<()>,
         jh: JoinHandle<()>,
+        port: u16,
     }
 
     impl TestStruct {
         pub async fn new(rfid: Rfid) -> TestStruct {
             let (tx, rx) = oneshot::channel::<()>();
-            let jh = start_server(rfid, rx).await;
+            let (port, jh) = start_server(rfid, rx).await;
 
-            TestStruct { tx: tx, jh: jh }
+            TestStruct { tx, jh, port }
+        }
+
+        pub async fn client(&self) -> ReadInfoClient<Channel> {
+            ReadInfoClient::connect(format!("http://127.0.0.1:{}", self.port))
+                .await
+                .unwrap()
         }
 
         pub async fn end(self) {
@@ -32,20 +52,122 @@ pub mod scaffold {
     }
 
     /* the one shot channel is just used to terminate the server after
-    one call by dropping rx*/
-    pub async fn start_server(rfid: Rfid, rx: oneshot::Receiver<()>) -> JoinHandle<()> {
-        tokio::spawn(async move {
-            let addr = "[::]:50051".parse().unwrap();
+    one call by dropping rx. every server gets a port of its own, so tests run side
+    by side */
+    pub async fn start_server(rfid: Rfid, rx: oneshot::Receiver<()>) -> (u16, JoinHandle<()>) {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let jh = tokio::spawn(async move {
             Server::builder()
                 .add_service(ReadInfoServer::new(rfid))
-                .serve_with_shutdown(addr, rx.map(drop))
+                .serve_with_incoming_shutdown(listener, rx.map(drop))
                 .await
                 .unwrap();
-        })
+        });
+        (port, jh)
     }
 
-    pub async fn start_client() -> ReadInfoClient<tonic::transport::Channel> {
-        tokio::time::delay_for(Duration::from_millis(100)).await;
-        ReadInfoClient::connect(IP_ADDR).await.unwrap()
+    /* collects what a subscriber writes, to look at the logs of a test */
+    #[derive(Clone, Default)]
+    pub struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl MakeWriter for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&self) -> Captured {
+            self.clone()
+        }
+    }
+
+    impl Captured {
+        pub fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    /* a ca with a server certificate for localhost and a client certificate */
+    pub struct Pki {
+        pub ca: String,
+        pub server: (String, String),
+        pub client: (String, String),
+    }
+
+    fn issue(ca: &rcgen::Certificate, name: &str) -> (String, String) {
+        let cert =
+            rcgen::Certificate::from_params(CertificateParams::new(vec![String::from(name)]))
+                .unwrap();
+        (
+            cert.serialize_pem_with_signer(ca).unwrap(),
+            cert.serialize_private_key_pem(),
+        )
+    }
+
+    pub fn pki() -> Pki {
+        let mut params = CertificateParams::new(vec![]);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(params).unwrap();
+        Pki {
+            ca: ca.serialize_pem().unwrap(),
+            server: issue(&ca, "localhost"),
+            client: issue(&ca, "client"),
+        }
+    }
+
+    pub fn temp_dir() -> PathBuf {
+        let name: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect();
+        let dir = std::env::temp_dir().join(format!("rfid-test-{}", name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /* the server side of the pki, clients need a certificate when mutual */
+    pub fn write_tls(dir: &Path, pki: &Pki, mutual: bool) -> TlsOptions {
+        fs::write(dir.join("server.pem"), &pki.server.0).unwrap();
+        fs::write(dir.join("server.key"), &pki.server.1).unwrap();
+        fs::write(dir.join("ca.pem"), &pki.ca).unwrap();
+        TlsOptions {
+            cert: dir.join("server.pem"),
+            key: dir.join("server.key"),
+            client_ca: Some(dir.join("ca.pem")).filter(|_| mutual),
+            reload_interval: Duration::from_millis(DEFAULT_RELOAD_INTERVAL_MS),
+        }
+    }
+
+    /* none when the handshake fails */
+    pub async fn tls_client(
+        port: u16,
+        ca: &str,
+        identity: Option<&(String, String)>,
+    ) -> Option<ReadInfoClient<Channel>> {
+        let mut config = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(ca))
+            .domain_name("localhost");
+        if let Some((cert, key)) = identity {
+            config = config.identity(Identity::from_pem(cert, key));
+        }
+        let channel = Channel::from_shared(format!("https://127.0.0.1:{}", port))
+            .unwrap()
+            .tls_config(config)
+            .connect()
+            .await
+            .ok()?;
+        Some(ReadInfoClient::new(channel))
     }
 }
@@ -0,0 +1,117 @@
+/* a linux pseudo terminal standing in for the usb serial port of the evm, so tests go
+through the real serial port code. only nix and std are used, the end to end tests of the
+binary include this file as well */
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::{grantpt, posix_openpt, ptsname_r, unlockpt, PtyMaster};
+use nix::unistd::{read, write};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// how often the device thread looks whether it has to stop
+const POLL_MS: i32 = 20;
+
+/* the reply to a command, none leaves the host waiting for its timeout */
+pub type Responder = Box<dyn FnMut(&str) -> Option<String> + Send>;
+
+pub struct FakeDevice {
+    path: String,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/* a command is SOF, its length in bytes and the rest of the frame, all in hex. anything
+else is taken as it arrived */
+fn split_command(pending: &mut String) -> Option<String> {
+    if pending.is_empty() {
+        return None;
+    }
+    let frame_len = match pending.get(2..4).map(|len| u8::from_str_radix(len, 16)) {
+        Some(Ok(len)) if pending.starts_with("01") && len > 0 => len as usize * 2,
+        Some(_) => pending.len(),
+        None => return None,
+    };
+    if pending.len() < frame_len {
+        return None;
+    }
+    let rest = pending.split_off(frame_len);
+    Some(std::mem::replace(pending, rest))
+}
+
+fn serve(master: PtyMaster, mut responder: Responder, stop: Arc<AtomicBool>) {
+    let fd = master.as_raw_fd();
+    let mut pending = String::new();
+    let mut buf = [0u8; 256];
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        match poll(&mut fds, POLL_MS) {
+            Ok(n) if n > 0 => {}
+            _ => continue,
+        }
+        let n = match read(fd, &mut buf) {
+            Ok(n) => n,
+            // nobody has the other side open, between tests or reconnects
+            Err(_) => {
+                thread::sleep(Duration::from_millis(POLL_MS as u64));
+                continue;
+            }
+        };
+        pending.push_str(&String::from_utf8_lossy(&buf[..n]));
+
+        while let Some(cmd) = split_command(&mut pending) {
+            if let Some(reply) = responder(&cmd) {
+                let mut sent = 0;
+                while sent < reply.len() {
+                    match write(fd, &reply.as_bytes()[sent..]) {
+                        Ok(n) => sent += n,
+                        Err(_) => break,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FakeDevice {
+    pub fn start(responder: Responder) -> FakeDevice {
+        let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY).unwrap();
+        grantpt(&master).unwrap();
+        unlockpt(&master).unwrap();
+        let path = ptsname_r(&master).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::Builder::new()
+            .name(String::from("fake-evm"))
+            .spawn(move || serve(master, responder, thread_stop))
+            .unwrap();
+        FakeDevice {
+            path,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /* the slave side, to open as a serial port */
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /* closes the master, the host side sees what it sees when the usb cable is pulled */
+    pub fn unplug(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for FakeDevice {
+    fn drop(&mut self) {
+        self.unplug();
+    }
+}
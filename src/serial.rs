@@ -1,210 +1,278 @@
 #[cfg(test)]
 use mockall::automock;
 
+use async_trait::async_trait;
 use serialport::prelude::*;
-use serialport::{SerialPortInfo, SerialPortSettings};
-
-use std::sync::Mutex;
-use std::time::Duration;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+use tokio::sync::oneshot;
+use tracing::{field, Instrument};
 
+pub mod capture;
 pub mod err;
+pub mod frame;
 pub mod low;
+pub mod options;
+pub mod select;
+pub mod sim;
 
+use crate::logging;
+use crate::metrics;
 use err::SerialError;
 use low::SerialCrateTraits;
-
-static TRIES: u32 = 3;
-static SERIAL_TIMEOUT_MS: u64 = 2000;
+use options::SerialOptions;
 
 #[cfg_attr(test, automock)]
+#[async_trait]
 pub trait RfidSerialTraits: Send + Sync {
     /* send and return received string */
-    fn send_recv(&mut self, msg: &str) -> Result<String, SerialError>;
+    async fn send_recv(&mut self, msg: &str) -> Result<String, SerialError>;
+}
+
+/* a command for the i/o thread together with where to put its reply */
+struct Exchange {
+    cmd: String,
+    reply: oneshot::Sender<Result<String, std::io::Error>>,
 }
 
+/* the port is owned by a dedicated thread, so blocking reads never hold up the runtime */
 pub struct RfidSerial {
-    port: Mutex<Box<dyn SerialPort>>,
-    lib: Mutex<Box<dyn SerialCrateTraits>>,
+    exchanges: mpsc::Sender<Exchange>,
+    options: SerialOptions,
 }
 
+#[async_trait]
 impl RfidSerialTraits for RfidSerial {
-    fn send_recv(&mut self, cmd: &str) -> Result<String, SerialError> {
-        let mut attempts = TRIES;
-        while attempts > 0 {
-            match self.send(cmd) {
-                Ok(recv) => {
-                    return Ok(recv);
-                }
-                Err(_) => {
-                    attempts -= 1;
-                }
-            }
+    async fn send_recv(&mut self, cmd: &str) -> Result<String, SerialError> {
+        // a child of the call that needed the reader
+        let span = tracing::debug_span!(
+            "serial",
+            sent = logging::tag_data(cmd),
+            received = field::Empty
+        );
+        let start = Instant::now();
+        let res = self.send_with_retries(cmd).instrument(span.clone()).await;
+        metrics::serial_command(res.is_ok(), start.elapsed());
+        if let Ok(ref recv) = res {
+            span.record("received", field::debug(logging::tag_data(recv)));
+            span.in_scope(|| tracing::debug!("{}", "Exchanged"));
         }
-        Err(SerialError::NoReplyAfterMultipleTries)
+        res
     }
 }
 
-impl RfidSerial {
-    pub fn new(lib: Box<dyn SerialCrateTraits>) -> RfidSerial {
-        let ports = match lib.get_ports() {
-            Ok(ports) => ports,
-            Err(e) => {
-                //error!("{}", SerialError::SerialError(e));
-                panic!("{}", SerialError::SerialError(e).to_string());
-            }
+fn io_thread_stopped() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Serial I/O thread stopped")
+}
+
+fn open(
+    lib: &dyn SerialCrateTraits,
+    options: &SerialOptions,
+) -> Result<Box<dyn SerialPort>, SerialError> {
+    let ports = if options.port.needs_scan() {
+        lib.get_ports()?
+    } else {
+        Vec::new()
+    };
+
+    let port_name = options.port.select(&ports)?;
+
+    /* try to open port */
+    let port = lib.open(&port_name, &options.settings)?;
+    log::info!("Opened serial port {}", port_name);
+    Ok(port)
+}
+
+/* runs until the RfidSerial is dropped, which closes the port */
+fn serve(
+    lib: Box<dyn SerialCrateTraits>,
+    mut port: Box<dyn SerialPort>,
+    options: SerialOptions,
+    exchanges: mpsc::Receiver<Exchange>,
+) {
+    for exchange in exchanges.iter() {
+        let timeout = options.timeout_for(&exchange.cmd);
+        let res = if port.timeout() != timeout {
+            port.set_timeout(timeout).map_err(std::io::Error::from)
+        } else {
+            Ok(())
         };
+        let res = res.and_then(|_| lib.send(&exchange.cmd, &mut port, &options.framing));
 
-        if ports.is_empty() {
-            log::error!("{}", SerialError::NoSerialPortsFound);
-            panic!("{}", SerialError::NoSerialPortsFound.to_string());
+        // the caller may have given up waiting
+        if exchange.reply.send(res).is_err() {
+            tracing::warn!("Reply to {} dropped", logging::tag_data(&exchange.cmd));
         }
+    }
+    log::info!("{}", "Closing serial port");
+}
 
-        let usb_ports: Vec<&SerialPortInfo> = ports
-            .iter()
-            .filter(|p| p.port_name.contains("USB"))
-            .collect();
+impl RfidSerial {
+    /* scanning, opening and every exchange afterwards happen on the i/o thread */
+    pub async fn try_new(
+        lib: Box<dyn SerialCrateTraits>,
+        options: &SerialOptions,
+    ) -> Result<RfidSerial, SerialError> {
+        let (opened_tx, opened_rx) = oneshot::channel();
+        let (exchanges, exchanges_rx) = mpsc::channel();
+        let thread_options = options.clone();
 
-        if usb_ports.is_empty() {
-            log::error!("{}", SerialError::UsbSerialNotFound);
-            panic!("{}", SerialError::UsbSerialNotFound.to_string());
-        }
+        thread::Builder::new()
+            .name(String::from("serial-io"))
+            .spawn(move || {
+                let port = match open(&*lib, &thread_options) {
+                    Ok(port) => port,
+                    Err(e) => {
+                        let _ = opened_tx.send(Err(e));
+                        return;
+                    }
+                };
+                if opened_tx.send(Ok(())).is_ok() {
+                    serve(lib, port, thread_options, exchanges_rx);
+                }
+            })?;
 
-        if usb_ports.len() > 1 {
-            log::error!("{}", SerialError::MultipleSerialPorts);
-            panic!("{}", SerialError::MultipleSerialPorts.to_string());
+        match opened_rx.await {
+            Ok(res) => res?,
+            Err(_) => return Err(SerialError::IoError(io_thread_stopped())),
         }
 
-        let s = SerialPortSettings {
-            baud_rate: 115200,
-            data_bits: DataBits::Eight,
-            flow_control: FlowControl::None,
-            parity: Parity::None,
-            stop_bits: StopBits::One,
-            timeout: Duration::from_millis(SERIAL_TIMEOUT_MS),
-        };
+        Ok(RfidSerial {
+            exchanges,
+            options: options.clone(),
+        })
+    }
 
-        /* try to open port */
-        let port = match lib.open(&usb_ports[0].port_name, &s) {
-            Ok(port) => port,
-            Err(e) => {
-                //log::error!("{}", SerialError::SerialError(e));
-                panic!("{}", SerialError::SerialError(e).to_string());
-            }
+    pub async fn send(&self, msg: &str) -> Result<String, std::io::Error> {
+        let (reply, replied) = oneshot::channel();
+        let exchange = Exchange {
+            cmd: String::from(msg),
+            reply,
         };
-
-        RfidSerial {
-            port: Mutex::new(port),
-            lib: Mutex::new(lib),
+        if self.exchanges.send(exchange).is_err() {
+            return Err(io_thread_stopped());
+        }
+        match replied.await {
+            Ok(res) => res,
+            Err(_) => Err(io_thread_stopped()),
         }
     }
 
-    pub fn send(&mut self, msg: &str) -> Result<String, std::io::Error> {
-        /* let the program panic if lock fails */
-        let lib = self.lib.lock().unwrap();
-        let mut port = self.port.lock().unwrap();
-        lib.send(&msg, &mut port)
+    /* the metrics count one command however many attempts it took */
+    async fn send_with_retries(&self, cmd: &str) -> Result<String, SerialError> {
+        let retry = self.options.retry.clone();
+        let mut failures: Vec<std::io::Error> = Vec::new();
+
+        for attempt in 1..=retry.tries {
+            if attempt > 1 {
+                metrics::serial_retry();
+            }
+            match self.send(cmd).await {
+                Ok(recv) => {
+                    return Ok(recv);
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "{} attempt {}/{} failed: {}",
+                        logging::tag_data(cmd),
+                        attempt,
+                        retry.tries,
+                        e
+                    );
+                    if !retry.is_retryable(&e) {
+                        return Err(SerialError::IoError(e));
+                    }
+                    failures.push(e);
+                    if attempt < retry.tries {
+                        tokio::time::delay_for(retry.backoff_after(attempt)).await;
+                    }
+                }
+            }
+        }
+        Err(SerialError::NoReplyAfterMultipleTries(failures))
     }
 }
 
 #[cfg(test)]
 mod test {
-    type Result<T> = std::result::Result<T, serialport::Error>;
     use super::*;
-    use crate::serial::low::MockSerialCrateTraits;
-    use mockall::mock;
-    use serialport::{ErrorKind, SerialPortInfo, SerialPortSettings, SerialPortType};
-    use std::io::{Read, Write};
-
-    mock! {
-        SerialPort {}
-        pub trait SerialPort {
-            fn name(&self) -> Option<String>;
-            fn settings(&self) -> SerialPortSettings;
-            fn baud_rate(&self) -> Result<u32>;
-            fn data_bits(&self) -> Result<DataBits>;
-            fn flow_control(&self) -> Result<FlowControl>;
-            fn parity(&self) -> Result<Parity>;
-            fn stop_bits(&self) -> Result<StopBits>;
-            fn timeout(&self) -> Duration;
-            fn set_all(&mut self, settings: &SerialPortSettings) -> Result<()>;
-            fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()>;
-            fn set_data_bits(&mut self, data_bits: DataBits) -> Result<()>;
-            fn set_flow_control(&mut self, flow_control: FlowControl) -> Result<()>;
-            fn set_parity(&mut self, parity: Parity) -> Result<()>;
-            fn set_stop_bits(&mut self, stop_bits: StopBits) -> Result<()>;
-            fn set_timeout(&mut self, timeout: Duration) -> Result<()>;
-            fn write_request_to_send(&mut self, level: bool) -> Result<()>;
-            fn write_data_terminal_ready(&mut self, level: bool) -> Result<()>;
-            fn read_clear_to_send(&mut self) -> Result<bool>;
-            fn read_data_set_ready(&mut self) -> Result<bool>;
-            fn read_ring_indicator(&mut self) -> Result<bool>;
-            fn read_carrier_detect(&mut self) -> Result<bool>;
-            fn bytes_to_read(&self) -> Result<u32>;
-            fn bytes_to_write(&self) -> Result<u32>;
-            fn clear(&self, buffer_to_clear: ClearBuffer) -> Result<()>;
-            fn try_clone(&self) -> Result<Box<dyn SerialPort>>;
-        }
-
-        trait Write {
-            fn write(&mut self, buf: &[u8]) -> std::result::Result<usize, std::io::Error>;
-            fn flush(&mut self) -> std::result::Result<(), std::io::Error>;
-        }
-
-        trait Read {
-            fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, std::io::Error>;
-        }
-    }
-
-    #[test]
-    #[should_panic(expected = "No serial ports found. Please ensure device is connected")]
-    fn empty_serial_port() {
+    use crate::reader::{Reader, ReaderTraits};
+    use crate::scaffold::pty::FakeDevice;
+    use crate::scaffold::scaffold::Captured;
+    use crate::serial::low::{MockSerialCrateTraits, MockSerialPort, SerialCrate};
+    use crate::serial::options::{RetryPolicy, DEFAULT_TRIES};
+    use crate::serial::select::PortSelector;
+    use crate::serial::sim::tag::VirtualTag;
+    use crate::serial::sim::Simulator;
+    use mockall::predicate::eq;
+    use serialport::{ErrorKind, SerialPortInfo, SerialPortType};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn empty_serial_port() {
         let mut s = MockSerialCrateTraits::new();
         s.expect_get_ports().returning(|| {
             //empty vector of serial ports
             let v: Vec<SerialPortInfo> = Vec::new();
             Ok(v)
         });
-        let _rs = RfidSerial::new(Box::new(s));
+        let res = RfidSerial::try_new(Box::new(s), &SerialOptions::default()).await;
+        match res {
+            Ok(_) => panic!("{}", "Should have been an error"),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("No serial ports found. Please ensure device is connected")),
+        }
     }
 
-    #[test]
-    #[should_panic(expected = "No Usb serial port found. Please ensure device is connected")]
-    fn no_usb_serial_port() {
+    #[tokio::test]
+    async fn no_usb_serial_port() {
         let mut s = MockSerialCrateTraits::new();
         s.expect_get_ports().returning(|| {
-            let mut v: Vec<SerialPortInfo> = Vec::new();
-            v.push(SerialPortInfo {
+            let v: Vec<SerialPortInfo> = vec![SerialPortInfo {
                 port_name: String::from("/dev/ttyS1"),
                 port_type: SerialPortType::Unknown,
-            });
+            }];
             Ok(v)
         });
 
-        let _rs = RfidSerial::new(Box::new(s));
+        let res = RfidSerial::try_new(Box::new(s), &SerialOptions::default()).await;
+        match res {
+            Ok(_) => panic!("{}", "Should have been an error"),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("No Usb serial port found. Please ensure device is connected")),
+        }
     }
 
-    #[test]
-    #[should_panic(expected = "More than one USB serial device found")]
-    fn more_than_one_usb_serial_port() {
+    #[tokio::test]
+    async fn more_than_one_usb_serial_port() {
         let mut s = MockSerialCrateTraits::new();
         s.expect_get_ports().returning(|| {
-            let mut v: Vec<SerialPortInfo> = Vec::new();
-            v.push(SerialPortInfo {
-                port_name: String::from("/dev/USB0"),
-                port_type: SerialPortType::Unknown,
-            });
-            v.push(SerialPortInfo {
-                port_name: String::from("/dev/ttyUSB1"),
-                port_type: SerialPortType::Unknown,
-            });
+            let v: Vec<SerialPortInfo> = vec![
+                SerialPortInfo {
+                    port_name: String::from("/dev/USB0"),
+                    port_type: SerialPortType::Unknown,
+                },
+                SerialPortInfo {
+                    port_name: String::from("/dev/ttyUSB1"),
+                    port_type: SerialPortType::Unknown,
+                },
+            ];
             Ok(v)
         });
-        let _rs = RfidSerial::new(Box::new(s));
+        let res = RfidSerial::try_new(Box::new(s), &SerialOptions::default()).await;
+        match res {
+            Ok(_) => panic!("{}", "Should have been an error"),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("More than one USB serial device found")),
+        }
     }
 
-    #[test]
-    #[should_panic(expected = "SerialPortError")]
-    fn serialport_error() {
+    #[tokio::test]
+    async fn serialport_error() {
         let mut s = MockSerialCrateTraits::new();
         s.expect_get_ports().returning(|| {
             let e = serialport::Error {
@@ -214,19 +282,21 @@ mod test {
             Err(e)
         });
 
-        let _rs = RfidSerial::new(Box::new(s));
+        let res = RfidSerial::try_new(Box::new(s), &SerialOptions::default()).await;
+        match res {
+            Ok(_) => panic!("{}", "Should have been an error"),
+            Err(e) => assert!(e.to_string().contains("SerialPortError")),
+        }
     }
 
-    #[test]
-    #[should_panic(expected = "Failed to open")]
-    fn failed_to_open() {
+    #[tokio::test]
+    async fn failed_to_open() {
         let mut s = MockSerialCrateTraits::new();
         s.expect_get_ports().returning(|| {
-            let mut v: Vec<SerialPortInfo> = Vec::new();
-            v.push(SerialPortInfo {
+            let v: Vec<SerialPortInfo> = vec![SerialPortInfo {
                 port_name: String::from("/dev/ttyUSB0"),
                 port_type: SerialPortType::Unknown,
-            });
+            }];
             Ok(v)
         });
         s.expect_open().returning(|_, _| {
@@ -237,12 +307,285 @@ mod test {
             Err(e)
         });
 
-        let _rs = RfidSerial::new(Box::new(s));
+        let res = RfidSerial::try_new(Box::new(s), &SerialOptions::default()).await;
+        match res {
+            Ok(_) => panic!("{}", "Should have been an error"),
+            Err(e) => assert!(e.to_string().contains("Failed to open")),
+        }
     }
 
-    //TODO: not sure how to fake a legit serial port object
-    // #[test]
-    // fn ok() {
+    #[tokio::test]
+    async fn explicit_path_skips_scan() {
+        let mut s = MockSerialCrateTraits::new();
+        s.expect_get_ports().times(0);
+        s.expect_open()
+            .withf(|path, _| path == "/dev/ttyACM0")
+            .times(1)
+            .returning(|_, _| {
+                let e = serialport::Error {
+                    kind: ErrorKind::NoDevice,
+                    description: String::from("Failed to open"),
+                };
+                Err(e)
+            });
+
+        let options = SerialOptions {
+            port: PortSelector::Path(String::from("/dev/ttyACM0")),
+            ..Default::default()
+        };
+        let res = RfidSerial::try_new(Box::new(s), &options).await;
+        match res {
+            Ok(_) => panic!("{}", "Should have been an error"),
+            Err(e) => assert!(e.to_string().contains("Failed to open")),
+        }
+    }
 
-    // }
+    fn open_helper(s: &mut MockSerialCrateTraits) {
+        s.expect_get_ports().returning(|| {
+            let v: Vec<SerialPortInfo> = vec![SerialPortInfo {
+                port_name: String::from("/dev/ttyUSB0"),
+                port_type: SerialPortType::Unknown,
+            }];
+            Ok(v)
+        });
+        s.expect_open().returning(|_, settings| {
+            let mut port = MockSerialPort::new();
+            port.expect_timeout().return_const(settings.timeout);
+            Ok(Box::new(port))
+        });
+    }
+
+    fn timed_out() -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out")
+    }
+
+    #[tokio::test]
+    async fn ok() {
+        let mut s = MockSerialCrateTraits::new();
+        open_helper(&mut s);
+        s.expect_send()
+            .withf(|msg, _, _| msg == "0108")
+            .times(1)
+            .returning(|_, _, _| Ok(String::from("reply")));
+
+        let mut rs = RfidSerial::try_new(Box::new(s), &SerialOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(rs.send_recv("0108").await.unwrap(), "reply");
+    }
+
+    #[tokio::test]
+    async fn slow_read_does_not_block_runtime() {
+        let mut s = MockSerialCrateTraits::new();
+        open_helper(&mut s);
+        s.expect_send().returning(|_, _, _| {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(String::from("reply"))
+        });
+
+        let mut rs = RfidSerial::try_new(Box::new(s), &SerialOptions::default())
+            .await
+            .unwrap();
+        // the test runtime has a single thread, a blocking read would starve the timer
+        tokio::select! {
+            _ = rs.send_recv("0108") => panic!("{}", "Timer should have fired first"),
+            _ = tokio::time::delay_for(Duration::from_millis(10)) => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_until_reply() {
+        let mut s = MockSerialCrateTraits::new();
+        open_helper(&mut s);
+        let mut calls = 0;
+        s.expect_send().times(2).returning(move |_, _, _| {
+            calls += 1;
+            if calls == 1 {
+                Err(timed_out())
+            } else {
+                Ok(String::from("reply"))
+            }
+        });
+
+        let mut rs = RfidSerial::try_new(Box::new(s), &SerialOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(rs.send_recv("0108").await.unwrap(), "reply");
+    }
+
+    #[tokio::test]
+    async fn redacted_logs_leave_out_tags() {
+        let mut s = MockSerialCrateTraits::new();
+        open_helper(&mut s);
+        let mut calls = 0;
+        s.expect_send().times(2).returning(move |_, _, _| {
+            calls += 1;
+            if calls == 1 {
+                Err(timed_out())
+            } else {
+                Ok(String::from("Request mode.\r\n[00CAFEBABE]"))
+            }
+        });
+        let mut rs = RfidSerial::try_new(Box::new(s), &SerialOptions::default())
+            .await
+            .unwrap();
+
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(captured.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let _redaction = logging::redaction(true);
+
+        // a block read, it carries the uid of the tag
+        rs.send_recv("0113000304182220CAFEDEADBEEFB0E0020000")
+            .await
+            .unwrap();
+        let text = captured.text();
+        assert!(text.contains("attempt 1/"));
+        assert!(text.contains("Exchanged"));
+        assert!(!text.contains("CAFEDEADBEEFB0E0"));
+        assert!(!text.contains("CAFEBABE"));
+    }
+
+    #[tokio::test]
+    async fn no_reply_keeps_causes() {
+        let mut s = MockSerialCrateTraits::new();
+        open_helper(&mut s);
+        s.expect_send()
+            .times(DEFAULT_TRIES as usize)
+            .returning(|_, _, _| Err(timed_out()));
+
+        let mut rs = RfidSerial::try_new(Box::new(s), &SerialOptions::default())
+            .await
+            .unwrap();
+        match rs.send_recv("0108").await {
+            Err(SerialError::NoReplyAfterMultipleTries(causes)) => {
+                assert_eq!(causes.len(), DEFAULT_TRIES as usize);
+                assert!(causes
+                    .iter()
+                    .all(|e| e.kind() == std::io::ErrorKind::TimedOut));
+            }
+            _ => panic!("{}", "Should have been NoReplyAfterMultipleTries"),
+        }
+    }
+
+    #[tokio::test]
+    async fn non_retryable_error_not_retried() {
+        let mut s = MockSerialCrateTraits::new();
+        open_helper(&mut s);
+        s.expect_send().times(1).returning(|_, _, _| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "Broken pipe",
+            ))
+        });
+
+        let options = SerialOptions {
+            retry: RetryPolicy {
+                retry_on: Some(vec![std::io::ErrorKind::TimedOut]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut rs = RfidSerial::try_new(Box::new(s), &options).await.unwrap();
+        match rs.send_recv("0108").await {
+            Err(SerialError::IoError(e)) => assert!(e.to_string().contains("Broken pipe")),
+            _ => panic!("{}", "Should have been an IoError"),
+        }
+    }
+
+    #[tokio::test]
+    async fn command_timeout_applied() {
+        let mut s = MockSerialCrateTraits::new();
+        s.expect_get_ports().times(0);
+        s.expect_open().returning(|_, settings| {
+            let mut port = MockSerialPort::new();
+            port.expect_timeout().return_const(settings.timeout);
+            port.expect_set_timeout()
+                .with(eq(Duration::from_millis(5000)))
+                .times(1)
+                .returning(|_| Ok(()));
+            Ok(Box::new(port))
+        });
+        s.expect_send()
+            .returning(|_, _, _| Ok(String::from("reply")));
+
+        let options = SerialOptions {
+            port: PortSelector::Path(String::from("/dev/ttyACM0")),
+            command_timeouts: vec![(String::from("010B"), Duration::from_millis(5000))],
+            ..Default::default()
+        };
+        let mut rs = RfidSerial::try_new(Box::new(s), &options).await.unwrap();
+        assert!(rs.send_recv("010B000304142601000000").await.is_ok());
+    }
+
+    /* the real serial port on a pty, the simulator answers on the other side */
+    fn pty_options(device: &FakeDevice) -> SerialOptions {
+        SerialOptions {
+            port: PortSelector::Path(String::from(device.path())),
+            settings: SerialPortSettings {
+                timeout: Duration::from_millis(200),
+                ..SerialOptions::default().settings
+            },
+            retry: RetryPolicy {
+                tries: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn simulated_device(sim: &Simulator) -> FakeDevice {
+        let sim = sim.clone();
+        FakeDevice::start(Box::new(move |cmd| sim.reply(cmd).ok()))
+    }
+
+    #[tokio::test]
+    async fn pty_reads_tag() {
+        let mut tag = VirtualTag::new(0xE004_0150_D2F3_A4B5);
+        tag.memory[8..12].copy_from_slice(&[0xCA, 0xFE, 0xBA, 0xBE]);
+        let device = simulated_device(&Simulator::new(vec![tag]));
+
+        let serial = RfidSerial::try_new(Box::new(SerialCrate::new()), &pty_options(&device))
+            .await
+            .unwrap();
+        let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+        assert_eq!(reader.read_uuid().await.unwrap(), "E0040150D2F3A4B5");
+        assert_eq!(
+            reader.read_single_block(2).await.unwrap(),
+            (String::from("E0040150D2F3A4B5"), String::from("CAFEBABE"))
+        );
+    }
+
+    #[tokio::test]
+    async fn pty_silent_device_times_out() {
+        let device = FakeDevice::start(Box::new(|_| None));
+        let mut rs = RfidSerial::try_new(Box::new(SerialCrate::new()), &pty_options(&device))
+            .await
+            .unwrap();
+        match rs.send_recv("0108000304FF0000").await {
+            Err(SerialError::NoReplyAfterMultipleTries(causes)) => {
+                assert_eq!(causes.len(), 2);
+                assert!(causes
+                    .iter()
+                    .all(|e| e.kind() == std::io::ErrorKind::TimedOut));
+            }
+            _ => panic!("{}", "Should have been NoReplyAfterMultipleTries"),
+        }
+    }
+
+    #[tokio::test]
+    async fn pty_unplugged_device_fails() {
+        let sim = Simulator::new(Vec::new());
+        let mut device = simulated_device(&sim);
+        let mut rs = RfidSerial::try_new(Box::new(SerialCrate::new()), &pty_options(&device))
+            .await
+            .unwrap();
+        assert!(rs.send_recv("0108000304FF0000").await.is_ok());
+
+        device.unplug();
+        assert!(rs.send_recv("0108000304FF0000").await.is_err());
+    }
 }
@@ -0,0 +1,453 @@
+use serde::{Deserialize, Serialize};
+use serialport::SerialPort;
+use serialport::{SerialPortInfo, SerialPortSettings};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::frame::FrameOptions;
+use super::low::{SerialCrate, SerialCrateTraits, VirtualPort};
+use super::sim::Simulator;
+
+/* one line of a capture file, written as JSON */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Record {
+    Opened {
+        at_ms: u64,
+        port: String,
+    },
+    Sent {
+        at_ms: u64,
+        data: String,
+    },
+    Received {
+        at_ms: u64,
+        data: String,
+    },
+    // the os error is kept, it tells an unplugged device from a slow one
+    Failed {
+        at_ms: u64,
+        kind: String,
+        os_error: Option<i32>,
+        message: String,
+    },
+}
+
+fn now_ms() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_millis() as u64,
+        Err(_) => 0,
+    }
+}
+
+const ERROR_KINDS: &[(ErrorKind, &str)] = &[
+    (ErrorKind::TimedOut, "timed_out"),
+    (ErrorKind::Interrupted, "interrupted"),
+    (ErrorKind::WouldBlock, "would_block"),
+    (ErrorKind::UnexpectedEof, "unexpected_eof"),
+    (ErrorKind::InvalidData, "invalid_data"),
+    (ErrorKind::BrokenPipe, "broken_pipe"),
+    (ErrorKind::NotFound, "not_found"),
+    (ErrorKind::NotConnected, "not_connected"),
+    (ErrorKind::PermissionDenied, "permission_denied"),
+];
+
+impl Record {
+    fn failed(e: &Error) -> Record {
+        let kind = ERROR_KINDS
+            .iter()
+            .find(|(kind, _)| *kind == e.kind())
+            .map_or("other", |(_, name)| name);
+        Record::Failed {
+            at_ms: now_ms(),
+            kind: String::from(kind),
+            os_error: e.raw_os_error(),
+            message: e.to_string(),
+        }
+    }
+
+    /* the reply as the port gave it, none when the record is not a reply */
+    fn reply(self) -> Option<Result<String, Error>> {
+        match self {
+            Record::Received { data, .. } => Some(Ok(data)),
+            Record::Failed {
+                os_error: Some(code),
+                ..
+            } => Some(Err(Error::from_raw_os_error(code))),
+            Record::Failed { kind, message, .. } => {
+                let kind = ERROR_KINDS
+                    .iter()
+                    .find(|(_, name)| *name == kind)
+                    .map_or(ErrorKind::Other, |(kind, _)| *kind);
+                Some(Err(Error::new(kind, message)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/* passes everything on to the port and appends it to the capture file as it happens */
+pub struct Recorder {
+    inner: Box<dyn SerialCrateTraits>,
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /* appends, a reconnect carries on in the same file */
+    pub fn create(inner: Box<dyn SerialCrateTraits>, path: &Path) -> Result<Recorder, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder {
+            inner,
+            file: Mutex::new(file),
+        })
+    }
+
+    /* a capture that cannot be written must not take the reader down with it */
+    fn record(&self, record: &Record) {
+        // records are plain strings and numbers, they always serialize
+        let mut line = serde_json::to_string(record).unwrap();
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            log::warn!("Unable to write serial capture: {}", e);
+        }
+    }
+
+    fn record_reply(&self, reply: &Result<String, Error>) {
+        match *reply {
+            Ok(ref data) => self.record(&Record::Received {
+                at_ms: now_ms(),
+                data: data.clone(),
+            }),
+            Err(ref e) => self.record(&Record::failed(e)),
+        }
+    }
+}
+
+impl SerialCrateTraits for Recorder {
+    fn get_ports(&self) -> Result<Vec<SerialPortInfo>, serialport::Error> {
+        self.inner.get_ports()
+    }
+
+    fn open(
+        &self,
+        path: &str,
+        settings: &SerialPortSettings,
+    ) -> Result<Box<dyn SerialPort>, serialport::Error> {
+        let port = self.inner.open(path, settings)?;
+        self.record(&Record::Opened {
+            at_ms: now_ms(),
+            port: String::from(path),
+        });
+        Ok(port)
+    }
+
+    fn send(
+        &self,
+        msg: &str,
+        device: &mut Box<dyn SerialPort>,
+        framing: &FrameOptions,
+    ) -> Result<String, Error> {
+        self.record(&Record::Sent {
+            at_ms: now_ms(),
+            data: String::from(msg),
+        });
+        let reply = self.inner.send(msg, device, framing);
+        self.record_reply(&reply);
+        reply
+    }
+
+    fn read(
+        &self,
+        device: &mut Box<dyn SerialPort>,
+        framing: &FrameOptions,
+    ) -> Result<String, Error> {
+        let reply = self.inner.read(device, framing);
+        self.record_reply(&reply);
+        reply
+    }
+}
+
+/* plays a capture back in place of the port. replies come in the recorded order without
+the recorded delays, and a command other than the recorded one fails with InvalidData */
+#[derive(Clone)]
+pub struct Replay {
+    // shared, so a reconnect carries on where the last connection stopped
+    records: Arc<Mutex<VecDeque<Record>>>,
+}
+
+fn capture_ended() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "Serial capture ended")
+}
+
+impl Replay {
+    pub fn new(records: Vec<Record>) -> Replay {
+        Replay {
+            records: Arc::new(Mutex::new(records.into_iter().collect())),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Replay, Error> {
+        let text = fs::read_to_string(path)?;
+        let mut records = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(line).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{} line {}: {}", path.display(), i + 1, e),
+                )
+            })?;
+            records.push(record);
+        }
+        Ok(Replay::new(records))
+    }
+
+    fn next_reply(&self) -> Result<String, Error> {
+        let mut records = self.records.lock().unwrap();
+        match records.front() {
+            Some(Record::Received { .. }) | Some(Record::Failed { .. }) => {}
+            _ => return Err(capture_ended()),
+        }
+        match records.pop_front().and_then(Record::reply) {
+            Some(reply) => reply,
+            None => Err(capture_ended()),
+        }
+    }
+}
+
+impl SerialCrateTraits for Replay {
+    /* replays are opened by path, there is nothing to scan for */
+    fn get_ports(&self) -> Result<Vec<SerialPortInfo>, serialport::Error> {
+        Ok(Vec::new())
+    }
+
+    /* whatever the last connection left unanswered was lost with the device */
+    fn open(
+        &self,
+        _path: &str,
+        settings: &SerialPortSettings,
+    ) -> Result<Box<dyn SerialPort>, serialport::Error> {
+        let mut records = self.records.lock().unwrap();
+        while let Some(record) = records.pop_front() {
+            if let Record::Opened { port, .. } = record {
+                log::info!("Replaying serial capture of {}", port);
+                return Ok(Box::new(VirtualPort::new(settings)));
+            }
+        }
+        Err(serialport::Error::new(
+            serialport::ErrorKind::NoDevice,
+            "Serial capture has no more connections",
+        ))
+    }
+
+    fn send(
+        &self,
+        msg: &str,
+        _device: &mut Box<dyn SerialPort>,
+        _framing: &FrameOptions,
+    ) -> Result<String, Error> {
+        {
+            let mut records = self.records.lock().unwrap();
+            match records.front() {
+                Some(Record::Sent { data, .. }) if data == msg => {
+                    records.pop_front();
+                }
+                Some(Record::Sent { data, .. }) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Serial capture sent {} next, not {}", data, msg),
+                    ));
+                }
+                _ => return Err(capture_ended()),
+            }
+        }
+        self.next_reply()
+    }
+
+    fn read(
+        &self,
+        _device: &mut Box<dyn SerialPort>,
+        _framing: &FrameOptions,
+    ) -> Result<String, Error> {
+        self.next_reply()
+    }
+}
+
+/* where the serial library used by every connection comes from */
+#[derive(Clone)]
+pub enum Source {
+    Port,
+    // another source, with every exchange appended to the file
+    Recorded(Box<Source>, PathBuf),
+    Replayed(Replay),
+    Simulated(Simulator),
+}
+
+impl Source {
+    pub fn lib(&self) -> Result<Box<dyn SerialCrateTraits>, Error> {
+        match *self {
+            Source::Port => Ok(Box::new(SerialCrate::new())),
+            Source::Recorded(ref source, ref path) => {
+                Ok(Box::new(Recorder::create(source.lib()?, path)?))
+            }
+            Source::Replayed(ref replay) => Ok(Box::new(replay.clone())),
+            Source::Simulated(ref simulator) => Ok(Box::new(simulator.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::{Reader, ReaderTraits};
+    use crate::scaffold::scaffold::temp_dir;
+    use crate::serial::err::SerialError;
+    use crate::serial::low::{MockSerialCrateTraits, MockSerialPort};
+    use crate::serial::options::SerialOptions;
+    use crate::serial::select::PortSelector;
+    use crate::serial::{RfidSerial, RfidSerialTraits};
+
+    // EIO, what a read from an unplugged usb serial device fails with
+    const EIO: i32 = 5;
+
+    fn by_path(path: &str) -> SerialOptions {
+        SerialOptions {
+            port: PortSelector::Path(String::from(path)),
+            ..Default::default()
+        }
+    }
+
+    fn events(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                String::from(value["event"].as_str().unwrap())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn records_exchanges() {
+        let mut s = MockSerialCrateTraits::new();
+        s.expect_open().returning(|_, settings| {
+            let mut port = MockSerialPort::new();
+            port.expect_timeout().return_const(settings.timeout);
+            Ok(Box::new(port))
+        });
+        let mut calls = 0;
+        s.expect_send().returning(move |_, _, _| {
+            calls += 1;
+            match calls {
+                1 => Ok(String::from("0108\r\nreply\r\n")),
+                _ => Err(Error::from_raw_os_error(EIO)),
+            }
+        });
+
+        let path = temp_dir().join("capture.jsonl");
+        let recorder = Recorder::create(Box::new(s), &path).unwrap();
+        let options = SerialOptions {
+            retry: crate::serial::options::RetryPolicy {
+                tries: 1,
+                ..Default::default()
+            },
+            ..by_path("/dev/ttyACM0")
+        };
+        let mut rs = RfidSerial::try_new(Box::new(recorder), &options)
+            .await
+            .unwrap();
+        assert!(rs.send_recv("0108").await.is_ok());
+        assert!(rs.send_recv("0109").await.is_err());
+        drop(rs);
+
+        assert_eq!(
+            events(&path),
+            vec!["opened", "sent", "received", "sent", "failed"]
+        );
+        let replay = Replay::load(&path).unwrap();
+        let records = replay.records.lock().unwrap();
+        match records[0] {
+            Record::Opened { ref port, .. } => assert_eq!(port, "/dev/ttyACM0"),
+            _ => panic!("{}", "Should have been the port"),
+        }
+        match records[2] {
+            Record::Received { ref data, .. } => assert_eq!(data, "0108\r\nreply\r\n"),
+            _ => panic!("{}", "Should have been the reply"),
+        }
+        match records[4] {
+            Record::Failed { os_error, .. } => assert_eq!(os_error, Some(EIO)),
+            _ => panic!("{}", "Should have been the failure"),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_through_reader() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/captures/read_uuid.jsonl");
+        let replay = Replay::load(&path).unwrap();
+        let serial = RfidSerial::try_new(Box::new(replay.clone()), &by_path("replay"))
+            .await
+            .unwrap();
+        let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+        assert_eq!(reader.read_uuid().await.unwrap(), "E0040150D2F3A4B5");
+
+        // the reader was unplugged after the first read
+        match reader.read_uuid().await {
+            Err(e) => assert!(e.is_device_lost()),
+            Ok(_) => panic!("{}", "Should have lost the device"),
+        }
+        drop(reader);
+
+        let serial = RfidSerial::try_new(Box::new(replay.clone()), &by_path("replay"))
+            .await
+            .unwrap();
+        let mut reader = Reader::try_new(Box::new(serial)).await.unwrap();
+        assert!(reader.read_uuid().await.is_err());
+        drop(reader);
+
+        let res = RfidSerial::try_new(Box::new(replay), &by_path("replay")).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn replay_catches_other_commands() {
+        let replay = Replay::new(vec![
+            Record::Opened {
+                at_ms: 0,
+                port: String::from("/dev/ttyACM0"),
+            },
+            Record::Sent {
+                at_ms: 1,
+                data: String::from("0108"),
+            },
+            Record::Received {
+                at_ms: 2,
+                data: String::from("reply"),
+            },
+        ]);
+        let mut rs = RfidSerial::try_new(Box::new(replay), &by_path("replay"))
+            .await
+            .unwrap();
+        match rs.send_recv("0109").await {
+            Err(SerialError::NoReplyAfterMultipleTries(causes)) => {
+                assert_eq!(causes[0].kind(), ErrorKind::InvalidData);
+                assert!(causes[0].to_string().contains("0108"));
+            }
+            _ => panic!("{}", "Should have been a mismatch"),
+        }
+        assert_eq!(rs.send_recv("0108").await.unwrap(), "reply");
+        match rs.send_recv("0108").await {
+            Err(SerialError::NoReplyAfterMultipleTries(causes)) => {
+                assert_eq!(causes[0].kind(), ErrorKind::UnexpectedEof);
+            }
+            _ => panic!("{}", "Should have run out of capture"),
+        }
+    }
+}
@@ -2,11 +2,16 @@ use std::fmt;
 use std::fmt::Debug;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum SerialError {
     NoSerialPortsFound,
     UsbSerialNotFound,
     MultipleSerialPorts,
-    NoReplyAfterMultipleTries,
+    NoMatchingPort(String),
+    MultipleMatchingPorts(String),
+    InvalidPortPattern(String),
+    // the error of every failed attempt, oldest first
+    NoReplyAfterMultipleTries(Vec<std::io::Error>),
     IoError(std::io::Error),
     SerialError(serialport::Error),
 }
@@ -23,11 +28,30 @@ impl fmt::Display for SerialError {
                 "No Usb serial port found. Please ensure device is connected"
             ),
             SerialError::MultipleSerialPorts => write!(f, "More than one USB serial device found"),
-            SerialError::NoReplyAfterMultipleTries => write!(
-                f,
-                "Serial device did not respond to read request \
+            SerialError::NoMatchingPort(ref sel) => {
+                write!(f, "No serial port matches {}", sel)
+            }
+            SerialError::MultipleMatchingPorts(ref sel) => {
+                write!(f, "More than one serial port matches {}", sel)
+            }
+            SerialError::InvalidPortPattern(ref e) => write!(f, "Invalid port pattern: {}", e),
+            SerialError::NoReplyAfterMultipleTries(ref causes) => {
+                write!(
+                    f,
+                    "Serial device did not respond to read request \
                             after multiple tries"
-            ),
+                )?;
+                for (i, e) in causes.iter().enumerate() {
+                    write!(
+                        f,
+                        "{} attempt {}: {}",
+                        if i == 0 { ":" } else { ";" },
+                        i + 1,
+                        e
+                    )?;
+                }
+                Ok(())
+            }
             SerialError::IoError(ref e) => std::fmt::Display::fmt(&e, f),
             SerialError::SerialError(ref e) => std::fmt::Display::fmt(&e, f),
         }
@@ -45,3 +69,31 @@ impl From<serialport::Error> for SerialError {
         SerialError::SerialError(err)
     }
 }
+
+/* errors the os reports once a usb serial device has been unplugged */
+const EIO: i32 = 5;
+const ENXIO: i32 = 6;
+const ENODEV: i32 = 19;
+
+fn io_device_lost(e: &std::io::Error) -> bool {
+    match e.kind() {
+        std::io::ErrorKind::BrokenPipe
+        | std::io::ErrorKind::NotFound
+        | std::io::ErrorKind::NotConnected => true,
+        _ => matches!(e.raw_os_error(), Some(EIO) | Some(ENXIO) | Some(ENODEV)),
+    }
+}
+
+impl SerialError {
+    /* whether the port is dead and has to be reopened */
+    pub fn is_device_lost(&self) -> bool {
+        match *self {
+            SerialError::IoError(ref e) => io_device_lost(e),
+            SerialError::SerialError(ref e) => e.kind == serialport::ErrorKind::NoDevice,
+            SerialError::NoReplyAfterMultipleTries(ref causes) => {
+                !causes.is_empty() && causes.iter().all(io_device_lost)
+            }
+            _ => false,
+        }
+    }
+}
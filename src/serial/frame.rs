@@ -0,0 +1,299 @@
+use serialport::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+pub const DEFAULT_IDLE_TIMEOUT_MS: u64 = 50;
+pub const DEFAULT_MAX_FRAME_LEN: usize = 4096;
+
+const READ_CHUNK: usize = 256;
+
+/* how the reply to one command is told apart from the next one */
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameOptions {
+    // a line the firmware prints once it is done, the reply ends there
+    pub end_marker: Option<String>,
+    // silence that ends the reply when no marker is seen, counted once the tags answered
+    pub idle_timeout: Duration,
+    pub max_len: usize,
+}
+
+impl Default for FrameOptions {
+    fn default() -> FrameOptions {
+        FrameOptions {
+            end_marker: None,
+            idle_timeout: Duration::from_millis(DEFAULT_IDLE_TIMEOUT_MS),
+            max_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+}
+
+impl FrameOptions {
+    fn is_complete(&self, frame: &[u8]) -> bool {
+        let marker = match self.end_marker {
+            Some(ref marker) => marker,
+            None => return false,
+        };
+
+        // only whole lines count, the marker might still be arriving
+        let text = String::from_utf8_lossy(frame);
+        let whole = match text.rfind('\n') {
+            Some(end) => &text[..end],
+            None => return false,
+        };
+        whole.lines().any(|line| line.trim() == marker)
+    }
+}
+
+/* drops whatever is left of an earlier reply, so it is not taken for the next one */
+pub fn clear_input(device: &mut Box<dyn SerialPort>) -> Result<(), Error> {
+    device.clear(ClearBuffer::Input)?;
+    Ok(())
+}
+
+/* collects every line of one reply. the firmware echoes the command before it talks to
+the tags, so the port timeout is the wait for the echo and for the first byte after it.
+from there the idle timeout applies until the reply is complete */
+pub fn read_frame(
+    device: &mut Box<dyn SerialPort>,
+    options: &FrameOptions,
+) -> Result<String, Error> {
+    let reply_timeout = device.timeout();
+    let res = read_until_idle(device, options);
+
+    // the next command expects the reply timeout again
+    device.set_timeout(reply_timeout)?;
+    res
+}
+
+/* whether anything arrived after the line echoing the command */
+fn past_echo(frame: &[u8]) -> bool {
+    match frame.iter().position(|b| *b == b'\n') {
+        Some(end) => end + 1 < frame.len(),
+        None => false,
+    }
+}
+
+fn read_until_idle(
+    device: &mut Box<dyn SerialPort>,
+    options: &FrameOptions,
+) -> Result<String, Error> {
+    let mut frame: Vec<u8> = Vec::new();
+    let mut buf = [0u8; READ_CHUNK];
+    let mut answered = false;
+
+    loop {
+        match device.read(&mut buf) {
+            Ok(0) if frame.is_empty() => {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "Serial port closed"));
+            }
+            Ok(0) => break,
+            Ok(n) => {
+                frame.extend_from_slice(&buf[..n]);
+                if !answered && past_echo(&frame) {
+                    answered = true;
+                    device.set_timeout(options.idle_timeout)?;
+                }
+                if frame.len() > options.max_len {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Reply longer than {} bytes", options.max_len),
+                    ));
+                }
+                if options.is_complete(&frame) {
+                    break;
+                }
+            }
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(ref e) if e.kind() == ErrorKind::TimedOut && !frame.is_empty() => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&frame).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::serial::low::MockSerialPort;
+    use mockall::predicate::eq;
+    use mockall::Sequence;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    const REPLY_TIMEOUT: Duration = Duration::from_millis(2000);
+
+    /* a port that hands out the chunks in order, then stays silent */
+    fn port(chunks: &[&str]) -> MockSerialPort {
+        let mut port = MockSerialPort::new();
+        let mut seq = Sequence::new();
+        for chunk in chunks {
+            let chunk = String::from(*chunk);
+            port.expect_read()
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(move |buf| {
+                    buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+                    Ok(chunk.len())
+                });
+        }
+        port.expect_read()
+            .returning(|_| Err(Error::new(ErrorKind::TimedOut, "timed out")));
+        port.expect_timeout().return_const(REPLY_TIMEOUT);
+        port.expect_set_timeout().returning(|_| Ok(()));
+        port
+    }
+
+    fn boxed(port: MockSerialPort) -> Box<dyn SerialPort> {
+        Box::new(port)
+    }
+
+    #[test]
+    fn collects_lines_until_idle() {
+        let mut device = boxed(port(&[
+            "010B000304142601000000\r\n",
+            "ISO 15693 Inventory request.\r\n[E0",
+            "04010012345678,4A]\r\n[]\r\n",
+        ]));
+        let frame = read_frame(&mut device, &FrameOptions::default()).unwrap();
+        assert_eq!(frame.lines().count(), 4);
+        assert!(frame.contains("[E004010012345678,4A]"));
+    }
+
+    /* a port that hands out each chunk once its delay after the previous one is over,
+    timing out like a real port when that takes longer than the timeout it was set to */
+    fn timed_port(chunks: Vec<(Duration, &'static str)>) -> MockSerialPort {
+        let timeout = Arc::new(Mutex::new(REPLY_TIMEOUT));
+        let pending = Arc::new(Mutex::new(VecDeque::from(chunks)));
+        let mut port = MockSerialPort::new();
+
+        let current = timeout.clone();
+        port.expect_timeout()
+            .returning(move || *current.lock().unwrap());
+        let current = timeout.clone();
+        port.expect_set_timeout().returning(move |t| {
+            *current.lock().unwrap() = t;
+            Ok(())
+        });
+        port.expect_read().returning(move |buf| {
+            let timeout = *timeout.lock().unwrap();
+            let mut pending = pending.lock().unwrap();
+            match pending.front_mut() {
+                Some((delay, chunk)) if *delay <= timeout => {
+                    std::thread::sleep(*delay);
+                    buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+                    let n = chunk.len();
+                    pending.pop_front();
+                    Ok(n)
+                }
+                Some((delay, _)) => {
+                    std::thread::sleep(timeout);
+                    *delay -= timeout;
+                    Err(Error::new(ErrorKind::TimedOut, "timed out"))
+                }
+                None => {
+                    std::thread::sleep(timeout);
+                    Err(Error::new(ErrorKind::TimedOut, "timed out"))
+                }
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn waits_for_tags_after_echo() {
+        let idle = Duration::from_millis(DEFAULT_IDLE_TIMEOUT_MS);
+        // the tags answer well after the echo, the firmware is busy with the rf meanwhile
+        let mut device = boxed(timed_port(vec![
+            (Duration::from_millis(0), "010B0003041426"),
+            (Duration::from_millis(10), "01000000\r\n"),
+            (idle * 3, "ISO 15693 Inventory request.\r\n"),
+            (Duration::from_millis(10), "[E004010012345678,4A]\r\n"),
+        ]));
+
+        let frame = read_frame(&mut device, &FrameOptions::default()).unwrap();
+        assert!(frame.contains("[E004010012345678,4A]"));
+        assert_eq!(device.timeout(), REPLY_TIMEOUT);
+    }
+
+    #[test]
+    fn stops_at_end_marker() {
+        let mut p = MockSerialPort::new();
+        p.expect_read().times(1).returning(|buf| {
+            let reply = b"Register write request.\r\n>\r\nstale";
+            buf[..reply.len()].copy_from_slice(reply);
+            Ok(reply.len())
+        });
+        p.expect_timeout().return_const(REPLY_TIMEOUT);
+        p.expect_set_timeout().returning(|_| Ok(()));
+        let mut device = boxed(p);
+
+        let options = FrameOptions {
+            end_marker: Some(String::from(">")),
+            ..Default::default()
+        };
+        let frame = read_frame(&mut device, &options).unwrap();
+        assert!(frame.starts_with("Register write request."));
+    }
+
+    #[test]
+    fn marker_must_be_whole_line() {
+        let options = FrameOptions {
+            end_marker: Some(String::from(">")),
+            ..Default::default()
+        };
+        assert!(!options.is_complete(b"[E004010012345678,4A]\r\n>"));
+        assert!(!options.is_complete(b"->\r\n"));
+        assert!(options.is_complete(b"[]\r\n>\r\n"));
+    }
+
+    #[test]
+    fn no_reply_times_out() {
+        let mut device = boxed(port(&[]));
+        let res = read_frame(&mut device, &FrameOptions::default());
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn too_long_reply() {
+        let mut device = boxed(port(&["0123456789", "0123456789"]));
+        let options = FrameOptions {
+            max_len: 16,
+            ..Default::default()
+        };
+        let res = read_frame(&mut device, &options);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn restores_reply_timeout() {
+        let mut p = MockSerialPort::new();
+        let mut seq = Sequence::new();
+        p.expect_timeout().return_const(REPLY_TIMEOUT);
+        p.expect_read()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|buf| {
+                buf[..13].copy_from_slice(b"echo\r\nreply\r\n");
+                Ok(13)
+            });
+        p.expect_set_timeout()
+            .with(eq(Duration::from_millis(DEFAULT_IDLE_TIMEOUT_MS)))
+            .times(1)
+            .returning(|_| Ok(()));
+        p.expect_read()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Err(Error::new(ErrorKind::TimedOut, "timed out")));
+        p.expect_set_timeout()
+            .with(eq(REPLY_TIMEOUT))
+            .times(1)
+            .returning(|_| Ok(()));
+        let mut device = boxed(p);
+
+        assert_eq!(
+            read_frame(&mut device, &FrameOptions::default()).unwrap(),
+            "echo\r\nreply\r\n"
+        );
+    }
+}
@@ -1,9 +1,14 @@
 #[cfg(test)]
-use mockall::automock;
+use mockall::{automock, mock};
+#[cfg(test)]
+use std::io::Read;
 
 use serialport::prelude::*;
 use serialport::{SerialPortInfo, SerialPortSettings};
-use std::io::{BufRead, BufReader, Write};
+use std::io::Write;
+use std::time::Duration;
+
+use super::frame::{clear_input, read_frame, FrameOptions};
 
 /* for mocking of open serial functions */
 #[cfg_attr(test, automock)]
@@ -14,8 +19,17 @@ pub trait SerialCrateTraits: Send + Sync {
         path: &str,
         settings: &SerialPortSettings,
     ) -> Result<Box<dyn SerialPort>, serialport::Error>;
-    fn send(&self, msg: &str, device: &mut Box<dyn SerialPort>) -> Result<String, std::io::Error>;
-    fn read(&self, device: &mut Box<dyn SerialPort>) -> Result<String, std::io::Error>;
+    fn send(
+        &self,
+        msg: &str,
+        device: &mut Box<dyn SerialPort>,
+        framing: &FrameOptions,
+    ) -> Result<String, std::io::Error>;
+    fn read(
+        &self,
+        device: &mut Box<dyn SerialPort>,
+        framing: &FrameOptions,
+    ) -> Result<String, std::io::Error>;
 }
 
 pub struct SerialCrate {}
@@ -39,16 +53,248 @@ impl SerialCrateTraits for SerialCrate {
         serialport::open_with_settings(path, settings)
     }
 
-    fn send(&self, msg: &str, device: &mut Box<dyn SerialPort>) -> Result<String, std::io::Error> {
+    fn send(
+        &self,
+        msg: &str,
+        device: &mut Box<dyn SerialPort>,
+        framing: &FrameOptions,
+    ) -> Result<String, std::io::Error> {
+        clear_input(device)?;
         let cmd = msg.as_bytes();
         device.write_all(cmd)?;
-        self.read(device)
+        self.read(device, framing)
+    }
+
+    fn read(
+        &self,
+        device: &mut Box<dyn SerialPort>,
+        framing: &FrameOptions,
+    ) -> Result<String, std::io::Error> {
+        read_frame(device, framing)
+    }
+}
+
+/* stands in for the port of a replay or the simulator, which answer in its place */
+#[derive(Clone)]
+pub struct VirtualPort {
+    settings: SerialPortSettings,
+}
+
+impl VirtualPort {
+    pub fn new(settings: &SerialPortSettings) -> VirtualPort {
+        VirtualPort {
+            settings: *settings,
+        }
+    }
+}
+
+impl std::io::Read for VirtualPort {
+    fn read(&mut self, _buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        Ok(0)
+    }
+}
+
+impl std::io::Write for VirtualPort {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+impl SerialPort for VirtualPort {
+    fn name(&self) -> Option<String> {
+        Some(String::from("virtual"))
+    }
+
+    fn settings(&self) -> SerialPortSettings {
+        self.settings
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.settings.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(self.settings.data_bits)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(self.settings.flow_control)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(self.settings.parity)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(self.settings.stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.settings.timeout
+    }
+
+    fn set_all(&mut self, settings: &SerialPortSettings) -> serialport::Result<()> {
+        self.settings = *settings;
+        Ok(())
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.settings.baud_rate = baud_rate;
+        Ok(())
     }
 
-    fn read(&self, device: &mut Box<dyn SerialPort>) -> Result<String, std::io::Error> {
-        let mut reader = BufReader::new(device);
-        let mut line = String::new();
-        reader.read_line(&mut line)?;
-        Ok(line)
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.settings.data_bits = data_bits;
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.settings.flow_control = flow_control;
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.settings.parity = parity;
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.settings.stop_bits = stop_bits;
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.settings.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, _buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+/* a port for tests that never touch the hardware */
+#[cfg(test)]
+mock! {
+    pub SerialPort {}
+    pub trait SerialPort {
+        fn name(&self) -> Option<String>;
+        fn settings(&self) -> SerialPortSettings;
+        fn baud_rate(&self) -> serialport::Result<u32>;
+        fn data_bits(&self) -> serialport::Result<DataBits>;
+        fn flow_control(&self) -> serialport::Result<FlowControl>;
+        fn parity(&self) -> serialport::Result<Parity>;
+        fn stop_bits(&self) -> serialport::Result<StopBits>;
+        fn timeout(&self) -> std::time::Duration;
+        fn set_all(&mut self, settings: &SerialPortSettings) -> serialport::Result<()>;
+        fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()>;
+        fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()>;
+        fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()>;
+        fn set_parity(&mut self, parity: Parity) -> serialport::Result<()>;
+        fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()>;
+        fn set_timeout(&mut self, timeout: std::time::Duration) -> serialport::Result<()>;
+        fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()>;
+        fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()>;
+        fn read_clear_to_send(&mut self) -> serialport::Result<bool>;
+        fn read_data_set_ready(&mut self) -> serialport::Result<bool>;
+        fn read_ring_indicator(&mut self) -> serialport::Result<bool>;
+        fn read_carrier_detect(&mut self) -> serialport::Result<bool>;
+        fn bytes_to_read(&self) -> serialport::Result<u32>;
+        fn bytes_to_write(&self) -> serialport::Result<u32>;
+        fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()>;
+        fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>>;
+    }
+
+    trait Write {
+        fn write(&mut self, buf: &[u8]) -> std::result::Result<usize, std::io::Error>;
+        fn flush(&mut self) -> std::result::Result<(), std::io::Error>;
+    }
+
+    trait Read {
+        fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, std::io::Error>;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mockall::predicate::eq;
+    use mockall::Sequence;
+    use std::time::Duration;
+
+    #[test]
+    fn send_clears_stale_input_first() {
+        let mut port = MockSerialPort::new();
+        let mut seq = Sequence::new();
+        port.expect_clear()
+            .with(eq(ClearBuffer::Input))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(()));
+        port.expect_write()
+            .withf(|buf| buf == b"0108")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|buf| Ok(buf.len()));
+        port.expect_read()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|buf| {
+                buf[..7].copy_from_slice(b"0108\r\n\n");
+                Ok(7)
+            });
+        port.expect_read().returning(|_| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "timed out",
+            ))
+        });
+        port.expect_timeout()
+            .return_const(Duration::from_millis(2000));
+        port.expect_set_timeout().returning(|_| Ok(()));
+
+        let mut device: Box<dyn SerialPort> = Box::new(port);
+        let reply = SerialCrate::new().send("0108", &mut device, &FrameOptions::default());
+        assert_eq!(reply.unwrap(), "0108\r\n\n");
     }
 }
@@ -0,0 +1,154 @@
+use serialport::prelude::*;
+use serialport::SerialPortSettings;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use super::frame::FrameOptions;
+use super::select::PortSelector;
+
+pub const DEFAULT_BAUD_RATE: u32 = 115200;
+pub const DEFAULT_TIMEOUT_MS: u64 = 2000;
+pub const DEFAULT_TRIES: u32 = 3;
+
+/* how often and how patiently a command is resent when the device does not answer */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub tries: u32,
+    // delay before the first resend, multiplied by the factor for every later one
+    pub backoff: Duration,
+    pub backoff_factor: u32,
+    pub max_backoff: Duration,
+    // None retries on any io error
+    pub retry_on: Option<Vec<ErrorKind>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            tries: DEFAULT_TRIES,
+            backoff: Duration::from_millis(0),
+            backoff_factor: 2,
+            max_backoff: Duration::from_millis(1000),
+            retry_on: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn is_retryable(&self, e: &std::io::Error) -> bool {
+        match self.retry_on {
+            Some(ref kinds) => kinds.contains(&e.kind()),
+            None => true,
+        }
+    }
+
+    /* delay to wait after the given failed attempt, counting from 1 */
+    pub fn backoff_after(&self, attempt: u32) -> Duration {
+        let factor = self
+            .backoff_factor
+            .saturating_pow(attempt.saturating_sub(1));
+        let delay = self.backoff.checked_mul(factor).unwrap_or(self.max_backoff);
+        std::cmp::min(delay, self.max_backoff)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SerialOptions {
+    pub port: PortSelector,
+    pub settings: SerialPortSettings,
+    // commands starting with the prefix use the timeout instead, longest prefix wins
+    pub command_timeouts: Vec<(String, Duration)>,
+    pub retry: RetryPolicy,
+    pub framing: FrameOptions,
+}
+
+impl Default for SerialOptions {
+    fn default() -> SerialOptions {
+        SerialOptions {
+            port: PortSelector::Auto,
+            settings: SerialPortSettings {
+                baud_rate: DEFAULT_BAUD_RATE,
+                data_bits: DataBits::Eight,
+                flow_control: FlowControl::None,
+                parity: Parity::None,
+                stop_bits: StopBits::One,
+                timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+            },
+            command_timeouts: Vec::new(),
+            retry: RetryPolicy::default(),
+            framing: FrameOptions::default(),
+        }
+    }
+}
+
+impl SerialOptions {
+    pub fn timeout_for(&self, cmd: &str) -> Duration {
+        self.command_timeouts
+            .iter()
+            .filter(|(prefix, _)| cmd.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, timeout)| *timeout)
+            .unwrap_or(self.settings.timeout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn retry_everything_by_default() {
+        let policy = RetryPolicy::default();
+        let e = std::io::Error::new(ErrorKind::InvalidData, "garbage");
+        assert!(policy.is_retryable(&e));
+    }
+
+    #[test]
+    fn retry_only_listed_kinds() {
+        let policy = RetryPolicy {
+            retry_on: Some(vec![ErrorKind::TimedOut]),
+            ..Default::default()
+        };
+        let timeout = std::io::Error::new(ErrorKind::TimedOut, "timed out");
+        let broken = std::io::Error::new(ErrorKind::BrokenPipe, "gone");
+        assert!(policy.is_retryable(&timeout));
+        assert!(!policy.is_retryable(&broken));
+    }
+
+    #[test]
+    fn exponential_backoff_capped() {
+        let policy = RetryPolicy {
+            backoff: Duration::from_millis(100),
+            backoff_factor: 2,
+            max_backoff: Duration::from_millis(300),
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff_after(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff_after(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff_after(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff_after(40), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn longest_prefix_timeout() {
+        let options = SerialOptions {
+            command_timeouts: vec![
+                (String::from("0108"), Duration::from_millis(100)),
+                (String::from("010B0003"), Duration::from_millis(500)),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            options.timeout_for("010B000304142601000000"),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            options.timeout_for("01080003042B0000"),
+            Duration::from_millis(100)
+        );
+        assert_eq!(
+            options.timeout_for("010A0003041001210000"),
+            Duration::from_millis(DEFAULT_TIMEOUT_MS)
+        );
+    }
+}
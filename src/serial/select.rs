@@ -0,0 +1,246 @@
+use glob::Pattern;
+use serialport::{SerialPortInfo, SerialPortType};
+use std::fmt;
+
+use super::err::SerialError;
+
+/* how the serial port of the evm is picked out of the available ports */
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortSelector {
+    // opened as is, without looking at the enumerated ports
+    Path(String),
+    Usb {
+        vid: Option<u16>,
+        pid: Option<u16>,
+        serial_number: Option<String>,
+    },
+    Glob(String),
+    // the only port with "USB" in its name
+    Auto,
+}
+
+impl fmt::Display for PortSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PortSelector::Path(ref p) => write!(f, "path {}", p),
+            PortSelector::Usb {
+                vid,
+                pid,
+                ref serial_number,
+            } => {
+                write!(f, "usb")?;
+                if let Some(vid) = vid {
+                    write!(f, " vid {:04x}", vid)?;
+                }
+                if let Some(pid) = pid {
+                    write!(f, " pid {:04x}", pid)?;
+                }
+                if let Some(sn) = serial_number {
+                    write!(f, " serial {}", sn)?;
+                }
+                Ok(())
+            }
+            PortSelector::Glob(ref g) => write!(f, "glob {}", g),
+            PortSelector::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl PortSelector {
+    /* whether the port list has to be enumerated before selecting */
+    pub fn needs_scan(&self) -> bool {
+        !matches!(self, PortSelector::Path(_))
+    }
+
+    /* returns the name of the single port matching the selector */
+    pub fn select(&self, ports: &[SerialPortInfo]) -> Result<String, SerialError> {
+        if let PortSelector::Path(ref path) = self {
+            return Ok(path.clone());
+        }
+
+        if ports.is_empty() {
+            return Err(SerialError::NoSerialPortsFound);
+        }
+
+        if let PortSelector::Auto = self {
+            let usb_ports: Vec<&SerialPortInfo> = ports
+                .iter()
+                .filter(|p| p.port_name.contains("USB"))
+                .collect();
+
+            return match usb_ports.len() {
+                0 => Err(SerialError::UsbSerialNotFound),
+                1 => Ok(usb_ports[0].port_name.clone()),
+                _ => Err(SerialError::MultipleSerialPorts),
+            };
+        }
+
+        let matching = match self {
+            PortSelector::Glob(ref g) => {
+                let pattern =
+                    Pattern::new(g).map_err(|e| SerialError::InvalidPortPattern(e.to_string()))?;
+                ports
+                    .iter()
+                    .filter(|p| pattern.matches(&p.port_name))
+                    .collect::<Vec<&SerialPortInfo>>()
+            }
+            _ => ports
+                .iter()
+                .filter(|p| self.matches_usb(&p.port_type))
+                .collect::<Vec<&SerialPortInfo>>(),
+        };
+
+        match matching.len() {
+            0 => Err(SerialError::NoMatchingPort(self.to_string())),
+            1 => Ok(matching[0].port_name.clone()),
+            _ => Err(SerialError::MultipleMatchingPorts(self.to_string())),
+        }
+    }
+
+    fn matches_usb(&self, port_type: &SerialPortType) -> bool {
+        let info = match port_type {
+            SerialPortType::UsbPort(info) => info,
+            _ => return false,
+        };
+
+        match self {
+            PortSelector::Usb {
+                vid,
+                pid,
+                serial_number,
+            } => {
+                // unset fields match anything
+                vid.iter().all(|v| *v == info.vid)
+                    && pid.iter().all(|p| *p == info.pid)
+                    && serial_number
+                        .iter()
+                        .all(|sn| info.serial_number.as_ref() == Some(sn))
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serialport::UsbPortInfo;
+
+    fn usb(name: &str, vid: u16, pid: u16, sn: &str) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: String::from(name),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid,
+                pid,
+                serial_number: Some(String::from(sn)),
+                manufacturer: None,
+                product: None,
+            }),
+        }
+    }
+
+    fn unknown(name: &str) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: String::from(name),
+            port_type: SerialPortType::Unknown,
+        }
+    }
+
+    #[test]
+    fn path_skips_scan() {
+        let sel = PortSelector::Path(String::from("/dev/pts/3"));
+        assert!(!sel.needs_scan());
+        assert_eq!(sel.select(&[]).unwrap(), "/dev/pts/3");
+    }
+
+    #[test]
+    fn auto_single_usb() {
+        let ports = vec![unknown("/dev/ttyS0"), unknown("/dev/ttyUSB0")];
+        assert_eq!(PortSelector::Auto.select(&ports).unwrap(), "/dev/ttyUSB0");
+    }
+
+    #[test]
+    fn auto_no_ports() {
+        let res = PortSelector::Auto.select(&[]);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            SerialError::NoSerialPortsFound.to_string()
+        );
+    }
+
+    #[test]
+    fn usb_by_vid_pid() {
+        let ports = vec![
+            usb("/dev/ttyUSB0", 0x0403, 0x6001, "FT1"),
+            usb("/dev/ttyACM0", 0x2047, 0x0300, "EVM1"),
+        ];
+        let sel = PortSelector::Usb {
+            vid: Some(0x2047),
+            pid: Some(0x0300),
+            serial_number: None,
+        };
+        assert_eq!(sel.select(&ports).unwrap(), "/dev/ttyACM0");
+    }
+
+    #[test]
+    fn usb_by_serial_number() {
+        let ports = vec![
+            usb("/dev/ttyACM0", 0x2047, 0x0300, "EVM1"),
+            usb("/dev/ttyACM1", 0x2047, 0x0300, "EVM2"),
+        ];
+        let sel = PortSelector::Usb {
+            vid: None,
+            pid: None,
+            serial_number: Some(String::from("EVM2")),
+        };
+        assert_eq!(sel.select(&ports).unwrap(), "/dev/ttyACM1");
+    }
+
+    #[test]
+    fn usb_multiple_matches() {
+        let ports = vec![
+            usb("/dev/ttyACM0", 0x2047, 0x0300, "EVM1"),
+            usb("/dev/ttyACM1", 0x2047, 0x0300, "EVM2"),
+        ];
+        let sel = PortSelector::Usb {
+            vid: Some(0x2047),
+            pid: None,
+            serial_number: None,
+        };
+        let res = sel.select(&ports);
+        assert!(res.unwrap_err().to_string().contains("More than one"));
+    }
+
+    #[test]
+    fn usb_ignores_non_usb_ports() {
+        let ports = vec![unknown("/dev/ttyUSB0")];
+        let sel = PortSelector::Usb {
+            vid: None,
+            pid: None,
+            serial_number: None,
+        };
+        let res = sel.select(&ports);
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("No serial port matches"));
+    }
+
+    #[test]
+    fn glob_match() {
+        let ports = vec![unknown("/dev/ttyS0"), unknown("/dev/ttyACM0")];
+        let sel = PortSelector::Glob(String::from("/dev/ttyACM*"));
+        assert_eq!(sel.select(&ports).unwrap(), "/dev/ttyACM0");
+    }
+
+    #[test]
+    fn glob_invalid() {
+        let ports = vec![unknown("/dev/ttyS0")];
+        let sel = PortSelector::Glob(String::from("/dev/tty["));
+        let res = sel.select(&ports);
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid port pattern"));
+    }
+}
@@ -0,0 +1,500 @@
+use serialport::{SerialPort, SerialPortInfo, SerialPortSettings, SerialPortType, UsbPortInfo};
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+
+pub mod tag;
+
+use super::frame::FrameOptions;
+use super::low::{SerialCrateTraits, VirtualPort};
+use tag::VirtualTag;
+
+pub const SIM_PORT: &str = "/dev/ttyUSB-sim";
+const TI_VID: u16 = 0x2047;
+const EVM_PID: u16 = 0x0300;
+
+/* every host command is framed as SOF, total length, 00 03 04, the command, its payload
+and two trailing zero bytes */
+const SOF: u8 = 0x01;
+const HEADER: &[u8] = &[0x00, 0x03, 0x04];
+const HEADER_LEN: usize = 6;
+const TRAILER_LEN: usize = 2;
+
+const REGISTER_WRITE: u8 = 0x10;
+const INVENTORY: u8 = 0x14;
+const REQUEST: u8 = 0x18;
+const ANTENNA: u8 = 0x2B;
+const AGC_TOGGLE: u8 = 0xF0;
+const AM_PM_TOGGLE: u8 = 0xF1;
+
+/* request flags, the low four bits are the same for inventories and other requests */
+const FLAG_INVENTORY: u8 = 0x04;
+const FLAG_SELECT: u8 = 0x10;
+const FLAG_ADDRESS: u8 = 0x20;
+const FLAG_OPTION: u8 = 0x40;
+const FLAG_AFI: u8 = 0x10;
+const FLAG_ONE_SLOT: u8 = 0x20;
+const INVENTORY_COMMAND: u8 = 0x01;
+const SLOTS: u64 = 16;
+
+const CHIP_STATUS_REG: usize = 0x00;
+const RF_ON: u8 = 0x20;
+// what the chip status register holds after power up, rf off
+const CHIP_STATUS_RESET: u8 = 0x01;
+const REGISTERS: usize = 0x20;
+
+// EIO, what the os reports for a usb serial device that went away
+const EIO: i32 = 5;
+
+/* what one inventory slot heard */
+enum Slot {
+    Empty,
+    // the uid as it went over the air and its rssi
+    Tag([u8; 8], u8),
+    Collision,
+}
+
+/* what came back to a request */
+enum Answer {
+    Silent,
+    Single(Vec<u8>),
+    Collision,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/* the board as seen from the host: the registers of the TRF7970A and the tags in its field */
+struct Evm {
+    tags: Vec<VirtualTag>,
+    registers: [u8; REGISTERS],
+    plugged: bool,
+}
+
+impl Evm {
+    fn rf_on(&self) -> bool {
+        self.registers[CHIP_STATUS_REG] & RF_ON != 0
+    }
+
+    /* the reply as the firmware prints it, the command echoed first */
+    fn reply(&mut self, cmd: &str) -> String {
+        let mut reply = format!("{}\r\n", cmd);
+        let frame = match unhex(cmd) {
+            Some(ref frame)
+                if frame.len() >= HEADER_LEN + TRAILER_LEN
+                    && frame[0] == SOF
+                    && frame[1] as usize == frame.len()
+                    && &frame[2..5] == HEADER =>
+            {
+                frame.clone()
+            }
+            // the firmware ignores what it cannot parse
+            _ => return reply,
+        };
+        let payload = &frame[HEADER_LEN..frame.len() - TRAILER_LEN];
+
+        match frame[5] {
+            REGISTER_WRITE => {
+                for pair in payload.chunks(2) {
+                    if let [reg, value] = *pair {
+                        if (reg as usize) < REGISTERS {
+                            self.registers[reg as usize] = value;
+                        }
+                    }
+                }
+                reply.push_str("Register write request.\r\n");
+            }
+            AGC_TOGGLE => reply.push_str("AGC Toggle\r\n"),
+            AM_PM_TOGGLE => reply.push_str("AM PM Toggle\r\n"),
+            ANTENNA => {}
+            INVENTORY => {
+                reply.push_str("\r\nISO 15693 Inventory request.\r\n");
+                for slot in self.inventory(payload) {
+                    let line = match slot {
+                        Slot::Empty => String::from("[]"),
+                        Slot::Tag(uid, rssi) => format!("[{},{:02X}]", hex(&uid), rssi),
+                        Slot::Collision => String::from("[z]"),
+                    };
+                    reply.push_str(&line);
+                    reply.push_str("\r\n");
+                }
+            }
+            REQUEST => {
+                reply.push_str("\r\nRequest mode.\r\n");
+                let line = match self.request(payload) {
+                    Answer::Silent => String::from("[]"),
+                    Answer::Single(res) => format!("[{}]", hex(&res)),
+                    Answer::Collision => String::from("[z]"),
+                };
+                reply.push_str(&line);
+                reply.push_str("\r\n");
+            }
+            _ => {}
+        }
+        reply
+    }
+
+    /* one answer per slot, a tag picks its slot by the four uid bits after the mask */
+    fn inventory(&self, payload: &[u8]) -> Vec<Slot> {
+        let (flags, rest) = match *payload {
+            [flags, INVENTORY_COMMAND, ref rest @ ..] => (flags, rest),
+            _ => return Vec::new(),
+        };
+        let (afi, rest) = match (flags & FLAG_AFI != 0, rest.split_first()) {
+            (true, Some((afi, rest))) => (Some(*afi), rest),
+            (true, None) => return Vec::new(),
+            (false, _) => (None, rest),
+        };
+        let (mask_bits, mask_bytes) = match rest.split_first() {
+            Some((bits, mask)) if *bits <= 64 && (*bits as usize).div_ceil(8) <= mask.len() => {
+                (*bits as u32, &mask[..(*bits as usize).div_ceil(8)])
+            }
+            _ => return Vec::new(),
+        };
+        let mask = mask_bytes
+            .iter()
+            .rev()
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        let slots = if flags & FLAG_ONE_SLOT != 0 { 1 } else { SLOTS };
+
+        let mut answers: Vec<Vec<&VirtualTag>> = (0..slots).map(|_| Vec::new()).collect();
+        if self.rf_on() {
+            for tag in &self.tags {
+                if tag.matches_afi(afi) && tag.matches_mask(mask_bits, mask) {
+                    let slot = tag.uid.checked_shr(mask_bits).unwrap_or(0) % slots;
+                    answers[slot as usize].push(tag);
+                }
+            }
+        }
+        answers
+            .into_iter()
+            .map(|tags| match tags.as_slice() {
+                [] => Slot::Empty,
+                [tag] => Slot::Tag(tag.uid_bytes(), tag.rssi),
+                _ => Slot::Collision,
+            })
+            .collect()
+    }
+
+    /* addressed requests go to the tag with the uid, the others to every tag in the field */
+    fn request(&mut self, payload: &[u8]) -> Answer {
+        let (flags, command, rest) = match *payload {
+            [flags, command, ref rest @ ..] => (flags, command, rest),
+            _ => return Answer::Silent,
+        };
+        if flags & FLAG_INVENTORY != 0 || !self.rf_on() {
+            return Answer::Silent;
+        }
+        let option = flags & FLAG_OPTION != 0;
+
+        if flags & FLAG_ADDRESS != 0 {
+            if rest.len() < 8 {
+                return Answer::Silent;
+            }
+            let uid = u64::from_le_bytes(to_array(&rest[..8]));
+            return match self.tags.iter_mut().find(|t| t.uid == uid) {
+                Some(tag) => Answer::Single(tag.respond(command, option, &rest[8..])),
+                None => Answer::Silent,
+            };
+        }
+
+        // a selected tag would be the only one to answer, the simulator selects none
+        if flags & FLAG_SELECT != 0 {
+            return Answer::Silent;
+        }
+        match self.tags.len() {
+            0 => Answer::Silent,
+            1 => Answer::Single(self.tags[0].respond(command, option, rest)),
+            _ => Answer::Collision,
+        }
+    }
+}
+
+fn to_array(bytes: &[u8]) -> [u8; 8] {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(bytes);
+    array
+}
+
+/* a TRF7970A EVM speaking the ascii host protocol, with virtual tags in its field. clones
+share the board, so a test can move tags or pull the plug while the server uses it */
+#[derive(Clone)]
+pub struct Simulator {
+    evm: Arc<Mutex<Evm>>,
+}
+
+impl Simulator {
+    pub fn new(tags: Vec<VirtualTag>) -> Simulator {
+        let mut registers = [0; REGISTERS];
+        registers[CHIP_STATUS_REG] = CHIP_STATUS_RESET;
+        Simulator {
+            evm: Arc::new(Mutex::new(Evm {
+                tags,
+                registers,
+                plugged: true,
+            })),
+        }
+    }
+
+    /* what the board prints for a command, also what a fake device on a pty sends back */
+    pub fn reply(&self, cmd: &str) -> Result<String, Error> {
+        let mut evm = self.evm.lock().unwrap();
+        if !evm.plugged {
+            return Err(Error::from_raw_os_error(EIO));
+        }
+        Ok(evm.reply(cmd))
+    }
+
+    #[cfg(test)]
+    pub fn add_tag(&self, tag: VirtualTag) {
+        self.evm.lock().unwrap().tags.push(tag);
+    }
+
+    #[cfg(test)]
+    pub fn remove_tag(&self, uid: u64) -> Option<VirtualTag> {
+        let mut evm = self.evm.lock().unwrap();
+        let idx = evm.tags.iter().position(|t| t.uid == uid)?;
+        Some(evm.tags.remove(idx))
+    }
+
+    #[cfg(test)]
+    pub fn tag(&self, uid: u64) -> Option<VirtualTag> {
+        let evm = self.evm.lock().unwrap();
+        evm.tags.iter().find(|t| t.uid == uid).cloned()
+    }
+
+    /* the port goes away until plugged in again, and the board forgets its registers */
+    #[cfg(test)]
+    pub fn unplug(&self) {
+        let mut evm = self.evm.lock().unwrap();
+        evm.plugged = false;
+        evm.registers = [0; REGISTERS];
+        evm.registers[CHIP_STATUS_REG] = CHIP_STATUS_RESET;
+    }
+
+    #[cfg(test)]
+    pub fn plug_in(&self) {
+        self.evm.lock().unwrap().plugged = true;
+    }
+}
+
+impl SerialCrateTraits for Simulator {
+    fn get_ports(&self) -> Result<Vec<SerialPortInfo>, serialport::Error> {
+        if !self.evm.lock().unwrap().plugged {
+            return Ok(Vec::new());
+        }
+        Ok(vec![SerialPortInfo {
+            port_name: String::from(SIM_PORT),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid: TI_VID,
+                pid: EVM_PID,
+                serial_number: Some(String::from("SIM")),
+                manufacturer: Some(String::from("Texas Instruments")),
+                product: Some(String::from("TRF7970A EVM (simulated)")),
+            }),
+        }])
+    }
+
+    /* any path opens the board, the simulator is the only device there is */
+    fn open(
+        &self,
+        _path: &str,
+        settings: &SerialPortSettings,
+    ) -> Result<Box<dyn SerialPort>, serialport::Error> {
+        if !self.evm.lock().unwrap().plugged {
+            return Err(serialport::Error::new(
+                serialport::ErrorKind::NoDevice,
+                "Simulated reader is unplugged",
+            ));
+        }
+        Ok(Box::new(VirtualPort::new(settings)))
+    }
+
+    fn send(
+        &self,
+        msg: &str,
+        _device: &mut Box<dyn SerialPort>,
+        _framing: &FrameOptions,
+    ) -> Result<String, Error> {
+        self.reply(msg)
+    }
+
+    /* the board only ever talks when asked */
+    fn read(
+        &self,
+        _device: &mut Box<dyn SerialPort>,
+        _framing: &FrameOptions,
+    ) -> Result<String, Error> {
+        if !self.evm.lock().unwrap().plugged {
+            return Err(Error::from_raw_os_error(EIO));
+        }
+        Err(Error::new(
+            ErrorKind::TimedOut,
+            "Simulated reader sent nothing",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::err::ReaderError;
+    use crate::reader::{Reader, ReaderTraits};
+    use crate::serial::options::SerialOptions;
+    use crate::serial::RfidSerial;
+    use tag::{LOCK_BLOCK, READ_SINGLE_BLOCK, WRITE_SINGLE_BLOCK};
+
+    const UID: u64 = 0xE004_0150_D2F3_A4B5;
+    // another slot of a 16 slot inventory
+    const OTHER_UID: u64 = 0xE004_0150_0000_C0A6;
+    // the same slot as UID
+    const SAME_SLOT_UID: u64 = 0xE004_0150_0000_1235;
+
+    fn frame(command: u8, payload: &[u8]) -> String {
+        let mut bytes = vec![SOF, 0];
+        bytes.extend_from_slice(HEADER);
+        bytes.push(command);
+        bytes.extend_from_slice(payload);
+        bytes.extend_from_slice(&[0, 0]);
+        bytes[1] = bytes.len() as u8;
+        hex(&bytes)
+    }
+
+    fn exchange(sim: &Simulator, cmd: &str) -> Result<String, Error> {
+        let mut port = sim.open(SIM_PORT, &SerialPortSettings::default()).unwrap();
+        sim.send(cmd, &mut port, &FrameOptions::default())
+    }
+
+    fn rf_on(sim: &Simulator) {
+        exchange(sim, &frame(REGISTER_WRITE, &[0x00, 0x31])).unwrap();
+    }
+
+    async fn reader(sim: &Simulator) -> Reader {
+        let serial = RfidSerial::try_new(Box::new(sim.clone()), &SerialOptions::default())
+            .await
+            .unwrap();
+        Reader::try_new(Box::new(serial)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reader_reads_tags() {
+        let mut tag = tag::VirtualTag::new(UID);
+        tag.memory[8..12].copy_from_slice(&[0xCA, 0xFE, 0xBA, 0xBE]);
+        let sim = Simulator::new(vec![tag]);
+        let mut reader = reader(&sim).await;
+
+        assert_eq!(reader.read_uuid().await.unwrap(), "E0040150D2F3A4B5");
+        assert_eq!(
+            reader.read_uuid_rssi().await.unwrap(),
+            (String::from("E0040150D2F3A4B5"), tag::DEFAULT_RSSI)
+        );
+        assert_eq!(
+            reader.read_single_block(2).await.unwrap(),
+            (String::from("E0040150D2F3A4B5"), String::from("CAFEBABE"))
+        );
+    }
+
+    #[tokio::test]
+    async fn collisions() {
+        let sim = Simulator::new(vec![tag::VirtualTag::new(UID)]);
+        let mut reader = reader(&sim).await;
+        sim.add_tag(tag::VirtualTag::new(OTHER_UID));
+        match reader.read_uuid().await {
+            Err(ReaderError::NoMatchingTargets(reply)) => assert!(reply.contains("[z]")),
+            _ => panic!("{}", "Should have collided"),
+        }
+
+        // 16 slots keep tags apart unless they share the four low bits of the uid
+        sim.add_tag(tag::VirtualTag::new(SAME_SLOT_UID));
+        let reply = exchange(&sim, &frame(INVENTORY, &[0x06, INVENTORY_COMMAND, 0])).unwrap();
+        let slots: Vec<&str> = reply.lines().filter(|l| l.starts_with('[')).collect();
+        assert_eq!(slots.len(), SLOTS as usize);
+        assert_eq!(slots[5], "[z]");
+        assert_eq!(slots[6], "[A6C00000500104E0,4E]");
+        assert_eq!(slots[0], "[]");
+
+        // a mask picks out the tags ending in it
+        let reply = exchange(&sim, &frame(INVENTORY, &[0x26, INVENTORY_COMMAND, 8, 0xB5])).unwrap();
+        assert!(reply.contains("[B5A4F3D2500104E0,4E]"));
+
+        sim.remove_tag(OTHER_UID);
+        sim.remove_tag(SAME_SLOT_UID);
+        assert_eq!(reader.read_uuid().await.unwrap(), "E0040150D2F3A4B5");
+    }
+
+    #[test]
+    fn rf_off_hears_nothing() {
+        let sim = Simulator::new(vec![tag::VirtualTag::new(UID)]);
+        let inventory = frame(INVENTORY, &[0x26, INVENTORY_COMMAND, 0]);
+        let reply = exchange(&sim, &inventory).unwrap();
+        assert_eq!(
+            reply,
+            format!(
+                "{}\r\n\r\nISO 15693 Inventory request.\r\n[]\r\n",
+                inventory
+            )
+        );
+
+        rf_on(&sim);
+        assert!(exchange(&sim, &inventory)
+            .unwrap()
+            .contains("[B5A4F3D2500104E0,4E]"));
+        // the firmware echoes what it does not understand
+        assert_eq!(exchange(&sim, "01XY").unwrap(), "01XY\r\n");
+    }
+
+    #[test]
+    fn writes_and_locks() {
+        let sim = Simulator::new(vec![tag::VirtualTag::new(UID)]);
+        rf_on(&sim);
+        let addressed = |command: u8, params: &[u8]| {
+            let mut payload = vec![0x22, command];
+            payload.extend_from_slice(&UID.to_le_bytes());
+            payload.extend_from_slice(params);
+            exchange(&sim, &frame(REQUEST, &payload)).unwrap()
+        };
+
+        assert!(addressed(WRITE_SINGLE_BLOCK, &[1, 0xDE, 0xAD, 0xBE, 0xEF]).ends_with("[00]\r\n"));
+        assert!(addressed(READ_SINGLE_BLOCK, &[1]).contains("[00DEADBEEF]"));
+        assert!(addressed(LOCK_BLOCK, &[1]).ends_with("[00]\r\n"));
+        assert!(addressed(WRITE_SINGLE_BLOCK, &[1, 0, 0, 0, 0]).ends_with("[0112]\r\n"));
+        assert_eq!(
+            sim.tag(UID).unwrap().block(1).unwrap(),
+            &[0xDE, 0xAD, 0xBE, 0xEF]
+        );
+
+        // nobody answers for a tag that is not there
+        let mut payload = vec![0x22, READ_SINGLE_BLOCK];
+        payload.extend_from_slice(&OTHER_UID.to_le_bytes());
+        payload.push(1);
+        assert!(exchange(&sim, &frame(REQUEST, &payload))
+            .unwrap()
+            .ends_with("Request mode.\r\n[]\r\n"));
+    }
+
+    #[tokio::test]
+    async fn unplugged() {
+        let sim = Simulator::new(vec![tag::VirtualTag::new(UID)]);
+        let mut lost = reader(&sim).await;
+        sim.unplug();
+        match lost.read_uuid().await {
+            Err(e) => assert!(e.is_device_lost()),
+            Ok(_) => panic!("{}", "Should have lost the device"),
+        }
+        assert!(sim.get_ports().unwrap().is_empty());
+
+        sim.plug_in();
+        let mut found = reader(&sim).await;
+        assert_eq!(found.read_uuid().await.unwrap(), "E0040150D2F3A4B5");
+    }
+}
@@ -0,0 +1,295 @@
+pub const DEFAULT_BLOCKS: usize = 28;
+pub const DEFAULT_BLOCK_SIZE: usize = 4;
+pub const DEFAULT_RSSI: u8 = 0x4E;
+
+/* iso15693 request codes the tags understand */
+pub const READ_SINGLE_BLOCK: u8 = 0x20;
+pub const WRITE_SINGLE_BLOCK: u8 = 0x21;
+pub const LOCK_BLOCK: u8 = 0x22;
+pub const READ_MULTIPLE_BLOCKS: u8 = 0x23;
+pub const WRITE_AFI: u8 = 0x27;
+pub const LOCK_AFI: u8 = 0x28;
+pub const WRITE_DSFID: u8 = 0x29;
+pub const LOCK_DSFID: u8 = 0x2A;
+pub const GET_SYSTEM_INFO: u8 = 0x2B;
+pub const GET_SECURITY_STATUS: u8 = 0x2C;
+
+/* iso15693 error codes, sent after the error flag */
+pub const ERR_NOT_SUPPORTED: u8 = 0x01;
+pub const ERR_NOT_RECOGNIZED: u8 = 0x02;
+pub const ERR_BLOCK_UNAVAILABLE: u8 = 0x10;
+pub const ERR_ALREADY_LOCKED: u8 = 0x11;
+pub const ERR_LOCKED: u8 = 0x12;
+
+const FLAG_ERROR: u8 = 0x01;
+// dsfid, afi, memory size and ic reference are all present
+const SYSTEM_INFO_FLAGS: u8 = 0x0F;
+const IC_REFERENCE: u8 = 0x01;
+
+fn error(code: u8) -> Vec<u8> {
+    vec![FLAG_ERROR, code]
+}
+
+/* an iso15693 tag in the field of the simulated reader */
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualTag {
+    // as printed on the tag, most significant byte (E0) first
+    pub uid: u64,
+    pub block_size: usize,
+    pub memory: Vec<u8>,
+    pub locked: Vec<bool>,
+    pub afi: u8,
+    pub dsfid: u8,
+    pub afi_locked: bool,
+    pub dsfid_locked: bool,
+    pub rssi: u8,
+}
+
+impl VirtualTag {
+    /* blank memory, nothing locked */
+    pub fn new(uid: u64) -> VirtualTag {
+        VirtualTag {
+            uid,
+            block_size: DEFAULT_BLOCK_SIZE,
+            memory: vec![0; DEFAULT_BLOCKS * DEFAULT_BLOCK_SIZE],
+            locked: vec![false; DEFAULT_BLOCKS],
+            afi: 0,
+            dsfid: 0,
+            afi_locked: false,
+            dsfid_locked: false,
+            rssi: DEFAULT_RSSI,
+        }
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.locked.len()
+    }
+
+    pub fn block(&self, idx: usize) -> Option<&[u8]> {
+        if idx >= self.blocks() {
+            return None;
+        }
+        Some(&self.memory[idx * self.block_size..(idx + 1) * self.block_size])
+    }
+
+    /* the uid in the order it goes over the air, least significant byte first */
+    pub fn uid_bytes(&self) -> [u8; 8] {
+        self.uid.to_le_bytes()
+    }
+
+    /* the mask is compared against the least significant bits of the uid */
+    pub fn matches_mask(&self, mask_bits: u32, mask: u64) -> bool {
+        match mask_bits {
+            0 => true,
+            64 => self.uid == mask,
+            n if n < 64 => (self.uid ^ mask) & ((1u64 << n) - 1) == 0,
+            _ => false,
+        }
+    }
+
+    /* a zero afi in the request selects tags of every family */
+    pub fn matches_afi(&self, afi: Option<u8>) -> bool {
+        match afi {
+            Some(0) | None => true,
+            Some(afi) => self.afi == afi,
+        }
+    }
+
+    /* the answer to a request, response flags first. with the option flag reads carry the
+    lock status of every block */
+    pub fn respond(&mut self, command: u8, option: bool, params: &[u8]) -> Vec<u8> {
+        match command {
+            READ_SINGLE_BLOCK => match params.first() {
+                Some(idx) => self.read_blocks(*idx as usize, 1, option),
+                None => error(ERR_NOT_RECOGNIZED),
+            },
+            READ_MULTIPLE_BLOCKS => match *params {
+                [first, count, ..] => self.read_blocks(first as usize, count as usize + 1, option),
+                _ => error(ERR_NOT_RECOGNIZED),
+            },
+            WRITE_SINGLE_BLOCK => match params.split_first() {
+                Some((idx, data)) => self.write_block(*idx as usize, data),
+                None => error(ERR_NOT_RECOGNIZED),
+            },
+            LOCK_BLOCK => match params.first() {
+                Some(idx) => self.lock_block(*idx as usize),
+                None => error(ERR_NOT_RECOGNIZED),
+            },
+            GET_SECURITY_STATUS => match *params {
+                [first, count, ..] => {
+                    let (first, count) = (first as usize, count as usize + 1);
+                    if first + count > self.blocks() {
+                        return error(ERR_BLOCK_UNAVAILABLE);
+                    }
+                    let mut res = vec![0];
+                    res.extend(self.locked[first..first + count].iter().map(|l| *l as u8));
+                    res
+                }
+                _ => error(ERR_NOT_RECOGNIZED),
+            },
+            WRITE_AFI => match (params.first(), self.afi_locked) {
+                (None, _) => error(ERR_NOT_RECOGNIZED),
+                (Some(_), true) => error(ERR_LOCKED),
+                (Some(afi), false) => {
+                    self.afi = *afi;
+                    vec![0]
+                }
+            },
+            WRITE_DSFID => match (params.first(), self.dsfid_locked) {
+                (None, _) => error(ERR_NOT_RECOGNIZED),
+                (Some(_), true) => error(ERR_LOCKED),
+                (Some(dsfid), false) => {
+                    self.dsfid = *dsfid;
+                    vec![0]
+                }
+            },
+            LOCK_AFI => lock(&mut self.afi_locked),
+            LOCK_DSFID => lock(&mut self.dsfid_locked),
+            GET_SYSTEM_INFO => {
+                let mut res = vec![0, SYSTEM_INFO_FLAGS];
+                res.extend_from_slice(&self.uid_bytes());
+                res.extend_from_slice(&[
+                    self.dsfid,
+                    self.afi,
+                    (self.blocks() - 1) as u8,
+                    (self.block_size - 1) as u8,
+                    IC_REFERENCE,
+                ]);
+                res
+            }
+            _ => error(ERR_NOT_SUPPORTED),
+        }
+    }
+
+    fn read_blocks(&self, first: usize, count: usize, option: bool) -> Vec<u8> {
+        if first + count > self.blocks() {
+            return error(ERR_BLOCK_UNAVAILABLE);
+        }
+        let mut res = vec![0];
+        for idx in first..first + count {
+            if option {
+                res.push(self.locked[idx] as u8);
+            }
+            // checked against the block count above
+            res.extend_from_slice(self.block(idx).unwrap());
+        }
+        res
+    }
+
+    fn write_block(&mut self, idx: usize, data: &[u8]) -> Vec<u8> {
+        if idx >= self.blocks() {
+            return error(ERR_BLOCK_UNAVAILABLE);
+        }
+        if data.len() != self.block_size {
+            return error(ERR_NOT_RECOGNIZED);
+        }
+        if self.locked[idx] {
+            return error(ERR_LOCKED);
+        }
+        self.memory[idx * self.block_size..(idx + 1) * self.block_size].copy_from_slice(data);
+        vec![0]
+    }
+
+    fn lock_block(&mut self, idx: usize) -> Vec<u8> {
+        if idx >= self.blocks() {
+            return error(ERR_BLOCK_UNAVAILABLE);
+        }
+        lock(&mut self.locked[idx])
+    }
+}
+
+fn lock(locked: &mut bool) -> Vec<u8> {
+    if *locked {
+        return error(ERR_ALREADY_LOCKED);
+    }
+    *locked = true;
+    vec![0]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const UID: u64 = 0xE004_0150_D2F3_A4B5;
+
+    #[test]
+    fn reads_and_writes_blocks() {
+        let mut tag = VirtualTag::new(UID);
+        assert_eq!(
+            tag.respond(WRITE_SINGLE_BLOCK, false, &[2, 0xCA, 0xFE, 0xBA, 0xBE]),
+            vec![0]
+        );
+        assert_eq!(
+            tag.respond(READ_SINGLE_BLOCK, false, &[2]),
+            vec![0, 0xCA, 0xFE, 0xBA, 0xBE]
+        );
+        assert_eq!(
+            tag.respond(READ_MULTIPLE_BLOCKS, true, &[1, 1]),
+            vec![0, 0, 0, 0, 0, 0, 0, 0xCA, 0xFE, 0xBA, 0xBE]
+        );
+        assert_eq!(
+            tag.respond(READ_SINGLE_BLOCK, false, &[DEFAULT_BLOCKS as u8]),
+            vec![FLAG_ERROR, ERR_BLOCK_UNAVAILABLE]
+        );
+        assert_eq!(
+            tag.respond(WRITE_SINGLE_BLOCK, false, &[2, 0xCA]),
+            vec![FLAG_ERROR, ERR_NOT_RECOGNIZED]
+        );
+    }
+
+    #[test]
+    fn locks() {
+        let mut tag = VirtualTag::new(UID);
+        assert_eq!(tag.respond(LOCK_BLOCK, false, &[3]), vec![0]);
+        assert_eq!(
+            tag.respond(LOCK_BLOCK, false, &[3]),
+            vec![FLAG_ERROR, ERR_ALREADY_LOCKED]
+        );
+        assert_eq!(
+            tag.respond(WRITE_SINGLE_BLOCK, false, &[3, 1, 2, 3, 4]),
+            vec![FLAG_ERROR, ERR_LOCKED]
+        );
+        assert_eq!(
+            tag.respond(GET_SECURITY_STATUS, false, &[2, 1]),
+            vec![0, 0, 1]
+        );
+
+        assert_eq!(tag.respond(WRITE_AFI, false, &[0x07]), vec![0]);
+        assert_eq!(tag.respond(LOCK_AFI, false, &[]), vec![0]);
+        assert_eq!(
+            tag.respond(WRITE_AFI, false, &[0x08]),
+            vec![FLAG_ERROR, ERR_LOCKED]
+        );
+        assert_eq!(tag.afi, 0x07);
+    }
+
+    #[test]
+    fn system_info() {
+        let mut tag = VirtualTag::new(UID);
+        tag.dsfid = 0x11;
+        tag.afi = 0x07;
+        let res = tag.respond(GET_SYSTEM_INFO, false, &[]);
+        assert_eq!(&res[..2], &[0, SYSTEM_INFO_FLAGS]);
+        assert_eq!(
+            &res[2..10],
+            &[0xB5, 0xA4, 0xF3, 0xD2, 0x50, 0x01, 0x04, 0xE0]
+        );
+        assert_eq!(&res[10..], &[0x11, 0x07, 27, 3, IC_REFERENCE]);
+        assert_eq!(
+            tag.respond(0xA0, false, &[]),
+            vec![FLAG_ERROR, ERR_NOT_SUPPORTED]
+        );
+    }
+
+    #[test]
+    fn selection() {
+        let tag = VirtualTag::new(UID);
+        assert!(tag.matches_mask(0, 0));
+        assert!(tag.matches_mask(4, 0x5));
+        assert!(!tag.matches_mask(4, 0x4));
+        assert!(tag.matches_mask(8, 0xB5));
+        assert!(tag.matches_mask(64, UID));
+        assert!(tag.matches_afi(Some(0)));
+        assert!(!tag.matches_afi(Some(0x07)));
+    }
+}
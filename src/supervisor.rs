@@ -0,0 +1,354 @@
+use futures::future::BoxFuture;
+use futures::lock::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, watch};
+
+use crate::lease::Leases;
+use crate::metrics;
+use crate::queue::err::QueueError;
+#[cfg(test)]
+use crate::queue::QueueStats;
+use crate::queue::{Priority, QueueOptions, ReaderQueue, ReaderTurn};
+use crate::reader::err::ReaderError;
+use crate::reader::ReaderTraits;
+
+/* empty while no reader is connected */
+pub type ReaderSlot = Arc<Mutex<Option<Box<dyn ReaderTraits>>>>;
+
+/* opens the serial port and initializes a fresh reader */
+pub type Connector =
+    Arc<dyn Fn() -> BoxFuture<'static, Result<Box<dyn ReaderTraits>, ReaderError>> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionStatus {
+    pub connected: bool,
+    // why the reader is disconnected, empty when connected
+    pub message: String,
+    pub since: SystemTime,
+}
+
+impl ConnectionStatus {
+    fn connected() -> ConnectionStatus {
+        ConnectionStatus {
+            connected: true,
+            message: String::new(),
+            since: SystemTime::now(),
+        }
+    }
+
+    fn disconnected(message: &str) -> ConnectionStatus {
+        ConnectionStatus {
+            connected: false,
+            message: String::from(message),
+            since: SystemTime::now(),
+        }
+    }
+}
+
+/* the service's side of the supervisor: the reader itself, its status and a way to
+report that the device went away */
+#[derive(Clone)]
+pub struct ReaderLink {
+    pub slot: ReaderSlot,
+    pub leases: Leases,
+    queue: ReaderQueue,
+    status: watch::Receiver<ConnectionStatus>,
+    lost: mpsc::UnboundedSender<String>,
+}
+
+impl ReaderLink {
+    /* a link without a supervisor, its status never changes */
+    #[cfg(test)]
+    pub fn fixed(reader: Option<Box<dyn ReaderTraits>>) -> ReaderLink {
+        let (link, _, _) = ReaderLink::new(reader, "No reader connected", QueueOptions::default());
+        link
+    }
+
+    fn new(
+        reader: Option<Box<dyn ReaderTraits>>,
+        reason: &str,
+        queue: QueueOptions,
+    ) -> (
+        ReaderLink,
+        watch::Sender<ConnectionStatus>,
+        mpsc::UnboundedReceiver<String>,
+    ) {
+        let status = match reader {
+            Some(_) => ConnectionStatus::connected(),
+            None => ConnectionStatus::disconnected(reason),
+        };
+        let (status_tx, status_rx) = watch::channel(status);
+        let (lost_tx, lost_rx) = mpsc::unbounded_channel();
+
+        let slot = Arc::new(Mutex::new(reader));
+        let queue = ReaderQueue::new(slot.clone(), queue);
+        let link = ReaderLink {
+            leases: Leases::new(queue.clone()),
+            queue,
+            slot,
+            status: status_rx,
+            lost: lost_tx,
+        };
+        (link, status_tx, lost_rx)
+    }
+
+    /* waits for a turn on the reader, the slot may still be empty once it comes */
+    pub async fn acquire(
+        &self,
+        priority: Priority,
+        deadline: Option<Duration>,
+    ) -> Result<ReaderTurn, QueueError> {
+        self.queue.acquire(priority, deadline).await
+    }
+
+    #[cfg(test)]
+    pub fn queue_stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+
+    /* yields the current status first, then every change */
+    pub fn status(&self) -> watch::Receiver<ConnectionStatus> {
+        self.status.clone()
+    }
+
+    /* drops the reader if the error means the device is gone, the caller holds the slot lock.
+    every failed reader call passes through here */
+    pub fn check_lost(&self, reader: &mut Option<Box<dyn ReaderTraits>>, e: &ReaderError) {
+        metrics::reader_error(e);
+        if !e.is_device_lost() || reader.is_none() {
+            return;
+        }
+
+        log::error!("Reader lost: {}", e);
+        *reader = None;
+        if self.lost.send(e.to_string()).is_err() {
+            log::warn!("{}", "No supervisor to reconnect the reader");
+        }
+    }
+}
+
+pub struct Supervisor {
+    link: ReaderLink,
+    status: watch::Sender<ConnectionStatus>,
+    lost: mpsc::UnboundedReceiver<String>,
+    connector: Connector,
+    interval: Duration,
+}
+
+impl Supervisor {
+    /* the initial connection result decides the starting state */
+    pub fn new(
+        initial: Result<Box<dyn ReaderTraits>, ReaderError>,
+        connector: Connector,
+        interval: Duration,
+        queue: QueueOptions,
+    ) -> Supervisor {
+        let (reader, reason) = match initial {
+            Ok(reader) => (Some(reader), String::new()),
+            Err(e) => (None, e.to_string()),
+        };
+        let (link, status, lost) = ReaderLink::new(reader, &reason, queue);
+
+        Supervisor {
+            link,
+            status,
+            lost,
+            connector,
+            interval,
+        }
+    }
+
+    pub fn link(&self) -> ReaderLink {
+        self.link.clone()
+    }
+
+    /* rescans every interval while disconnected, or straight away once a loss is reported.
+    a lease can hold the slot for long, so the slot is never waited on: the reader is
+    reconnected meanwhile and put in place on the first tick the slot is free */
+    pub async fn run(mut self) {
+        let mut pending: Option<Box<dyn ReaderTraits>> = None;
+        loop {
+            let mut reason = None;
+            tokio::select! {
+                lost = self.lost.recv() => reason = lost,
+                _ = tokio::time::delay_for(self.interval) => {}
+            }
+
+            if let Some(reason) = reason {
+                self.publish(ConnectionStatus::disconnected(&reason));
+            }
+            let busy = match self.link.slot.try_lock() {
+                Some(guard) if guard.is_some() => continue,
+                Some(_) => false,
+                None => true,
+            };
+            if busy && self.link.status.borrow().connected {
+                continue;
+            }
+
+            if pending.is_none() {
+                match (self.connector)().await {
+                    Ok(reader) => pending = Some(reader),
+                    Err(e) => {
+                        log::warn!("Reader still unavailable: {}", e);
+                        let changed = {
+                            let current = self.link.status.borrow();
+                            current.connected || current.message != e.to_string()
+                        };
+                        if changed {
+                            self.publish(ConnectionStatus::disconnected(&e.to_string()));
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            match self.link.slot.try_lock() {
+                Some(mut guard) => {
+                    log::info!("{}", "Reader connected");
+                    *guard = pending.take();
+                    self.publish(ConnectionStatus::connected());
+                }
+                None => log::debug!("{}", "Reader reconnected, waiting for the slot"),
+            }
+        }
+    }
+
+    fn publish(&self, status: ConnectionStatus) {
+        if self.status.broadcast(status).is_err() {
+            log::error!("{}", "Unable to publish reader status");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::MockReaderTraits;
+    use crate::serial::err::SerialError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn unplugged() -> ReaderError {
+        ReaderError::SerialError(SerialError::IoError(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            "Broken pipe",
+        )))
+    }
+
+    #[tokio::test]
+    async fn fixed_link_status() {
+        let link = ReaderLink::fixed(None);
+        let mut status = link.status();
+        let first = status.recv().await.unwrap();
+        assert!(!first.connected);
+        assert!(status.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn check_lost_ignores_other_errors() {
+        let link = ReaderLink::fixed(Some(Box::new(MockReaderTraits::new())));
+        let mut guard = link.slot.lock().await;
+        link.check_lost(
+            &mut guard,
+            &ReaderError::NoMatchingTargets(String::from("")),
+        );
+        assert!(guard.is_some());
+        link.check_lost(&mut guard, &unplugged());
+        assert!(guard.is_none());
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_loss() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let connector: Connector = Arc::new(move || {
+            // first rescan finds nothing, the second one finds the reader again
+            let res = if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(ReaderError::SerialError(SerialError::NoSerialPortsFound))
+            } else {
+                Ok(Box::new(MockReaderTraits::new()) as Box<dyn ReaderTraits>)
+            };
+            Box::pin(async move {
+                // rescanning takes a while, long enough to see the loss being published
+                tokio::time::delay_for(Duration::from_millis(50)).await;
+                res
+            })
+        });
+
+        let supervisor = Supervisor::new(
+            Ok(Box::new(MockReaderTraits::new())),
+            connector,
+            Duration::from_millis(10),
+            QueueOptions::default(),
+        );
+        let link = supervisor.link();
+        let mut status = link.status();
+        tokio::spawn(supervisor.run());
+
+        assert!(status.recv().await.unwrap().connected);
+
+        {
+            let mut guard = link.slot.lock().await;
+            link.check_lost(&mut guard, &unplugged());
+        }
+
+        let lost = status.recv().await.unwrap();
+        assert!(!lost.connected);
+        assert!(lost.message.contains("Broken pipe"));
+
+        let mut next = status.recv().await.unwrap();
+        while !next.connected {
+            next = status.recv().await.unwrap();
+        }
+        assert!(link.slot.lock().await.is_some());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn reconnects_during_lease() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let connector: Connector = Arc::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async { Ok(Box::new(MockReaderTraits::new()) as Box<dyn ReaderTraits>) })
+        });
+
+        let supervisor = Supervisor::new(
+            Ok(Box::new(MockReaderTraits::new())),
+            connector,
+            Duration::from_millis(10),
+            QueueOptions::default(),
+        );
+        let link = supervisor.link();
+        let mut status = link.status();
+        tokio::spawn(supervisor.run());
+        assert!(status.recv().await.unwrap().connected);
+
+        let lease = link
+            .leases
+            .acquire(Duration::from_secs(60), None)
+            .await
+            .unwrap();
+        {
+            let mut access = link.leases.access(&lease.token).await.unwrap();
+            link.check_lost(&mut access, &unplugged());
+        }
+        assert!(!status.recv().await.unwrap().connected);
+
+        // the port is opened again while the lease still holds the slot
+        for _ in 0..100 {
+            if attempts.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert!(link.slot.try_lock().is_none());
+
+        link.leases.release(&lease.token).unwrap();
+        assert!(status.recv().await.unwrap().connected);
+        assert!(link.slot.lock().await.is_some());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
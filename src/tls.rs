@@ -0,0 +1,377 @@
+use futures::future::poll_fn;
+use std::fs;
+use std::io::{self, BufReader, Cursor};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{
+    AllowAnyAuthenticatedClient, NoClientAuth, RootCertStore, ServerConfig, Session,
+};
+use tokio_rustls::{server, TlsAcceptor};
+use tonic::transport::server::Connected;
+use tonic::transport::Certificate;
+
+pub mod err;
+
+use err::TlsError;
+
+pub const DEFAULT_RELOAD_INTERVAL_MS: u64 = 10000;
+
+const ALPN_H2: &[u8] = b"h2";
+// connections accepted but not yet picked up by the server
+const ACCEPT_BUFFER: usize = 16;
+// a client that has not finished its handshake by then is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsOptions {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // clients must present a certificate signed by one of these when set
+    pub client_ca: Option<PathBuf>,
+    // how often the files are checked for changes
+    pub reload_interval: Duration,
+}
+
+impl TlsOptions {
+    fn paths(&self) -> Vec<&Path> {
+        let mut paths = vec![self.cert.as_path(), self.key.as_path()];
+        paths.extend(self.client_ca.as_deref());
+        paths
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, TlsError> {
+    fs::read(path).map_err(|e| TlsError::Io(path.to_path_buf(), e))
+}
+
+/* builds the server config from the files read, in the order of TlsOptions::paths */
+fn to_server_config(options: &TlsOptions, files: &[Vec<u8>]) -> Result<ServerConfig, TlsError> {
+    let certs = pemfile::certs(&mut Cursor::new(&files[0])).unwrap_or_default();
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(options.cert.clone()));
+    }
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut Cursor::new(&files[1])).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut Cursor::new(&files[1])).unwrap_or_default();
+    }
+    let key = match keys.into_iter().next() {
+        Some(key) => key,
+        None => return Err(TlsError::NoPrivateKey(options.key.clone())),
+    };
+
+    let mut config = match options.client_ca {
+        Some(ref path) => {
+            let mut roots = RootCertStore::empty();
+            match roots.add_pem_file(&mut BufReader::new(Cursor::new(&files[2]))) {
+                Ok((valid, _)) if valid > 0 => {}
+                _ => return Err(TlsError::InvalidCa(path.clone())),
+            }
+            ServerConfig::new(AllowAnyAuthenticatedClient::new(roots))
+        }
+        None => ServerConfig::new(NoClientAuth::new()),
+    };
+    config.set_single_cert(certs, key)?;
+    config.set_protocols(&[ALPN_H2.to_vec()]);
+    Ok(config)
+}
+
+/* a tls connection as the grpc server sees it, with the client certificates for
+authentication */
+pub struct TlsConnection(server::TlsStream<TcpStream>);
+
+impl Connected for TlsConnection {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.0.get_ref().0.peer_addr().ok()
+    }
+
+    fn peer_certs(&self) -> Option<Vec<Certificate>> {
+        let certs = self.0.get_ref().1.get_peer_certificates()?;
+        // tonic keeps the der encoding in a certificate made from pem
+        Some(
+            certs
+                .into_iter()
+                .map(|c| Certificate::from_pem(c.0))
+                .collect(),
+        )
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/* the certificates the server presents, reloaded when the files change. a reload only
+affects new connections, the ones already open and the reader carry on */
+#[derive(Clone)]
+pub struct Tls {
+    options: TlsOptions,
+    config: Arc<RwLock<Arc<ServerConfig>>>,
+    // contents of the files the current config was built from
+    loaded: Arc<Mutex<Vec<Vec<u8>>>>,
+    handshake_timeout: Duration,
+}
+
+impl Tls {
+    pub fn load(options: &TlsOptions) -> Result<Tls, TlsError> {
+        let files = options
+            .paths()
+            .into_iter()
+            .map(read)
+            .collect::<Result<Vec<Vec<u8>>, TlsError>>()?;
+        let config = to_server_config(options, &files)?;
+
+        Ok(Tls {
+            options: options.clone(),
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            loaded: Arc::new(Mutex::new(files)),
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+        })
+    }
+
+    /* true if the files changed and the new certificates are in use */
+    pub fn reload(&self) -> Result<bool, TlsError> {
+        let files = self
+            .options
+            .paths()
+            .into_iter()
+            .map(read)
+            .collect::<Result<Vec<Vec<u8>>, TlsError>>()?;
+        let mut loaded = self.loaded.lock().unwrap();
+        if *loaded == files {
+            return Ok(false);
+        }
+
+        // the old config stays in use when the new files are broken
+        let config = to_server_config(&self.options, &files)?;
+        *self.config.write().unwrap() = Arc::new(config);
+        *loaded = files;
+        Ok(true)
+    }
+
+    /* checks the files for changes until the server stops. a certificate being written
+    may be read half way, it is picked up on the next check */
+    pub async fn watch(self) {
+        loop {
+            tokio::time::delay_for(self.options.reload_interval).await;
+            match self.reload() {
+                Ok(true) => log::info!("{}", "TLS certificates reloaded"),
+                Ok(false) => {}
+                Err(e) => log::warn!("TLS certificates not reloaded: {}", e),
+            }
+        }
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.config.read().unwrap().clone())
+    }
+
+    /* accepts connections on the listener, handshakes run apart so a slow client does
+    not hold up the others. one that never finishes its handshake is dropped after the
+    handshake timeout */
+    pub fn incoming(self, mut listener: TcpListener) -> mpsc::Receiver<io::Result<TlsConnection>> {
+        let (mut tx, rx) = mpsc::channel(ACCEPT_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        log::warn!("Unable to accept connection: {}", e);
+                        continue;
+                    }
+                };
+                // the server is gone
+                if poll_fn(|cx| tx.poll_ready(cx)).await.is_err() {
+                    return;
+                }
+
+                let accept =
+                    tokio::time::timeout(self.handshake_timeout, self.acceptor().accept(stream));
+                let mut tx = tx.clone();
+                tokio::spawn(async move {
+                    match accept.await {
+                        Ok(Ok(stream)) => {
+                            // the server stopped in the meantime
+                            let _ = tx.send(Ok(TlsConnection(stream))).await;
+                        }
+                        Ok(Err(e)) => log::debug!("TLS handshake failed: {}", e),
+                        Err(_) => log::debug!("{}", "TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+        rx
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::include::read_info_server::ReadInfoServer;
+    use crate::include::Empty;
+    use crate::reader::MockReaderTraits;
+    use crate::rfid::Rfid;
+    use crate::scaffold::scaffold::*;
+    use futures_util::FutureExt;
+    use tokio::io::AsyncReadExt;
+    use tokio::sync::oneshot;
+    use tonic::transport::Server;
+    use tonic::Request;
+
+    async fn start_server(tls: Tls) -> (u16, oneshot::Sender<()>) {
+        let mut reader = MockReaderTraits::new();
+        reader
+            .expect_read_uuid()
+            .returning(|| Ok(String::from("CAFEDEADBEEFB0B0")));
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            Server::builder()
+                .add_service(ReadInfoServer::new(Rfid::new(Box::new(reader))))
+                .serve_with_incoming_shutdown(tls.incoming(listener), rx.map(drop))
+                .await
+                .unwrap();
+        });
+        (port, tx)
+    }
+
+    async fn read_uuid(port: u16, ca: &str, identity: Option<&(String, String)>) -> bool {
+        match tls_client(port, ca, identity).await {
+            Some(mut client) => client.read_uuid(Request::new(Empty {})).await.is_ok(),
+            None => false,
+        }
+    }
+
+    #[test]
+    fn load_errors() {
+        let dir = temp_dir();
+        let options = write_tls(&dir, &pki(), false);
+
+        let missing = TlsOptions {
+            cert: dir.join("missing.pem"),
+            ..options.clone()
+        };
+        match Tls::load(&missing) {
+            Err(TlsError::Io(path, _)) => assert_eq!(path, dir.join("missing.pem")),
+            _ => panic!("{}", "Should have been an io error"),
+        }
+
+        let no_key = TlsOptions {
+            key: options.cert.clone(),
+            ..options
+        };
+        match Tls::load(&no_key) {
+            Err(TlsError::NoPrivateKey(_)) => {}
+            _ => panic!("{}", "Should have been missing a key"),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_tls() {
+        let dir = temp_dir();
+        let ours = pki();
+        let tls = Tls::load(&write_tls(&dir, &ours, false)).unwrap();
+        let (port, stop) = start_server(tls).await;
+
+        assert!(read_uuid(port, &ours.ca, None).await);
+        assert!(!read_uuid(port, &pki().ca, None).await);
+        stop.send(()).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_requires_client_certificate() {
+        let dir = temp_dir();
+        let ours = pki();
+        let tls = Tls::load(&write_tls(&dir, &ours, true)).unwrap();
+        let (port, stop) = start_server(tls).await;
+
+        assert!(!read_uuid(port, &ours.ca, None).await);
+        // signed by a ca the server does not trust
+        assert!(!read_uuid(port, &ours.ca, Some(&pki().client)).await);
+        assert!(read_uuid(port, &ours.ca, Some(&ours.client)).await);
+        stop.send(()).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_swaps_certificates() {
+        let dir = temp_dir();
+        let old = pki();
+        let tls = Tls::load(&write_tls(&dir, &old, false)).unwrap();
+        assert!(!tls.reload().unwrap());
+        let (port, stop) = start_server(tls.clone()).await;
+        assert!(read_uuid(port, &old.ca, None).await);
+
+        let new = pki();
+        write_tls(&dir, &new, false);
+        // half written files keep the old certificates in use
+        fs::write(dir.join("server.key"), "").unwrap();
+        assert!(tls.reload().is_err());
+        assert!(read_uuid(port, &old.ca, None).await);
+
+        fs::write(dir.join("server.key"), &new.server.1).unwrap();
+        assert!(tls.reload().unwrap());
+        assert!(read_uuid(port, &new.ca, None).await);
+        assert!(!read_uuid(port, &old.ca, None).await);
+        stop.send(()).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn silent_client_dropped() {
+        let dir = temp_dir();
+        let mut tls = Tls::load(&write_tls(&dir, &pki(), false)).unwrap();
+        tls.handshake_timeout = Duration::from_millis(100);
+        let (port, stop) = start_server(tls).await;
+
+        // connects and never says hello
+        let mut silent = TcpStream::connect(SocketAddr::from(([127, 0, 0, 1], port)))
+            .await
+            .unwrap();
+        let mut buf = [0u8; 16];
+        let read = tokio::time::timeout(Duration::from_secs(5), silent.read(&mut buf)).await;
+        stop.send(()).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        // closed by the server well before the test gives up
+        assert_eq!(read.unwrap().unwrap(), 0);
+    }
+}
@@ -0,0 +1,40 @@
+use std::fmt;
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(PathBuf, std::io::Error),
+    NoCertificates(PathBuf),
+    NoPrivateKey(PathBuf),
+    InvalidCa(PathBuf),
+    Rustls(tokio_rustls::rustls::TLSError),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TlsError::Io(ref path, ref e) => {
+                write!(f, "Unable to read {}: {}", path.display(), e)
+            }
+            TlsError::NoCertificates(ref path) => {
+                write!(f, "No certificates found in {}", path.display())
+            }
+            TlsError::NoPrivateKey(ref path) => {
+                write!(f, "No PKCS8 or RSA private key found in {}", path.display())
+            }
+            TlsError::InvalidCa(ref path) => {
+                write!(f, "Invalid CA certificates in {}", path.display())
+            }
+            TlsError::Rustls(ref e) => write!(f, "Invalid TLS identity: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<tokio_rustls::rustls::TLSError> for TlsError {
+    fn from(err: tokio_rustls::rustls::TLSError) -> TlsError {
+        TlsError::Rustls(err)
+    }
+}
@@ -0,0 +1,114 @@
+/* the built binary against a scripted evm on a pty, talked to over grpc like any client */
+#[path = "../src/scaffold/pty.rs"]
+mod pty;
+
+mod rfid {
+    tonic::include_proto!("rfid");
+}
+
+use pty::FakeDevice;
+use rfid::read_info_client::ReadInfoClient;
+use rfid::{Empty, SingleBlockRequest};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+
+const BIN: &str = env!("CARGO_BIN_EXE_rfid-trf7970-grpc");
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/* what the evm prints for the commands of a uid and a single block read, a tag with
+CAFEBABE in block 2 is in the field */
+const SCRIPT: &[(&str, &str)] = &[
+    (
+        "010A0003041001210000",
+        "010A0003041001210000\r\nRegister write request.\r\n",
+    ),
+    (
+        "010C00030410003101020000",
+        "010C00030410003101020000\r\nRegister write request.\r\n",
+    ),
+    ("0109000304F0000000", "0109000304F0000000\r\nAGC Toggle\r\n"),
+    (
+        "0109000304F1FF0000",
+        "0109000304F1FF0000\r\nAM PM Toggle\r\n",
+    ),
+    ("01080003042B0000", "01080003042B0000\r\n"),
+    (
+        "010B000304142601000000",
+        "010B000304142601000000\r\n\r\nISO 15693 Inventory request.\r\n[B5A4F3D2500104E0,4E]\r\n",
+    ),
+    (
+        "0113000304182220B5A4F3D2500104E0020000",
+        "0113000304182220B5A4F3D2500104E0020000\r\n\r\nRequest mode.\r\n[00CAFEBABE]\r\n",
+    ),
+];
+
+fn scripted_device() -> FakeDevice {
+    let script: HashMap<&str, &str> = SCRIPT.iter().cloned().collect();
+    FakeDevice::start(Box::new(move |cmd| {
+        script.get(cmd).map(|reply| String::from(*reply))
+    }))
+}
+
+/* the server process, killed when the test is over however it ends */
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn start_server(device: &FakeDevice, port: u16) -> Server {
+    let child = Command::new(BIN)
+        .args(["--port", device.path()])
+        .args(["--listen", &format!("127.0.0.1:{}", port)])
+        .args(["--serial-timeout-ms", "500"])
+        .env_remove("RFID_CONFIG")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    Server(child)
+}
+
+/* the server only listens once the reader is up */
+async fn connect(port: u16) -> ReadInfoClient<Channel> {
+    let start = Instant::now();
+    loop {
+        match ReadInfoClient::connect(format!("http://127.0.0.1:{}", port)).await {
+            Ok(client) => return client,
+            Err(e) if start.elapsed() > CONNECT_TIMEOUT => panic!("Server did not start: {}", e),
+            Err(_) => tokio::time::delay_for(Duration::from_millis(100)).await,
+        }
+    }
+}
+
+#[tokio::test]
+async fn reads_tag_over_grpc() {
+    let device = scripted_device();
+    let port = free_port();
+    let _server = start_server(&device, port);
+    let mut client = connect(port).await;
+
+    let uuid = client.read_uuid(Empty {}).await.unwrap().into_inner();
+    assert_eq!(uuid.info, "E0040150D2F3A4B5");
+
+    let block = client
+        .read_single_block(SingleBlockRequest { block_index: 2 })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(block.info, "CAFEBABE");
+}